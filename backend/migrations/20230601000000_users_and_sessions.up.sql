@@ -0,0 +1,15 @@
+CREATE TABLE users
+(
+    id        SERIAL PRIMARY KEY,
+    username  VARCHAR UNIQUE NOT NULL,
+    email     VARCHAR UNIQUE NOT NULL,
+    password  VARCHAR        NOT NULL,
+    createdAt TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE sessions
+(
+    id         SERIAL PRIMARY KEY,
+    session_id VARCHAR NOT NULL UNIQUE,
+    user_id    INT     NOT NULL UNIQUE REFERENCES users (id)
+);
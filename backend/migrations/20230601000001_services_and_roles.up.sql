@@ -0,0 +1,23 @@
+CREATE TABLE services
+(
+    id      VARCHAR PRIMARY KEY,
+    name    TEXT NOT NULL,
+    api_key TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE roles
+(
+    id         SERIAL PRIMARY KEY,
+    name       VARCHAR NOT NULL,
+    service_id VARCHAR NOT NULL REFERENCES services (id),
+    api_key    TEXT    NOT NULL UNIQUE
+);
+
+CREATE INDEX roles_service_id_idx ON roles (service_id);
+
+CREATE TABLE role_permissions
+(
+    role_id    INT     NOT NULL REFERENCES roles (id),
+    permission VARCHAR NOT NULL,
+    PRIMARY KEY (role_id, permission)
+);
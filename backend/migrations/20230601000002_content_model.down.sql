@@ -0,0 +1,4 @@
+DROP TABLE content_items;
+DROP TABLE fields;
+DROP TABLE content_types;
+DROP FUNCTION set_updated_at();
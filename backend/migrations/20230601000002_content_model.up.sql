@@ -0,0 +1,60 @@
+-- updated_at を更新のたびに現在時刻へ書き換える
+CREATE FUNCTION set_updated_at() RETURNS TRIGGER AS
+$$
+BEGIN
+    NEW.updated_at = CURRENT_TIMESTAMP;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TABLE content_types
+(
+    id         SERIAL PRIMARY KEY,
+    name       VARCHAR                  NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    service_id VARCHAR REFERENCES services (id) ON DELETE CASCADE
+);
+
+CREATE INDEX content_types_service_id_idx ON content_types (service_id);
+
+CREATE TRIGGER content_types_set_updated_at
+    BEFORE UPDATE
+    ON content_types
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE fields
+(
+    id              SERIAL PRIMARY KEY,
+    content_type_id INT                      NOT NULL REFERENCES content_types (id) ON DELETE CASCADE,
+    display_id      VARCHAR                  NOT NULL,
+    field_type      VARCHAR                  NOT NULL,
+    required        BOOLEAN                  NOT NULL,
+    created_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (content_type_id, display_id)
+);
+
+CREATE TRIGGER fields_set_updated_at
+    BEFORE UPDATE
+    ON fields
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE content_items
+(
+    id              UUID PRIMARY KEY,
+    content_type_id INT                      NOT NULL REFERENCES content_types (id) ON DELETE CASCADE,
+    data            JSONB                    NOT NULL,
+    created_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX content_items_content_type_id_created_at_idx ON content_items (content_type_id, created_at);
+
+CREATE TRIGGER content_items_set_updated_at
+    BEFORE UPDATE
+    ON content_items
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
@@ -0,0 +1,14 @@
+DROP INDEX sessions_user_id_idx;
+
+-- ユーザーごとに最新のセッションだけを残す
+DELETE FROM sessions s
+WHERE EXISTS (SELECT 1 FROM sessions newer WHERE newer.user_id = s.user_id AND newer.id > s.id);
+
+ALTER TABLE sessions
+    DROP COLUMN created_at,
+    DROP COLUMN expires_at,
+    DROP COLUMN last_seen_at,
+    DROP COLUMN user_agent,
+    DROP COLUMN ip_address;
+
+ALTER TABLE sessions ADD CONSTRAINT sessions_user_id_key UNIQUE (user_id);
@@ -0,0 +1,11 @@
+-- 1ユーザーが複数の端末でログインできるようにする
+ALTER TABLE sessions DROP CONSTRAINT sessions_user_id_key;
+
+ALTER TABLE sessions
+    ADD COLUMN created_at   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN expires_at   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP + INTERVAL '7 days',
+    ADD COLUMN last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN user_agent   VARCHAR,
+    ADD COLUMN ip_address   VARCHAR;
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
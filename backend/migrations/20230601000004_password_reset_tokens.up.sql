@@ -0,0 +1,12 @@
+-- パスワードリセット用のトークン。平文はメールで送るだけで、DBにはSHA-256のハッシュを保存する
+CREATE TABLE password_reset_tokens
+(
+    id         SERIAL PRIMARY KEY,
+    user_id    INT                      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash VARCHAR                  NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at    TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
@@ -0,0 +1,7 @@
+-- 登録したメールアドレスの確認。確認するまではサービスを作成できない
+ALTER TABLE users
+    ADD COLUMN email_verified_at    TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN verification_sent_at TIMESTAMP WITH TIME ZONE;
+
+-- 既存のユーザーは確認済みとして扱う
+UPDATE users SET email_verified_at = COALESCE(createdAt, CURRENT_TIMESTAMP);
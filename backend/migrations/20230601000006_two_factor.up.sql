@@ -0,0 +1,20 @@
+-- ローカルアカウントのTOTPによる二段階認証
+ALTER TABLE users
+    -- 登録中(totp_enabled_atがNULL)または有効なTOTPの秘密鍵 (base32)
+    ADD COLUMN totp_secret     VARCHAR,
+    ADD COLUMN totp_enabled_at TIMESTAMP WITH TIME ZONE,
+    -- 最後に受け付けたコードの時間ステップ。同じコードを二度使えないようにする
+    ADD COLUMN totp_last_step  BIGINT,
+    ADD COLUMN totp_failures   INT NOT NULL DEFAULT 0,
+    ADD COLUMN totp_failed_at  TIMESTAMP WITH TIME ZONE;
+
+-- 認証アプリを使えないときのリカバリーコード。SHA-256のハッシュだけを保存する
+CREATE TABLE recovery_codes
+(
+    id        SERIAL PRIMARY KEY,
+    user_id   INT     NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash VARCHAR NOT NULL,
+    used_at   TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
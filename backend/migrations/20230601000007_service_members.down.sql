@@ -0,0 +1,2 @@
+DROP TABLE service_invitations;
+DROP TABLE service_members;
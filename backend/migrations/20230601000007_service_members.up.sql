@@ -0,0 +1,31 @@
+-- サービスのメンバー。利用者は認証プロバイダとその中のsubjectで識別する
+-- (ローカルアカウントならprovider = 'local'、subject = users.id)
+CREATE TABLE service_members
+(
+    id         SERIAL PRIMARY KEY,
+    service_id VARCHAR                  NOT NULL REFERENCES services (id) ON DELETE CASCADE,
+    provider   VARCHAR                  NOT NULL,
+    subject    VARCHAR                  NOT NULL,
+    email      VARCHAR,
+    role       VARCHAR                  NOT NULL CHECK (role IN ('owner', 'admin', 'editor', 'viewer')),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (service_id, provider, subject)
+);
+
+CREATE INDEX service_members_principal_idx ON service_members (provider, subject);
+
+-- メールで送る招待。トークンはSHA-256のハッシュだけを保存する
+CREATE TABLE service_invitations
+(
+    id          SERIAL PRIMARY KEY,
+    service_id  VARCHAR                  NOT NULL REFERENCES services (id) ON DELETE CASCADE,
+    email       VARCHAR                  NOT NULL,
+    role        VARCHAR                  NOT NULL CHECK (role IN ('owner', 'admin', 'editor', 'viewer')),
+    token_hash  VARCHAR                  NOT NULL UNIQUE,
+    invited_by  INT                      REFERENCES service_members (id) ON DELETE SET NULL,
+    created_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at  TIMESTAMP WITH TIME ZONE NOT NULL,
+    accepted_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX service_invitations_service_id_idx ON service_invitations (service_id);
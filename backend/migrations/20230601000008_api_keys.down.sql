@@ -0,0 +1,18 @@
+-- ハッシュから元のキーは戻せないので、各ロールとサービスに新しいキーを割り当てる
+ALTER TABLE roles
+    ADD COLUMN api_key TEXT;
+UPDATE roles
+SET api_key = md5(random()::text || clock_timestamp()::text);
+ALTER TABLE roles
+    ALTER COLUMN api_key SET NOT NULL,
+    ADD UNIQUE (api_key);
+
+ALTER TABLE services
+    ADD COLUMN api_key TEXT;
+UPDATE services
+SET api_key = md5(random()::text || clock_timestamp()::text);
+ALTER TABLE services
+    ALTER COLUMN api_key SET NOT NULL,
+    ADD UNIQUE (api_key);
+
+DROP TABLE api_keys;
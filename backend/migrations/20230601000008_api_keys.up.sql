@@ -0,0 +1,33 @@
+-- ロールごとに複数持てるAPIキー。SHA-256のハッシュと、一覧で見分けるための先頭部分だけを保存する
+CREATE TABLE api_keys
+(
+    id           SERIAL PRIMARY KEY,
+    role_id      INT     NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    name         VARCHAR,
+    prefix       VARCHAR NOT NULL,
+    key_hash     VARCHAR NOT NULL UNIQUE,
+    created_at   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at   TIMESTAMP WITH TIME ZONE,
+    revoked_at   TIMESTAMP WITH TIME ZONE,
+    last_used_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX api_keys_role_id_idx ON api_keys (role_id);
+
+-- 発行済みのキーはそのまま使えるようにハッシュにして移す
+INSERT INTO api_keys (role_id, prefix, key_hash)
+SELECT id, left(api_key, 8), encode(sha256(api_key::bytea), 'hex')
+FROM roles;
+
+-- ロールのキーだけを再発行したサービスでは、サービスのキーをAdminロールのキーとして残す
+INSERT INTO api_keys (role_id, prefix, key_hash)
+SELECT DISTINCT ON (s.id) r.id, left(s.api_key, 8), encode(sha256(s.api_key::bytea), 'hex')
+FROM services s
+         JOIN roles r ON r.service_id = s.id AND r.name = 'Admin'
+WHERE NOT EXISTS (SELECT 1 FROM roles k WHERE k.api_key = s.api_key)
+ORDER BY s.id, r.id;
+
+ALTER TABLE roles
+    DROP COLUMN api_key;
+ALTER TABLE services
+    DROP COLUMN api_key;
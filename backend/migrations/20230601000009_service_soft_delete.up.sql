@@ -0,0 +1,5 @@
+-- 削除されたサービスは猶予期間が過ぎるまで残し、その間は復元できる
+ALTER TABLE services
+    ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX services_deleted_at_idx ON services (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,6 @@
+-- ゴミ箱のアイテムは削除されたものなので、元に戻さずに消す
+DELETE FROM content_items WHERE deleted_at IS NOT NULL;
+
+ALTER TABLE content_items
+    DROP COLUMN deleted_by_key_id,
+    DROP COLUMN deleted_at;
@@ -0,0 +1,6 @@
+-- 削除したコンテンツアイテムは保持期間が過ぎるまでゴミ箱に残し、その間は復元できる
+ALTER TABLE content_items
+    ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN deleted_by_key_id INTEGER REFERENCES api_keys (id) ON DELETE SET NULL;
+
+CREATE INDEX content_items_deleted_at_idx ON content_items (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,35 @@
+-- 管理APIとコンテンツAPIでの変更の記録。サービスやユーザーが削除されても残すので外部キーは張らない
+CREATE TABLE audit_logs
+(
+    id            BIGSERIAL PRIMARY KEY,
+    created_at    TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    request_id    VARCHAR NOT NULL,
+    -- user、api_key、anonymousのいずれか
+    actor_type    VARCHAR NOT NULL,
+    -- userなら「provider:subject」、api_keyならキーのID
+    actor_id      VARCHAR,
+    actor_role_id INT,
+    service_id    VARCHAR,
+    action        VARCHAR NOT NULL,
+    target_type   VARCHAR NOT NULL,
+    target_id     VARCHAR,
+    before        JSONB,
+    after         JSONB
+);
+
+CREATE INDEX audit_logs_service_id_id_idx ON audit_logs (service_id, id);
+CREATE INDEX audit_logs_created_at_idx ON audit_logs (created_at);
+
+-- 追記専用にする。記録を書き換えたり消したりはできない
+CREATE FUNCTION audit_logs_append_only() RETURNS TRIGGER AS
+$$
+BEGIN
+    RAISE EXCEPTION 'audit_logs is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_logs_append_only
+    BEFORE UPDATE OR DELETE
+    ON audit_logs
+    FOR EACH ROW
+EXECUTE FUNCTION audit_logs_append_only();
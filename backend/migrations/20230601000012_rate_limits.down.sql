@@ -0,0 +1,4 @@
+DROP TABLE service_monthly_requests;
+
+ALTER TABLE services
+    DROP COLUMN plan;
@@ -0,0 +1,12 @@
+-- サービスのプラン。NULLならrate_limit.default_planを使う
+ALTER TABLE services
+    ADD COLUMN plan VARCHAR;
+
+-- サービスごと・月ごと(UTC)のリクエスト数。プランの月間上限と比べる
+CREATE TABLE service_monthly_requests
+(
+    service_id VARCHAR NOT NULL REFERENCES services (id) ON DELETE CASCADE,
+    month      DATE    NOT NULL,
+    requests   BIGINT  NOT NULL DEFAULT 0,
+    PRIMARY KEY (service_id, month)
+);
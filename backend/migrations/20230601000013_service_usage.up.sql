@@ -0,0 +1,16 @@
+-- コンテンツAPIの利用量をサービス・コンテンツタイプ・日(UTC)ごとに集計する。
+-- content_type_idはパスにコンテンツタイプを含まないリクエストではNULL。
+-- 削除したコンテンツタイプの集計も残すので、content_type_idには外部キーを張らない
+CREATE TABLE service_usage_daily
+(
+    service_id      VARCHAR NOT NULL REFERENCES services (id) ON DELETE CASCADE,
+    day             DATE    NOT NULL,
+    content_type_id INT,
+    requests        BIGINT  NOT NULL DEFAULT 0,
+    client_errors   BIGINT  NOT NULL DEFAULT 0,
+    server_errors   BIGINT  NOT NULL DEFAULT 0,
+    bytes_in        BIGINT  NOT NULL DEFAULT 0,
+    bytes_out       BIGINT  NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX service_usage_daily_key_idx ON service_usage_daily (service_id, day, (COALESCE(content_type_id, 0)));
@@ -0,0 +1,4 @@
+DROP INDEX roles_service_id_is_admin_idx;
+
+ALTER TABLE roles
+    DROP COLUMN is_admin;
@@ -0,0 +1,10 @@
+-- サービスの作成時に作ったAdminロール。名前ではなくこの列で見分け、変更・削除できないようにする
+ALTER TABLE roles
+    ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- 既存のサービスでは、最初に作られたAdminという名前のロールがそれにあたる
+UPDATE roles
+SET is_admin = TRUE
+WHERE id IN (SELECT min(id) FROM roles WHERE name = 'Admin' GROUP BY service_id);
+
+CREATE UNIQUE INDEX roles_service_id_is_admin_idx ON roles (service_id) WHERE is_admin;
@@ -0,0 +1,624 @@
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand};
+use futures::TryStreamExt;
+use headless_cms::libs::api_keys;
+use headless_cms::libs::audit::{self, AuditFilter};
+use headless_cms::libs::content_model::load_service_content_model;
+use headless_cms::libs::management;
+use headless_cms::libs::membership::{add_member, MemberIdentity, MemberRole};
+use headless_cms::libs::schema_version::{ensure_schema_not_ahead, run_migrations, MIGRATOR};
+use headless_cms::libs::service_archive::{export_service, import_service, parse_archive};
+use headless_cms::libs::typescript::render_declarations;
+use headless_cms::models::prelude::{Roles, Services};
+use headless_cms::router_comp::content_router::{FieldType, NewField};
+use headless_cms::router_comp::service_router::Permission;
+use sea_orm::{DatabaseConnection, EntityTrait, SqlxPostgresConnector};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(
+    name = "cms",
+    about = "Manage the headless CMS directly through its database"
+)]
+struct Cli {
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    database_url: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create, delete and rotate the API key of services
+    #[command(subcommand)]
+    Service(ServiceCommand),
+    /// Manage the roles of a service
+    #[command(subcommand)]
+    Role(RoleCommand),
+    /// Manage content types
+    #[command(subcommand)]
+    ContentType(ContentTypeCommand),
+    /// Manage fields of a content type
+    #[command(subcommand)]
+    Field(FieldCommand),
+    /// Manage who can administer a service
+    #[command(subcommand)]
+    Member(MemberCommand),
+    /// Manage local accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Write a service's content types, fields, roles and content items as NDJSON
+    Export {
+        service_id: String,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Import an archive written by `export` into an existing service
+    Import {
+        service_id: String,
+        file: PathBuf,
+        /// Validate the archive and roll back instead of writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write the audit log as NDJSON, oldest first
+    AuditLog {
+        /// Only entries of this service. Without it, account events are included too
+        #[arg(long)]
+        service_id: Option<String>,
+        /// Only entries with this action, e.g. content_item.update
+        #[arg(long)]
+        action: Option<String>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Run the pending database migrations
+    Migrate {
+        /// Revert applied migrations newer than this version instead
+        #[arg(long, value_name = "VERSION")]
+        revert_to: Option<i64>,
+    },
+    /// Generate a .d.ts file describing a service's content types
+    Typegen {
+        service_id: String,
+        #[command(flatten)]
+        output: Output,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServiceCommand {
+    /// Create a service with an Admin role holding every permission
+    Create {
+        name: String,
+        /// Make this local account the owner of the service
+        #[arg(long, value_name = "USERNAME")]
+        owner: Option<String>,
+    },
+    /// Mark a service as deleted; it can be restored until it is purged
+    Delete {
+        service_id: String,
+        /// Remove the service with its roles and content right away
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Restore a deleted service that has not been purged yet
+    Restore { service_id: String },
+    /// Remove services that were deleted more than the given number of days ago
+    Purge {
+        #[arg(long, default_value_t = 30)]
+        older_than_days: u32,
+    },
+    /// Revoke the API keys of the service's Admin role and issue a new one
+    RotateKey { service_id: String },
+    /// Choose the rate limit plan of a service from rate_limit.plans in the server config
+    SetPlan {
+        service_id: String,
+        /// Leave out to use rate_limit.default_plan
+        plan: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RoleCommand {
+    /// Create a role and print its API key
+    Create {
+        service_id: String,
+        name: String,
+        /// Allowed method, repeatable: Get, Post, Put, Patch or Delete
+        #[arg(short, long = "permission")]
+        permissions: Vec<Permission>,
+    },
+    /// List the roles of a service with their permissions
+    List { service_id: String },
+    /// Revoke every API key of a role and issue a new one
+    RotateKey { role_id: i32 },
+    /// List the API keys of a role
+    Keys { role_id: i32 },
+    /// Revoke one API key of a role
+    RevokeKey { role_id: i32, key_id: i32 },
+    /// Delete a role and its permissions
+    Delete { role_id: i32 },
+}
+
+#[derive(Subcommand)]
+enum ContentTypeCommand {
+    /// Create a content type and print its ID
+    Create { service_id: String, name: String },
+}
+
+#[derive(Subcommand)]
+enum FieldCommand {
+    /// Add a field to a content type
+    Create {
+        content_type_id: i32,
+        name: String,
+        /// Text, Number, Date or Boolean
+        field_type: FieldType,
+        #[arg(long)]
+        required: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemberCommand {
+    /// Add a member identified by a local username, or by --provider and --subject
+    Add {
+        service_id: String,
+        /// owner, admin, editor or viewer
+        role: MemberRole,
+        #[command(flatten)]
+        member: MemberArgs,
+    },
+    /// List the members of a service
+    List { service_id: String },
+    /// Remove a member from a service
+    Remove { service_id: String, member_id: i32 },
+}
+
+#[derive(Args)]
+struct MemberArgs {
+    /// Username of a local account
+    #[arg(
+        long,
+        conflicts_with = "provider",
+        required_unless_present = "provider"
+    )]
+    username: Option<String>,
+    /// Authentication provider of the member: oidc or static
+    #[arg(long, requires = "subject")]
+    provider: Option<String>,
+    /// The OIDC sub claim, or the name of the static token
+    #[arg(long)]
+    subject: Option<String>,
+    /// Email address to show in the member list
+    #[arg(long)]
+    email: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Turn off two-factor authentication and delete the recovery codes
+    ResetTwoFactor { username: String },
+}
+
+#[derive(Args)]
+struct Output {
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+impl Output {
+    fn writer(&self) -> anyhow::Result<Box<dyn Write>> {
+        Ok(match &self.output {
+            Some(path) => Box::new(std::fs::File::create(path)?),
+            None => Box::new(std::io::stdout()),
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&cli.database_url)
+        .await
+        .context("failed to connect to Postgres")?;
+    let db = SqlxPostgresConnector::from_sqlx_postgres_pool(pool.clone());
+
+    match cli.command {
+        Command::Service(command) => service(&db, command).await,
+        Command::Role(command) => role(&db, command).await,
+        Command::ContentType(ContentTypeCommand::Create { service_id, name }) => {
+            let content_type = management::create_content_type(&db, &service_id, name).await?;
+            println!("{}", content_type.id);
+            Ok(())
+        }
+        Command::Field(FieldCommand::Create {
+            content_type_id,
+            name,
+            field_type,
+            required,
+        }) => {
+            let new_field = NewField {
+                display_name: name,
+                field_type,
+                required,
+            };
+            let field = management::create_field(&db, content_type_id, new_field).await?;
+            println!("{}", field.id);
+            Ok(())
+        }
+        Command::Member(command) => member(&db, command).await,
+        Command::User(UserCommand::ResetTwoFactor { username }) => {
+            if !management::reset_two_factor(&db, &username).await? {
+                bail!("user {} not found", username);
+            }
+            Ok(())
+        }
+        Command::Export { service_id, output } => {
+            let Some(service) = Services::find_by_id(service_id.clone()).one(&db).await? else {
+                bail!("service {} not found", service_id);
+            };
+            let mut writer = output.writer()?;
+            let mut records = Box::pin(export_service(&db, &service).await?);
+            while let Some(record) = records.try_next().await? {
+                writer.write_all(record.to_line().as_bytes())?;
+            }
+            writer.flush()?;
+            Ok(())
+        }
+        Command::Import {
+            service_id,
+            file,
+            dry_run,
+        } => {
+            let archive = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let records = parse_archive(&archive).map_err(|e| anyhow::anyhow!("{}", e))?;
+            let report = import_service(&db, &service_id, records, dry_run)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(())
+        }
+        Command::AuditLog {
+            service_id,
+            action,
+            output,
+        } => {
+            let filter = AuditFilter {
+                action,
+                ..Default::default()
+            };
+            let mut writer = output.writer()?;
+            let mut entries = Box::pin(audit::export(db.clone(), service_id, filter));
+            while let Some(entry) = entries.try_next().await? {
+                serde_json::to_writer(&mut writer, &entry)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+            Ok(())
+        }
+        Command::Migrate { revert_to } => migrate(&pool, revert_to).await,
+        Command::Typegen { service_id, output } => {
+            let Some((service, content_model)) =
+                load_service_content_model(&db, &service_id).await?
+            else {
+                bail!("service {} not found", service_id);
+            };
+            let mut writer = output.writer()?;
+            writer.write_all(render_declarations(&service, &content_model).as_bytes())?;
+            writer.flush()?;
+            Ok(())
+        }
+    }
+}
+
+async fn service(db: &DatabaseConnection, command: ServiceCommand) -> anyhow::Result<()> {
+    match command {
+        ServiceCommand::Create { name, owner } => {
+            let owner = match owner {
+                Some(username) => match management::local_member_identity(db, &username).await? {
+                    Some(identity) => Some(identity),
+                    None => bail!("user {} not found", username),
+                },
+                None => None,
+            };
+            let (service, api_key) = management::create_service(db, name, owner.as_ref()).await?;
+            println!("service_id: {}", service.id);
+            println!("api_key: {}", api_key);
+        }
+        ServiceCommand::Delete { service_id, purge } => {
+            let found = if purge {
+                management::purge_service(db, &service_id).await?
+            } else {
+                management::delete_service(db, &service_id).await?.is_some()
+            };
+            if !found {
+                bail!("service {} not found", service_id);
+            }
+        }
+        ServiceCommand::Restore { service_id } => {
+            if !management::restore_service(db, &service_id).await? {
+                bail!("deleted service {} not found", service_id);
+            }
+        }
+        ServiceCommand::SetPlan { service_id, plan } => {
+            if !management::set_service_plan(db, &service_id, plan).await? {
+                bail!("service {} not found", service_id);
+            }
+        }
+        ServiceCommand::Purge { older_than_days } => {
+            let deleted_before =
+                chrono::Utc::now() - chrono::Duration::days(older_than_days.into());
+            for service_id in management::purge_deleted_services(db, deleted_before.into()).await? {
+                println!("{}", service_id);
+            }
+        }
+        ServiceCommand::RotateKey { service_id } => {
+            let Some(api_key) = management::rotate_service_key(db, &service_id).await? else {
+                bail!("service {} not found", service_id);
+            };
+            println!("{}", api_key);
+        }
+    }
+    Ok(())
+}
+
+async fn member(db: &DatabaseConnection, command: MemberCommand) -> anyhow::Result<()> {
+    match command {
+        MemberCommand::Add {
+            service_id,
+            role,
+            member,
+        } => {
+            let identity = match (member.username, member.provider, member.subject) {
+                (Some(username), _, _) => {
+                    let Some(mut identity) =
+                        management::local_member_identity(db, &username).await?
+                    else {
+                        bail!("user {} not found", username);
+                    };
+                    identity.email = member.email.or(identity.email);
+                    identity
+                }
+                (None, Some(provider), Some(subject)) => MemberIdentity {
+                    provider,
+                    subject,
+                    email: member.email,
+                },
+                _ => bail!("give either --username or --provider and --subject"),
+            };
+            if Services::find_by_id(service_id.clone())
+                .one(db)
+                .await?
+                .is_none()
+            {
+                bail!("service {} not found", service_id);
+            }
+            let member = add_member(db, &service_id, &identity, role).await?;
+            println!("{}", member.id);
+        }
+        MemberCommand::List { service_id } => {
+            for member in management::list_members(db, &service_id).await? {
+                println!(
+                    "{}\t{}\t{}:{}\t{}",
+                    member.id,
+                    member.role,
+                    member.provider,
+                    member.subject,
+                    member.email.unwrap_or_default()
+                );
+            }
+        }
+        MemberCommand::Remove {
+            service_id,
+            member_id,
+        } => {
+            if !management::remove_member(db, &service_id, member_id).await? {
+                bail!("member {} not found", member_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn role(db: &DatabaseConnection, command: RoleCommand) -> anyhow::Result<()> {
+    match command {
+        RoleCommand::Create {
+            service_id,
+            name,
+            permissions,
+        } => {
+            let permissions = permissions.into_iter().collect();
+            let (role, api_key) =
+                management::create_role(db, &service_id, &name, &permissions).await?;
+            println!("role_id: {}", role.id);
+            println!("api_key: {}", api_key);
+        }
+        RoleCommand::List { service_id } => {
+            for (role, permissions) in management::list_roles(db, &service_id).await? {
+                let mut permissions: Vec<String> =
+                    permissions.iter().map(|p| p.to_string()).collect();
+                permissions.sort();
+                println!("{}\t{}\t{}", role.id, role.name, permissions.join(","));
+            }
+        }
+        RoleCommand::RotateKey { role_id } => {
+            let Some(api_key) = management::rotate_role_key(db, role_id).await? else {
+                bail!("role {} not found", role_id);
+            };
+            println!("{}", api_key);
+        }
+        RoleCommand::Keys { role_id } => {
+            for key in api_keys::list_keys(db, role_id).await? {
+                let status = if key.revoked_at.is_some() {
+                    "revoked".to_string()
+                } else {
+                    match key.expires_at {
+                        Some(expires_at) if expires_at <= chrono::Utc::now() => {
+                            "expired".to_string()
+                        }
+                        Some(expires_at) => format!("expires {}", expires_at.to_rfc3339()),
+                        None => "active".to_string(),
+                    }
+                };
+                let last_used = key
+                    .last_used_at
+                    .map_or("never used".to_string(), |at| at.to_rfc3339());
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    key.id,
+                    key.prefix,
+                    key.name.unwrap_or_default(),
+                    status,
+                    last_used
+                );
+            }
+        }
+        RoleCommand::RevokeKey { role_id, key_id } => {
+            if !api_keys::revoke_key(db, role_id, key_id).await? {
+                bail!("active key {} of role {} not found", key_id, role_id);
+            }
+        }
+        RoleCommand::Delete { role_id } => {
+            if let Some(role) = Roles::find_by_id(role_id).one(db).await? {
+                if management::is_admin_role(&role) {
+                    bail!(
+                        "the {} role cannot be deleted; delete the service instead",
+                        role.name
+                    );
+                }
+            }
+            if !management::delete_role(db, role_id).await? {
+                bail!("role {} not found", role_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn migrate(pool: &PgPool, revert_to: Option<i64>) -> anyhow::Result<()> {
+    match revert_to {
+        Some(version) => {
+            ensure_schema_not_ahead(pool).await?;
+            MIGRATOR
+                .undo(pool, version)
+                .await
+                .context("failed to revert migrations")?;
+            println!("reverted migrations newer than {}", version);
+        }
+        None => {
+            run_migrations(pool)
+                .await
+                .context("failed to run migrations")?;
+            println!("migrations are up to date");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(
+            ["cms", "--database-url", "postgres://localhost/cms"]
+                .iter()
+                .chain(args),
+        )
+    }
+
+    #[test]
+    fn the_command_definition_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn role_permissions_are_repeatable() {
+        let cli = parse(&[
+            "role",
+            "create",
+            "svc",
+            "Editor",
+            "-p",
+            "Get",
+            "--permission",
+            "Patch",
+        ])
+        .unwrap();
+        let Command::Role(RoleCommand::Create { permissions, .. }) = cli.command else {
+            panic!("expected role create");
+        };
+        assert_eq!(permissions, vec![Permission::Get, Permission::Patch]);
+        assert!(parse(&["role", "create", "svc", "Editor", "-p", "Options"]).is_err());
+    }
+
+    #[test]
+    fn field_types_are_parsed() {
+        let cli = parse(&["field", "create", "3", "title", "Text", "--required"]).unwrap();
+        let Command::Field(FieldCommand::Create {
+            content_type_id,
+            field_type,
+            required,
+            ..
+        }) = cli.command
+        else {
+            panic!("expected field create");
+        };
+        assert_eq!(content_type_id, 3);
+        assert_eq!(field_type, FieldType::Text);
+        assert!(required);
+        assert!(parse(&["field", "create", "3", "title", "Color"]).is_err());
+    }
+
+    #[test]
+    fn members_need_a_username_or_a_provider_and_subject() {
+        assert!(parse(&["member", "add", "svc", "editor", "--username", "alice"]).is_ok());
+        assert!(parse(&[
+            "member",
+            "add",
+            "svc",
+            "viewer",
+            "--provider",
+            "oidc",
+            "--subject",
+            "auth0|1"
+        ])
+        .is_ok());
+        assert!(parse(&["member", "add", "svc", "editor"]).is_err());
+        assert!(parse(&["member", "add", "svc", "editor", "--provider", "oidc"]).is_err());
+        assert!(parse(&[
+            "member",
+            "add",
+            "svc",
+            "editor",
+            "--username",
+            "alice",
+            "--provider",
+            "oidc",
+            "--subject",
+            "x"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn purge_defaults_to_thirty_days() {
+        let cli = parse(&["service", "purge"]).unwrap();
+        let Command::Service(ServiceCommand::Purge { older_than_days }) = cli.command else {
+            panic!("expected service purge");
+        };
+        assert_eq!(older_than_days, 30);
+    }
+}
@@ -0,0 +1,41 @@
+pub mod config;
+pub mod libs;
+pub mod models;
+pub mod router;
+pub mod router_comp;
+
+use crate::config::Config;
+use crate::libs::auth::{AuthProvider, SessionKeys};
+use crate::libs::mailer::Mailer;
+use crate::libs::metrics::Metrics;
+use crate::libs::rate_limit::RateLimiter;
+use crate::libs::usage::UsageRecorder;
+use axum::extract::FromRef;
+use axum_extra::extract::cookie::Key;
+use sea_orm::DatabaseConnection;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub postgres: DatabaseConnection,
+    pub pgpool: PgPool,
+    pub keys: Arc<SessionKeys>,
+    pub config: Arc<Config>,
+    // authセクションが設定されていない場合はNone
+    pub auth: Option<Arc<dyn AuthProvider>>,
+    // mailセクションが設定されていない場合はNone
+    pub mailer: Option<Mailer>,
+    // APIキーごとの呼び出し回数の制限
+    pub rate_limiter: Arc<RateLimiter>,
+    // コンテンツAPIの利用量。一定間隔でまとめて書き込む
+    pub usage: Arc<UsageRecorder>,
+    // /metricsで公開する応答時間やバックグラウンドのタスクの状態
+    pub metrics: Arc<Metrics>,
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.keys.current.clone()
+    }
+}
@@ -0,0 +1,212 @@
+use crate::libs::generate_random_key::generate_key;
+use crate::libs::token_hash::hash_token;
+use crate::models::prelude::{ApiKeys, Roles, Services};
+use crate::models::{api_keys, roles, services};
+use sea_orm::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{Condition, JoinType, NotSet, QueryOrder, QuerySelect, TransactionTrait};
+use serde::Serialize;
+
+// 発行するキーの先頭。ログや設定ファイルに紛れたときに何のキーか分かるようにする
+const KEY_PREFIX: &str = "cms_";
+// 一覧でキーを見分けるために保存する先頭部分の長さ (KEY_PREFIXを含む)
+const VISIBLE_PREFIX_LEN: usize = 12;
+// 最終利用日時はこの間隔より細かくは書き込まない
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+// 一覧に返すキーの情報。ハッシュは返さない
+#[derive(Serialize, Debug)]
+pub struct ApiKeySummary {
+    pub id: i32,
+    pub role_id: i32,
+    pub name: Option<String>,
+    pub prefix: String,
+    pub created_at: DateTimeWithTimeZone,
+    pub expires_at: Option<DateTimeWithTimeZone>,
+    pub revoked_at: Option<DateTimeWithTimeZone>,
+    pub last_used_at: Option<DateTimeWithTimeZone>,
+}
+
+impl From<api_keys::Model> for ApiKeySummary {
+    fn from(key: api_keys::Model) -> Self {
+        ApiKeySummary {
+            id: key.id,
+            role_id: key.role_id,
+            name: key.name,
+            prefix: key.prefix,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            revoked_at: key.revoked_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+// 発行したキー。平文のキーを返すのはこのときだけ
+#[derive(Serialize, Debug)]
+pub struct IssuedApiKey {
+    #[serde(flatten)]
+    pub summary: ApiKeySummary,
+    pub key: String,
+}
+
+pub async fn issue_key<C: ConnectionTrait>(
+    db: &C,
+    role_id: i32,
+    name: Option<String>,
+    expires_at: Option<DateTimeWithTimeZone>,
+) -> Result<IssuedApiKey, DbErr> {
+    let key = format!("{}{}", KEY_PREFIX, generate_key(40));
+    let model = api_keys::ActiveModel {
+        id: NotSet,
+        role_id: Set(role_id),
+        name: Set(name),
+        prefix: Set(key[..VISIBLE_PREFIX_LEN].to_string()),
+        key_hash: Set(hash_token(&key)),
+        created_at: NotSet,
+        expires_at: Set(expires_at),
+        revoked_at: NotSet,
+        last_used_at: NotSet,
+    }
+    .insert(db)
+    .await?;
+    Ok(IssuedApiKey {
+        summary: model.into(),
+        key,
+    })
+}
+
+pub async fn list_keys<C: ConnectionTrait>(
+    db: &C,
+    role_id: i32,
+) -> Result<Vec<api_keys::Model>, DbErr> {
+    ApiKeys::find()
+        .filter(api_keys::Column::RoleId.eq(role_id))
+        .order_by_asc(api_keys::Column::Id)
+        .all(db)
+        .await
+}
+
+// 失効も期限切れもしていないキー
+fn active() -> Condition {
+    Condition::all()
+        .add(api_keys::Column::RevokedAt.is_null())
+        .add(
+            Condition::any()
+                .add(api_keys::Column::ExpiresAt.is_null())
+                .add(api_keys::Column::ExpiresAt.gt(chrono::Utc::now())),
+        )
+}
+
+pub async fn revoke_key<C: ConnectionTrait>(
+    db: &C,
+    role_id: i32,
+    key_id: i32,
+) -> Result<bool, DbErr> {
+    let result = ApiKeys::update_many()
+        .col_expr(api_keys::Column::RevokedAt, Expr::value(chrono::Utc::now()))
+        .filter(api_keys::Column::RoleId.eq(role_id))
+        .filter(api_keys::Column::Id.eq(key_id))
+        .filter(api_keys::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+// 同じ名前で新しいキーを発行し、古いキーはgraceの後に使えなくする。
+// graceの間は新旧どちらのキーでも呼び出せるので、クライアントを止めずに入れ替えられる
+pub async fn rotate_key(
+    db: &DatabaseConnection,
+    role_id: i32,
+    key_id: i32,
+    expires_at: Option<DateTimeWithTimeZone>,
+    grace: chrono::Duration,
+) -> Result<Option<IssuedApiKey>, DbErr> {
+    let txn = db.begin().await?;
+    let Some(old) = ApiKeys::find_by_id(key_id)
+        .filter(api_keys::Column::RoleId.eq(role_id))
+        .filter(active())
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let issued = issue_key(&txn, role_id, old.name.clone(), expires_at).await?;
+    if grace > chrono::Duration::zero() {
+        let retire_at: DateTimeWithTimeZone = (chrono::Utc::now() + grace).into();
+        ApiKeys::update_many()
+            .col_expr(api_keys::Column::ExpiresAt, Expr::value(retire_at))
+            .filter(api_keys::Column::Id.eq(old.id))
+            .filter(
+                Condition::any()
+                    .add(api_keys::Column::ExpiresAt.is_null())
+                    .add(api_keys::Column::ExpiresAt.gt(retire_at)),
+            )
+            .exec(&txn)
+            .await?;
+    } else {
+        revoke_key(&txn, role_id, old.id).await?;
+    }
+    txn.commit().await?;
+    Ok(Some(issued))
+}
+
+// ロールの有効なキーをすべて失効させ、新しいキーを一つ発行する。漏洩時の対応に使う
+pub async fn replace_role_keys(
+    db: &DatabaseConnection,
+    role_id: i32,
+) -> Result<Option<IssuedApiKey>, DbErr> {
+    let txn = db.begin().await?;
+    if Roles::find_by_id(role_id).one(&txn).await?.is_none() {
+        return Ok(None);
+    }
+    ApiKeys::update_many()
+        .col_expr(api_keys::Column::RevokedAt, Expr::value(chrono::Utc::now()))
+        .filter(api_keys::Column::RoleId.eq(role_id))
+        .filter(api_keys::Column::RevokedAt.is_null())
+        .exec(&txn)
+        .await?;
+    let issued = issue_key(&txn, role_id, None, None).await?;
+    txn.commit().await?;
+    Ok(Some(issued))
+}
+
+// サービスのロールに発行された有効なキーを探し、サービスとともに返す。
+// 削除済みのサービスのキーは使えない
+pub async fn authenticate(
+    db: &DatabaseConnection,
+    service_id: &str,
+    key: &str,
+) -> Result<Option<(api_keys::Model, services::Model)>, DbErr> {
+    let found = ApiKeys::find()
+        .inner_join(Roles)
+        .join(JoinType::InnerJoin, roles::Relation::Services.def())
+        .select_also(Services)
+        .filter(api_keys::Column::KeyHash.eq(hash_token(key)))
+        .filter(roles::Column::ServiceId.eq(service_id))
+        .filter(services::Column::DeletedAt.is_null())
+        .filter(active())
+        .one(db)
+        .await?;
+    Ok(found.and_then(|(key, service)| service.map(|service| (key, service))))
+}
+
+// 呼び出しのたびに書き込まないよう、前回の記録からLAST_USED_RESOLUTION_SECONDS以上経っていれば更新する
+pub async fn record_use(db: &DatabaseConnection, key_id: i32) -> Result<(), DbErr> {
+    let now = chrono::Utc::now();
+    let threshold = now - chrono::Duration::seconds(LAST_USED_RESOLUTION_SECONDS);
+    ApiKeys::update_many()
+        .col_expr(api_keys::Column::LastUsedAt, Expr::value(now))
+        .filter(api_keys::Column::Id.eq(key_id))
+        .filter(
+            Condition::any()
+                .add(api_keys::Column::LastUsedAt.is_null())
+                .add(api_keys::Column::LastUsedAt.lt(threshold)),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
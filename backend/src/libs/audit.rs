@@ -0,0 +1,283 @@
+use crate::libs::auth::Principal;
+use crate::models::prelude::AuditLogs;
+use crate::models::{api_keys, audit_logs};
+use crate::AppState;
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use futures::stream::{self, Stream, StreamExt};
+use sea_orm::prelude::*;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{Condition, NotSet, QueryOrder, QuerySelect};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use uuid::Uuid;
+
+// エクスポートで一度に読み込む件数
+const EXPORT_PAGE_SIZE: u64 = 500;
+
+// リクエストごとのID。assign_request_idがextensionsに入れ、X-Request-Idヘッダでも返す
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+// 変更を行った主体
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Actor {
+    User { provider: String, subject: String },
+    ApiKey { key_id: i32, role_id: i32 },
+    Anonymous,
+}
+
+impl Actor {
+    pub fn local_user(user_id: i32) -> Self {
+        Actor::User {
+            provider: "local".to_string(),
+            subject: user_id.to_string(),
+        }
+    }
+
+    // actor_type、actor_id、actor_role_idの各列の値
+    fn columns(&self) -> (&'static str, Option<String>, Option<i32>) {
+        match self {
+            Actor::User { provider, subject } => {
+                ("user", Some(format!("{}:{}", provider, subject)), None)
+            }
+            Actor::ApiKey { key_id, role_id } => {
+                ("api_key", Some(key_id.to_string()), Some(*role_id))
+            }
+            Actor::Anonymous => ("anonymous", None, None),
+        }
+    }
+}
+
+impl From<&Principal> for Actor {
+    fn from(principal: &Principal) -> Self {
+        Actor::User {
+            provider: principal.provider.to_string(),
+            subject: principal.subject.clone(),
+        }
+    }
+}
+
+impl From<&api_keys::Model> for Actor {
+    fn from(key: &api_keys::Model) -> Self {
+        Actor::ApiKey {
+            key_id: key.id,
+            role_id: key.role_id,
+        }
+    }
+}
+
+// 記録する変更。スナップショットにはパスワードやキーなどの秘密を入れない
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: &'static str,
+    target_type: &'static str,
+    target_id: Option<String>,
+    service_id: Option<String>,
+    before: Option<Json>,
+    after: Option<Json>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &'static str, target_type: &'static str) -> Self {
+        AuditEvent {
+            action,
+            target_type,
+            target_id: None,
+            service_id: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn target(mut self, target_id: impl ToString) -> Self {
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    pub fn service(mut self, service_id: &str) -> Self {
+        self.service_id = Some(service_id.to_string());
+        self
+    }
+
+    pub fn before(mut self, snapshot: &impl Serialize) -> Self {
+        self.before = serde_json::to_value(snapshot).ok();
+        self
+    }
+
+    pub fn after(mut self, snapshot: &impl Serialize) -> Self {
+        self.after = serde_json::to_value(snapshot).ok();
+        self
+    }
+}
+
+// ハンドラから変更を記録するためのextractor。
+// 利用者はvalidate_sessionかvalidate_api_keyがextensionsに入れたものを使う
+pub struct AuditLog {
+    db: DatabaseConnection,
+    request_id: String,
+    actor: Actor,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuditLog {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let request_id = match parts.extensions.get::<RequestId>() {
+            Some(request_id) => request_id.0.clone(),
+            None => Uuid::new_v4().to_string(),
+        };
+        let actor = if let Some(principal) = parts.extensions.get::<Principal>() {
+            Actor::from(principal)
+        } else if let Some(key) = parts.extensions.get::<api_keys::Model>() {
+            Actor::from(key)
+        } else {
+            Actor::Anonymous
+        };
+        Ok(AuditLog {
+            db: state.postgres.clone(),
+            request_id,
+            actor,
+        })
+    }
+}
+
+impl AuditLog {
+    // ログインや登録のように、処理の中で利用者が分かる場合に使う
+    pub fn set_actor(&mut self, actor: Actor) {
+        self.actor = actor;
+    }
+
+    // 記録に失敗しても変更はすでに済んでいるので、ログに残して処理を続ける
+    pub async fn record(&self, event: AuditEvent) {
+        let (actor_type, actor_id, actor_role_id) = self.actor.columns();
+        let result = audit_logs::ActiveModel {
+            id: NotSet,
+            created_at: NotSet,
+            request_id: Set(self.request_id.clone()),
+            actor_type: Set(actor_type.to_string()),
+            actor_id: Set(actor_id),
+            actor_role_id: Set(actor_role_id),
+            service_id: Set(event.service_id),
+            action: Set(event.action.to_string()),
+            target_type: Set(event.target_type.to_string()),
+            target_id: Set(event.target_id),
+            before: Set(event.before),
+            after: Set(event.after),
+        }
+        .insert(&self.db)
+        .await;
+        if let Err(e) = result {
+            log::error!(
+                "failed to record {} of request {}: {}",
+                event.action,
+                self.request_id,
+                e
+            );
+        }
+    }
+}
+
+// 一覧とエクスポートの絞り込み条件。指定したものはすべて一致する必要がある
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub actor_type: Option<String>,
+    pub actor_id: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub request_id: Option<String>,
+    pub since: Option<DateTimeWithTimeZone>,
+    pub until: Option<DateTimeWithTimeZone>,
+}
+
+impl AuditFilter {
+    fn condition(&self, service_id: Option<&str>) -> Condition {
+        let mut condition = Condition::all();
+        if let Some(service_id) = service_id {
+            condition = condition.add(audit_logs::Column::ServiceId.eq(service_id));
+        }
+        for (column, value) in [
+            (audit_logs::Column::ActorType, &self.actor_type),
+            (audit_logs::Column::ActorId, &self.actor_id),
+            (audit_logs::Column::Action, &self.action),
+            (audit_logs::Column::TargetType, &self.target_type),
+            (audit_logs::Column::TargetId, &self.target_id),
+            (audit_logs::Column::RequestId, &self.request_id),
+        ] {
+            if let Some(value) = value {
+                condition = condition.add(column.eq(value.as_str()));
+            }
+        }
+        if let Some(since) = self.since {
+            condition = condition.add(audit_logs::Column::CreatedAt.gte(since));
+        }
+        if let Some(until) = self.until {
+            condition = condition.add(audit_logs::Column::CreatedAt.lt(until));
+        }
+        condition
+    }
+}
+
+// 新しい順にlimit件返す。cursorを指定すると、そのIDより前の記録を返す
+pub async fn list(
+    db: &DatabaseConnection,
+    service_id: Option<&str>,
+    filter: &AuditFilter,
+    cursor: Option<i64>,
+    limit: u64,
+) -> Result<Vec<audit_logs::Model>, DbErr> {
+    let mut query = AuditLogs::find().filter(filter.condition(service_id));
+    if let Some(cursor) = cursor {
+        query = query.filter(audit_logs::Column::Id.lt(cursor));
+    }
+    query
+        .order_by_desc(audit_logs::Column::Id)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+// 古い順にすべての記録を、ページごとに読み込みながら返す
+pub fn export(
+    db: DatabaseConnection,
+    service_id: Option<String>,
+    filter: AuditFilter,
+) -> impl Stream<Item = Result<audit_logs::Model, DbErr>> {
+    stream::unfold(Some(0i64), move |after| {
+        let db = db.clone();
+        let condition = filter.condition(service_id.as_deref());
+        async move {
+            let after = after?;
+            let page = AuditLogs::find()
+                .filter(condition)
+                .filter(audit_logs::Column::Id.gt(after))
+                .order_by_asc(audit_logs::Column::Id)
+                .limit(EXPORT_PAGE_SIZE)
+                .all(&db)
+                .await;
+            match page {
+                Ok(page) if page.is_empty() => None,
+                Ok(page) => {
+                    // IDの続きから読むので、読み込み中に記録が増えても同じ記録を二度返さない
+                    let next = if (page.len() as u64) < EXPORT_PAGE_SIZE {
+                        None
+                    } else {
+                        page.last().map(|entry| entry.id)
+                    };
+                    Some((
+                        stream::iter(page.into_iter().map(Ok).collect::<Vec<_>>()),
+                        next,
+                    ))
+                }
+                Err(e) => Some((stream::iter(vec![Err(e)]), None)),
+            }
+        }
+    })
+    .flatten()
+}
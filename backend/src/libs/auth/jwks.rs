@@ -0,0 +1,257 @@
+use super::AuthError;
+use anyhow::{bail, Context};
+use axum::http::{header::CACHE_CONTROL, HeaderMap};
+use hyper::body::to_bytes;
+use hyper::Client;
+use hyper_tls::HttpsConnector;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime};
+
+// Cache-Controlがない場合のJWKSの有効期間
+const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+const MIN_TTL: Duration = Duration::from_secs(60);
+const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// 取得に失敗したときにタイマーで再試行するまでの間隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+struct DiscoveryDocument {
+    issuer: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug)]
+pub enum RefreshError {
+    // discoveryドキュメントのissuerが設定と違う。再試行しても直らない
+    IssuerMismatch { expected: String, actual: String },
+    Unreachable(anyhow::Error),
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::IssuerMismatch { expected, actual } => write!(
+                f,
+                "the discovery document is for issuer {}, expected {}",
+                actual, expected
+            ),
+            RefreshError::Unreachable(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for RefreshError {}
+
+struct CacheState {
+    jwks_uri: Option<String>,
+    keys: Option<JwkSet>,
+    expires_at: Instant,
+    last_attempt: Option<Instant>,
+    last_success: Option<SystemTime>,
+    failures: u64,
+}
+
+// /metrics用の取得状況
+#[derive(Clone, Copy, Debug)]
+pub struct JwksStatus {
+    pub keys_loaded: bool,
+    pub last_success: Option<SystemTime>,
+    pub failures: u64,
+}
+
+// issuerのJWKSを保持し、期限切れ・未知のkid・タイマーで取り直す
+pub struct JwksCache {
+    issuer: String,
+    min_refetch: Duration,
+    state: Mutex<CacheState>,
+    // 同時に複数のリクエストが再取得しないようにする
+    fetching: tokio::sync::Mutex<()>,
+}
+
+async fn fetch_json<T: DeserializeOwned>(uri: &str) -> anyhow::Result<(T, HeaderMap)> {
+    let https = HttpsConnector::new();
+    let client = Client::builder().build::<_, hyper::Body>(https);
+    let response = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.get(
+            uri.parse()
+                .with_context(|| format!("invalid URL: {}", uri))?,
+        ),
+    )
+    .await
+    .with_context(|| format!("timed out fetching {}", uri))?
+    .with_context(|| format!("failed to fetch {}", uri))?;
+    if !response.status().is_success() {
+        bail!("{} returned {}", uri, response.status());
+    }
+    let headers = response.headers().clone();
+    let body_bytes = to_bytes(response.into_body()).await?;
+    let body = serde_json::from_slice(&body_bytes)
+        .with_context(|| format!("invalid JSON from {}", uri))?;
+    Ok((body, headers))
+}
+
+// Cache-Controlのmax-ageをMIN_TTL〜MAX_TTLに収めて返す
+fn time_to_live(headers: &HeaderMap) -> Duration {
+    let Some(cache_control) = headers.get(CACHE_CONTROL).and_then(|v| v.to_str().ok()) else {
+        return DEFAULT_TTL;
+    };
+    let mut ttl = DEFAULT_TTL;
+    for directive in cache_control.split(',').map(str::trim) {
+        let directive = directive.to_ascii_lowercase();
+        if directive == "no-cache" || directive == "no-store" {
+            return MIN_TTL;
+        }
+        if let Some(seconds) = directive
+            .strip_prefix("max-age=")
+            .and_then(|s| s.trim_matches('"').parse::<u64>().ok())
+        {
+            ttl = Duration::from_secs(seconds);
+        }
+    }
+    ttl.clamp(MIN_TTL, MAX_TTL)
+}
+
+impl JwksCache {
+    pub fn new(issuer: &str, min_refetch: Duration) -> Arc<Self> {
+        Arc::new(JwksCache {
+            issuer: issuer.to_string(),
+            min_refetch,
+            state: Mutex::new(CacheState {
+                jwks_uri: None,
+                keys: None,
+                expires_at: Instant::now(),
+                last_attempt: None,
+                last_success: None,
+                failures: 0,
+            }),
+            fetching: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    async fn discover(&self) -> Result<String, RefreshError> {
+        let discovery_uri = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let (document, _): (DiscoveryDocument, _) = fetch_json(&discovery_uri)
+            .await
+            .map_err(RefreshError::Unreachable)?;
+        // OIDC Discoveryの仕様どおり、設定したissuerと完全に一致しなければ使わない
+        if document.issuer != self.issuer {
+            return Err(RefreshError::IssuerMismatch {
+                expected: self.issuer.clone(),
+                actual: document.issuer,
+            });
+        }
+        Ok(document.jwks_uri)
+    }
+
+    pub async fn refresh(&self) -> Result<(), RefreshError> {
+        let _fetching = self.fetching.lock().await;
+        let result = self.fetch().await;
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(()) => state.last_success = Some(SystemTime::now()),
+            Err(_) => state.failures += 1,
+        }
+        result
+    }
+
+    async fn fetch(&self) -> Result<(), RefreshError> {
+        let jwks_uri = {
+            let mut state = self.state.lock().unwrap();
+            state.last_attempt = Some(Instant::now());
+            state.jwks_uri.clone()
+        };
+        let jwks_uri = match jwks_uri {
+            Some(jwks_uri) => jwks_uri,
+            None => {
+                let jwks_uri = self.discover().await?;
+                self.state.lock().unwrap().jwks_uri = Some(jwks_uri.clone());
+                jwks_uri
+            }
+        };
+
+        let (keys, headers): (JwkSet, _) = fetch_json(&jwks_uri)
+            .await
+            .map_err(RefreshError::Unreachable)?;
+        let mut state = self.state.lock().unwrap();
+        state.keys = Some(keys);
+        state.expires_at = Instant::now() + time_to_live(&headers);
+        Ok(())
+    }
+
+    pub fn status(&self) -> JwksStatus {
+        let state = self.state.lock().unwrap();
+        JwksStatus {
+            keys_loaded: state.keys.is_some(),
+            last_success: state.last_success,
+            failures: state.failures,
+        }
+    }
+
+    fn lookup(&self, kid: &str) -> (Option<Jwk>, bool) {
+        let state = self.state.lock().unwrap();
+        let jwk = state.keys.as_ref().and_then(|keys| keys.find(kid)).cloned();
+        let may_refetch = state
+            .last_attempt
+            .is_none_or(|last| last.elapsed() >= self.min_refetch);
+        (jwk, may_refetch)
+    }
+
+    pub async fn find(&self, kid: &str) -> Result<Jwk, AuthError> {
+        let (jwk, may_refetch) = self.lookup(kid);
+        if let Some(jwk) = jwk {
+            return Ok(jwk);
+        }
+
+        // 鍵がローテーションされた可能性があるので取り直す。ただし間隔はmin_refetch以上あける
+        if may_refetch {
+            if let Err(e) = self.refresh().await {
+                log::warn!("failed to refresh JWKS for {}: {}", self.issuer, e);
+            }
+            if let (Some(jwk), _) = self.lookup(kid) {
+                return Ok(jwk);
+            }
+        }
+
+        if self.state.lock().unwrap().keys.is_none() {
+            Err(AuthError::Unavailable(
+                "the identity provider is unavailable".to_string(),
+            ))
+        } else {
+            Err(AuthError::Unauthorized("no valid jwk".to_string()))
+        }
+    }
+
+    // 期限が来たら取り直すタスクを起動する。キャッシュが破棄されたら終了する
+    pub fn spawn_refresh(self: &Arc<Self>) {
+        let cache: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                let wait = {
+                    let Some(cache) = cache.upgrade() else { return };
+                    let state = cache.state.lock().unwrap();
+                    if state.keys.is_some() {
+                        state.expires_at.saturating_duration_since(Instant::now())
+                    } else {
+                        RETRY_INTERVAL
+                    }
+                };
+                tokio::time::sleep(wait.max(Duration::from_secs(1))).await;
+
+                let Some(cache) = cache.upgrade() else { return };
+                if let Err(e) = cache.refresh().await {
+                    log::warn!("failed to refresh JWKS for {}: {}", cache.issuer, e);
+                    // 次の試行まで古い鍵を使い続ける
+                    let mut state = cache.state.lock().unwrap();
+                    state.expires_at = Instant::now() + RETRY_INTERVAL;
+                }
+            }
+        });
+    }
+}
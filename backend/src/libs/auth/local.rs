@@ -0,0 +1,154 @@
+use super::{AuthError, AuthProvider, Principal};
+use crate::config::SessionConfig;
+use crate::models::prelude::{Sessions, Users};
+use crate::models::sessions;
+use crate::router_comp::auth_router::SESSION_COOKIE;
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use axum_extra::extract::cookie::{Key, PrivateCookieJar};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+};
+use serde::{Deserialize, Serialize};
+
+// last_seen_atの更新はこの間隔より頻繁には行わない
+const LAST_SEEN_INTERVAL_MINUTES: i64 = 5;
+
+// セッションクッキーの暗号化鍵。currentで暗号化し、previousはローテーション前のクッキーの復号にだけ使う
+pub struct SessionKeys {
+    pub current: Key,
+    pub previous: Vec<Key>,
+}
+
+impl SessionKeys {
+    // 鍵が設定されていなければ起動ごとに生成する(再起動でセッションは無効になる)
+    pub fn from_config(config: &SessionConfig) -> Self {
+        let mut keys = config.keys.iter().map(|key| {
+            // Config::validateで64バイト以上のbase64であることを確認済み
+            Key::from(&BASE64.decode(key).expect("session key is valid base64"))
+        });
+        match keys.next() {
+            Some(current) => SessionKeys {
+                current,
+                previous: keys.collect(),
+            },
+            None => SessionKeys {
+                current: Key::generate(),
+                previous: Vec::new(),
+            },
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.current).chain(&self.previous)
+    }
+
+    pub fn session_id(&self, headers: &HeaderMap) -> Option<String> {
+        self.all().find_map(|key| {
+            PrivateCookieJar::from_headers(headers, key.clone())
+                .get(SESSION_COOKIE)
+                .map(|cookie| cookie.value().to_owned())
+        })
+    }
+
+    // メールのリンクに載せる期限付きのトークン。DBに保存せず、署名とメールアドレスで検証する
+    pub fn sign_user_token(
+        &self,
+        purpose: &str,
+        user_id: i32,
+        email: &str,
+        ttl: Duration,
+    ) -> String {
+        let claims = UserTokenClaims {
+            sub: user_id.to_string(),
+            email: email.to_string(),
+            purpose: purpose.to_string(),
+            exp: (Utc::now() + ttl).timestamp(),
+        };
+        encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(self.current.signing()),
+        )
+        .expect("HS256 tokens can always be signed")
+    }
+
+    // 署名が有効で期限内なら(ユーザーID, メールアドレス)を返す
+    pub fn verify_user_token(&self, purpose: &str, token: &str) -> Option<(i32, String)> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        let claims = self.all().find_map(|key| {
+            decode::<UserTokenClaims>(token, &DecodingKey::from_secret(key.signing()), &validation)
+                .ok()
+        })?;
+        if claims.claims.purpose != purpose {
+            return None;
+        }
+        Some((claims.claims.sub.parse().ok()?, claims.claims.email))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserTokenClaims {
+    sub: String,
+    email: String,
+    // 別の用途のトークンを流用できないようにする
+    purpose: String,
+    exp: i64,
+}
+
+// auth_routerのloginで発行したセッションクッキーで認証する
+pub struct LocalSessionProvider {
+    db: DatabaseConnection,
+    keys: std::sync::Arc<SessionKeys>,
+}
+
+impl LocalSessionProvider {
+    pub fn new(db: DatabaseConnection, keys: std::sync::Arc<SessionKeys>) -> Self {
+        LocalSessionProvider { db, keys }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for LocalSessionProvider {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError> {
+        let Some(session_id) = self.keys.session_id(headers) else {
+            return Err(AuthError::Unauthorized("ログインしてください".to_string()));
+        };
+
+        let session = Sessions::find()
+            .filter(sessions::Column::SessionId.eq(session_id))
+            .filter(sessions::Column::ExpiresAt.gt(Utc::now()))
+            .find_also_related(Users)
+            .one(&self.db)
+            .await
+            .map_err(|e| AuthError::Unavailable(format!("failed to load session: {}", e)))?;
+
+        let Some((session, Some(user))) = session else {
+            return Err(AuthError::Unauthorized("ログインしていません".to_string()));
+        };
+
+        let session_id = session.id;
+        if Utc::now() - session.last_seen_at.with_timezone(&Utc)
+            > Duration::minutes(LAST_SEEN_INTERVAL_MINUTES)
+        {
+            let mut session = session.into_active_model();
+            session.last_seen_at = Set(Utc::now().into());
+            if let Err(e) = session.update(&self.db).await {
+                log::warn!("failed to update last_seen_at: {}", e);
+            }
+        }
+
+        Ok(Principal {
+            provider: "local",
+            subject: user.id.to_string(),
+            email: Some(user.email),
+            session_id: Some(session_id),
+        })
+    }
+}
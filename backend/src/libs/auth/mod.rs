@@ -0,0 +1,85 @@
+use crate::config::AuthConfig;
+use async_trait::async_trait;
+use axum::http::{header::AUTHORIZATION, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use sea_orm::DatabaseConnection;
+use std::sync::Arc;
+
+mod jwks;
+mod local;
+mod oidc;
+mod static_token;
+pub mod totp;
+
+pub use jwks::JwksStatus;
+pub use local::{LocalSessionProvider, SessionKeys};
+pub use oidc::OidcProvider;
+pub(crate) use static_token::constant_time_eq;
+pub use static_token::StaticTokenProvider;
+
+// 認証済みの利用者。validate_sessionがリクエストのextensionsに入れる
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub provider: &'static str,
+    pub subject: String,
+    pub email: Option<String>,
+    // ローカルアカウントの場合、認証に使ったsessionsの行ID
+    pub session_id: Option<i32>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Unauthorized(String),
+    // IdPに接続できないなど、利用者側の問題ではない場合
+    Unavailable(String),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        match self {
+            AuthError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message).into_response(),
+            AuthError::Unavailable(message) => {
+                (StatusCode::SERVICE_UNAVAILABLE, message).into_response()
+            }
+        }
+    }
+}
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError>;
+
+    // JWKSを取得するプロバイダだけが返す。/metricsに使う
+    fn jwks_status(&self) -> Option<JwksStatus> {
+        None
+    }
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Result<&str, AuthError> {
+    let Some(authorization) = headers.get(AUTHORIZATION) else {
+        return Err(AuthError::Unauthorized(
+            "no authorization header".to_string(),
+        ));
+    };
+    let Ok(authorization) = authorization.to_str() else {
+        return Err(AuthError::Unauthorized(
+            "invalid authorization header".to_string(),
+        ));
+    };
+    authorization
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| AuthError::Unauthorized("No Bearer".to_string()))
+}
+
+// 設定に応じて認証プロバイダを作成する。OIDCの場合はここでdiscoveryを行う
+pub async fn from_config(
+    config: &AuthConfig,
+    db: &DatabaseConnection,
+    keys: &Arc<SessionKeys>,
+) -> anyhow::Result<Arc<dyn AuthProvider>> {
+    Ok(match config {
+        AuthConfig::Oidc(oidc) => Arc::new(OidcProvider::discover(oidc).await?),
+        AuthConfig::Local => Arc::new(LocalSessionProvider::new(db.clone(), keys.clone())),
+        AuthConfig::Static(config) => Arc::new(StaticTokenProvider::new(config)),
+    })
+}
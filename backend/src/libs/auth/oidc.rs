@@ -0,0 +1,126 @@
+use super::jwks::{JwksCache, RefreshError};
+use super::{bearer_token, AuthError, AuthProvider, JwksStatus, Principal};
+use crate::config::OidcConfig;
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    email: Option<String>,
+    // 未確認のメールアドレスで招待を受けられないようにする
+    email_verified: Option<bool>,
+}
+
+// issuerの/.well-known/openid-configurationからJWKSの場所を調べ、JWTを手元で検証する
+pub struct OidcProvider {
+    issuer: String,
+    audience: String,
+    jwks: Arc<JwksCache>,
+}
+
+impl OidcProvider {
+    // IdPに接続できない場合も起動は続け、鍵が取得できるまで認証は503を返す
+    pub async fn discover(config: &OidcConfig) -> anyhow::Result<Self> {
+        let jwks = JwksCache::new(
+            &config.issuer,
+            Duration::from_secs(config.jwks_min_refetch_secs),
+        );
+        match jwks.refresh().await {
+            Ok(()) => {}
+            Err(e @ RefreshError::IssuerMismatch { .. }) => return Err(e.into()),
+            Err(e) => log::warn!(
+                "starting without JWKS for {}, will retry: {}",
+                config.issuer,
+                e
+            ),
+        }
+        jwks.spawn_refresh();
+
+        Ok(OidcProvider {
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            jwks,
+        })
+    }
+}
+
+// JWKにalgがあればそれだけを、なければ鍵の種類で使える署名アルゴリズムを許す。
+// 公開されたJWKSの共通鍵(oct)は誰でも読めるので、HMACは許さない
+fn allowed_algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+    if let Some(algorithm) = jwk.common.algorithm {
+        return vec![algorithm];
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => Vec::new(),
+        },
+        AlgorithmParameters::OctetKeyPair(params) if params.curve == EllipticCurve::Ed25519 => {
+            vec![Algorithm::EdDSA]
+        }
+        AlgorithmParameters::OctetKeyPair(_) | AlgorithmParameters::OctetKey(_) => Vec::new(),
+    }
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError> {
+        let jwt_token = bearer_token(headers)?;
+        let Ok(header) = decode_header(jwt_token) else {
+            return Err(AuthError::Unauthorized(
+                "failed to decode header".to_string(),
+            ));
+        };
+        let Some(kid) = header.kid else {
+            return Err(AuthError::Unauthorized("no valid kid".to_string()));
+        };
+        let jwk = self.jwks.find(&kid).await?;
+        let Ok(decoding_key) = DecodingKey::from_jwk(&jwk) else {
+            return Err(AuthError::Unauthorized("unsupported jwk".to_string()));
+        };
+
+        // トークンのヘッダーのalgは送り手が決められるので、鍵の側で許すアルゴリズムに含まれるものだけを受け付ける
+        let algorithms = allowed_algorithms(&jwk);
+        if !algorithms.contains(&header.alg) {
+            return Err(AuthError::Unauthorized(format!(
+                "alg {:?} is not allowed for key {}",
+                header.alg, kid
+            )));
+        }
+        let mut validation = Validation::default();
+        validation.algorithms = algorithms;
+        validation.set_audience(&[&self.audience]);
+        validation.set_issuer(&[&self.issuer]);
+
+        match decode::<Claims>(jwt_token, &decoding_key, &validation) {
+            Ok(data) => Ok(Principal {
+                provider: "oidc",
+                subject: data.claims.sub,
+                email: data
+                    .claims
+                    .email
+                    .filter(|_| data.claims.email_verified != Some(false)),
+                session_id: None,
+            }),
+            Err(e) => Err(AuthError::Unauthorized(format!("invalid token: {}", e))),
+        }
+    }
+    fn jwks_status(&self) -> Option<JwksStatus> {
+        Some(self.jwks.status())
+    }
+}
@@ -0,0 +1,43 @@
+use super::{bearer_token, AuthError, AuthProvider, Principal};
+use crate::config::StaticTokenConfig;
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+
+// CIなどから使う固定トークン。設定ファイルに書いたトークン名がsubjectになる
+pub struct StaticTokenProvider {
+    tokens: Vec<(String, String)>,
+}
+
+impl StaticTokenProvider {
+    pub fn new(config: &StaticTokenConfig) -> Self {
+        StaticTokenProvider {
+            tokens: config
+                .tokens
+                .iter()
+                .map(|(name, token)| (name.clone(), token.clone()))
+                .collect(),
+        }
+    }
+}
+
+// 一致するまでの時間からトークンを推測されないように、長さ以外は全バイトを比較する
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[async_trait]
+impl AuthProvider for StaticTokenProvider {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError> {
+        let token = bearer_token(headers)?;
+        self.tokens
+            .iter()
+            .find(|(_, expected)| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+            .map(|(name, _)| Principal {
+                provider: "static",
+                subject: name.clone(),
+                email: None,
+                session_id: None,
+            })
+            .ok_or_else(|| AuthError::Unauthorized("invalid token".to_string()))
+    }
+}
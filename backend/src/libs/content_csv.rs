@@ -0,0 +1,359 @@
+use crate::models::{content_items, fields};
+use crate::router_comp::content_router::FieldType;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use uuid::Uuid;
+
+const ID_COLUMN: &str = "id";
+// 列の対応を指定するクエリパラメータの接頭辞。ほかのクエリパラメータは対応として扱わない
+pub const MAPPING_PREFIX: &str = "map.";
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+// 1列目はコンテンツアイテムID、以降はフィールドの定義順。取り込むとIDの一致するアイテムを更新する
+pub fn write_csv(
+    fields: &[fields::Model],
+    items: &[content_items::Model],
+) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    let mut header = vec![ID_COLUMN];
+    header.extend(fields.iter().map(|field| field.display_id.as_str()));
+    writer.write_record(&header)?;
+
+    for item in items {
+        let mut record = vec![item.id.to_string()];
+        record.extend(
+            fields
+                .iter()
+                .map(|field| cell(item.data.get(&field.display_id))),
+        );
+        writer.write_record(&record)?;
+    }
+
+    writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))
+}
+
+// 「map.<CSVのヘッダー名>=<フィールドのdisplay_id>」の形のクエリパラメータだけを列の対応として取り出す
+pub fn column_mapping(query: &HashMap<String, String>) -> HashMap<String, String> {
+    query
+        .iter()
+        .filter_map(|(key, target)| {
+            key.strip_prefix(MAPPING_PREFIX)
+                .map(|header| (header.to_string(), target.clone()))
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+pub struct RowError {
+    pub row: u64,
+    pub errors: Vec<String>,
+}
+
+// id列に既存のアイテムのIDがあれば、そのアイテムのdataをこの行で置き換える
+#[derive(Debug)]
+pub struct CsvRow {
+    pub id: Option<Uuid>,
+    pub data: Value,
+}
+
+pub struct ParsedCsv {
+    pub rows: Vec<CsvRow>,
+    pub errors: Vec<RowError>,
+    pub ignored_columns: Vec<String>,
+}
+
+// mappingは「CSVのヘッダー名 → フィールドのdisplay_id」。指定がないヘッダーは同名のフィールドに対応させる
+pub fn read_csv(
+    fields: &[fields::Model],
+    input: &[u8],
+    mapping: &HashMap<String, String>,
+) -> Result<ParsedCsv, String> {
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("CSVのヘッダーを読み込めません: {}", e))?
+        .clone();
+
+    for (header, target) in mapping {
+        if !headers.iter().any(|h| h == header) {
+            return Err(format!("マッピングされた列がCSVにありません: {}", header));
+        }
+        if !fields.iter().any(|field| &field.display_id == target) {
+            return Err(format!(
+                "マッピング先のフィールドがありません: {} → {}",
+                header, target
+            ));
+        }
+    }
+
+    // マッピングされていないid列はアイテムIDとして読む
+    let id_column = headers
+        .iter()
+        .position(|header| header == ID_COLUMN && !mapping.contains_key(header));
+    let mut columns: Vec<Option<&fields::Model>> = Vec::new();
+    let mut ignored_columns = Vec::new();
+    for (index, header) in headers.iter().enumerate() {
+        if Some(index) == id_column {
+            columns.push(None);
+            continue;
+        }
+        let name = mapping.get(header).map(String::as_str).unwrap_or(header);
+        let field = fields.iter().find(|field| field.display_id == name);
+        if field.is_none() {
+            ignored_columns.push(header.to_string());
+        }
+        columns.push(field);
+    }
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen_ids = HashSet::new();
+
+    for result in reader.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                let row = e.position().map(|p| p.line()).unwrap_or_default();
+                errors.push(RowError {
+                    row,
+                    errors: vec![e.to_string()],
+                });
+                continue;
+            }
+        };
+        let row = record.position().map(|p| p.line()).unwrap_or_default();
+        let mut data = Map::new();
+        let mut row_errors = Vec::new();
+
+        let id = match id_column.and_then(|index| record.get(index)).map(str::trim) {
+            None | Some("") => None,
+            Some(raw) => match Uuid::parse_str(raw) {
+                Ok(id) if seen_ids.insert(id) => Some(id),
+                Ok(_) => {
+                    row_errors.push(format!(
+                        "{}: 同じIDの行がすでにあります: {}",
+                        ID_COLUMN, raw
+                    ));
+                    None
+                }
+                Err(_) => {
+                    row_errors.push(format!("{}: \"{}\" はUUIDではありません", ID_COLUMN, raw));
+                    None
+                }
+            },
+        };
+
+        for (raw, field) in record.iter().zip(&columns) {
+            let Some(field) = field else {
+                continue;
+            };
+            if raw.is_empty() {
+                continue;
+            }
+            let Ok(field_type) = FieldType::from_str(&field.field_type) else {
+                row_errors.push(format!("{}: 不明なフィールドタイプです", field.display_id));
+                continue;
+            };
+            match field_type.coerce_str(raw) {
+                Ok(value) => {
+                    data.insert(field.display_id.clone(), value);
+                }
+                Err(e) => row_errors.push(format!("{}: {}", field.display_id, e)),
+            }
+        }
+
+        for field in fields.iter().filter(|field| field.required) {
+            if !data.contains_key(&field.display_id) {
+                row_errors.push(format!("必須フィールドがありません: {}", field.display_id));
+            }
+        }
+
+        if row_errors.is_empty() {
+            rows.push(CsvRow {
+                id,
+                data: Value::Object(data),
+            });
+        } else {
+            errors.push(RowError {
+                row,
+                errors: row_errors,
+            });
+        }
+    }
+
+    Ok(ParsedCsv {
+        rows,
+        errors,
+        ignored_columns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(display_id: &str, field_type: FieldType, required: bool) -> fields::Model {
+        fields::Model {
+            id: 1,
+            content_type_id: 1,
+            display_id: display_id.to_string(),
+            field_type: field_type.to_string(),
+            required,
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+        }
+    }
+
+    fn fields() -> Vec<fields::Model> {
+        vec![
+            field("title", FieldType::Text, true),
+            field("views", FieldType::Number, false),
+            field("draft", FieldType::Boolean, false),
+        ]
+    }
+
+    fn read(input: &str, mapping: &[(&str, &str)]) -> Result<ParsedCsv, String> {
+        let mapping = mapping
+            .iter()
+            .map(|(header, target)| (header.to_string(), target.to_string()))
+            .collect();
+        read_csv(&fields(), input.as_bytes(), &mapping)
+    }
+
+    #[test]
+    fn only_prefixed_query_parameters_map_columns() {
+        let query = HashMap::from([
+            ("map.Title".to_string(), "title".to_string()),
+            ("map.".to_string(), "views".to_string()),
+            ("dry_run".to_string(), "true".to_string()),
+        ]);
+        assert_eq!(
+            column_mapping(&query),
+            HashMap::from([
+                ("Title".to_string(), "title".to_string()),
+                (String::new(), "views".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn exported_csv_reads_back_with_ids() {
+        let now = chrono::Utc::now().into();
+        let item = content_items::Model {
+            id: Uuid::new_v4(),
+            content_type_id: 1,
+            data: json!({ "title": "a, \"quoted\" title", "views": 3, "draft": true }),
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            deleted_by_key_id: None,
+        };
+        let csv = write_csv(&fields(), std::slice::from_ref(&item)).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.clone()).unwrap(),
+            format!(
+                "id,title,views,draft\n{},\"a, \"\"quoted\"\" title\",3,true\n",
+                item.id
+            )
+        );
+
+        let parsed = read_csv(&fields(), &csv, &HashMap::new()).unwrap();
+        assert!(parsed.errors.is_empty());
+        assert!(parsed.ignored_columns.is_empty());
+        assert_eq!(parsed.rows.len(), 1);
+        assert_eq!(parsed.rows[0].id, Some(item.id));
+        assert_eq!(parsed.rows[0].data, item.data);
+    }
+
+    #[test]
+    fn mapped_headers_import_into_fields() {
+        let parsed = read(
+            "Title,Views,notes\nhello,12,ignored\n",
+            &[("Title", "title"), ("Views", "views")],
+        )
+        .unwrap();
+        assert_eq!(parsed.ignored_columns, vec!["notes"]);
+        assert_eq!(parsed.rows[0].id, None);
+        assert_eq!(
+            parsed.rows[0].data,
+            json!({ "title": "hello", "views": 12 })
+        );
+    }
+
+    #[test]
+    fn mappings_must_name_existing_columns_and_fields() {
+        assert_eq!(
+            read("title\nhello\n", &[("Title", "title")]).err().unwrap(),
+            "マッピングされた列がCSVにありません: Title"
+        );
+        assert_eq!(
+            read("Title\nhello\n", &[("Title", "heading")])
+                .err()
+                .unwrap(),
+            "マッピング先のフィールドがありません: Title → heading"
+        );
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_and_valid_rows_kept() {
+        let id = Uuid::new_v4();
+        let input = format!(
+            "id,title,views,draft\n\
+             {id},first,1,yes\n\
+             ,,2,\n\
+             not-a-uuid,third,many,maybe\n\
+             {id},duplicate,4,no\n",
+            id = id
+        );
+        let parsed = read(&input, &[]).unwrap();
+        assert_eq!(parsed.rows.len(), 1);
+        assert_eq!(parsed.rows[0].id, Some(id));
+        assert_eq!(
+            parsed.rows[0].data,
+            json!({ "title": "first", "views": 1, "draft": true })
+        );
+
+        let errors: Vec<(u64, Vec<String>)> = parsed
+            .errors
+            .into_iter()
+            .map(|e| (e.row, e.errors))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (3, vec!["必須フィールドがありません: title".to_string()]),
+                (
+                    4,
+                    vec![
+                        "id: \"not-a-uuid\" はUUIDではありません".to_string(),
+                        "views: \"many\" is not a number".to_string(),
+                        "draft: \"maybe\" is not a boolean".to_string(),
+                    ]
+                ),
+                (5, vec![format!("id: 同じIDの行がすでにあります: {}", id)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn an_id_column_can_be_mapped_onto_a_field() {
+        let parsed = read("id,title\nA-1,hello\n", &[("id", "views")]).unwrap();
+        assert_eq!(
+            parsed.errors[0].errors,
+            vec!["views: \"A-1\" is not a number"]
+        );
+    }
+}
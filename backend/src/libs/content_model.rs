@@ -0,0 +1,126 @@
+use crate::models::prelude::{ContentTypes, Fields, Services};
+use crate::models::{content_types, fields, services};
+use crate::router_comp::content_router::FieldType;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+pub type ContentModel = Vec<(content_types::Model, Vec<fields::Model>)>;
+
+// サービスに属するコンテンツタイプとそのフィールドをまとめて取得する
+pub async fn load_content_model(
+    db: &DatabaseConnection,
+    service_id: &str,
+) -> Result<ContentModel, DbErr> {
+    ContentTypes::find()
+        .filter(content_types::Column::ServiceId.eq(service_id))
+        .order_by_asc(content_types::Column::Id)
+        .find_with_related(Fields)
+        .order_by_asc(fields::Column::Id)
+        .all(db)
+        .await
+}
+
+// サービスが存在しなければNoneを返す
+pub async fn load_service_content_model(
+    db: &DatabaseConnection,
+    service_id: &str,
+) -> Result<Option<(services::Model, ContentModel)>, DbErr> {
+    let Some(service) = Services::find_by_id(service_id).one(db).await? else {
+        return Ok(None);
+    };
+    let content_model = load_content_model(db, &service.id).await?;
+    Ok(Some((service, content_model)))
+}
+
+// コンテンツタイプ名から型名を作る。名前が重複しても衝突しないようにIDを付ける
+pub fn type_name(content_type: &content_types::Model) -> String {
+    let mut name = String::new();
+    let mut upper = true;
+    for c in content_type.name.chars() {
+        if c.is_ascii_alphanumeric() {
+            if upper {
+                name.push(c.to_ascii_uppercase());
+            } else {
+                name.push(c);
+            }
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert_str(0, "ContentType");
+    }
+    format!("{}{}", name, content_type.id)
+}
+
+// フィールド定義からコンテンツアイテムのdataを表すJSON Schemaを作る
+pub fn data_schema(fields: &[fields::Model]) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+
+    for field in fields {
+        let schema = match FieldType::from_str(&field.field_type) {
+            Ok(field_type) => field_type.json_schema(),
+            Err(_) => json!({}),
+        };
+        properties.insert(field.display_id.clone(), schema);
+        if field.required {
+            required.push(Value::String(field.display_id.clone()));
+        }
+    }
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_type(id: i32, name: &str) -> content_types::Model {
+        content_types::Model {
+            id,
+            name: name.to_string(),
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+            service_id: None,
+        }
+    }
+
+    #[test]
+    fn type_names_are_pascal_case_identifiers_with_the_id() {
+        assert_eq!(type_name(&content_type(3, "blog posts")), "BlogPosts3");
+        assert_eq!(type_name(&content_type(4, "news-item_v2")), "NewsItemV24");
+        assert_eq!(
+            type_name(&content_type(5, "2024 events")),
+            "ContentType2024Events5"
+        );
+        assert_eq!(type_name(&content_type(6, "お知らせ")), "ContentType6");
+    }
+
+    #[test]
+    fn unknown_field_types_accept_any_value() {
+        let field = fields::Model {
+            id: 1,
+            content_type_id: 1,
+            display_id: "legacy".to_string(),
+            field_type: "Color".to_string(),
+            required: true,
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+        };
+        assert_eq!(
+            data_schema(&[field]),
+            json!({
+                "type": "object",
+                "properties": { "legacy": {} },
+                "required": ["legacy"]
+            })
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::libs::content_model::data_schema;
+use crate::models::{content_types, fields};
+use crate::router_comp::content_router::{FieldType, NewField};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+// 取り込み時に意味を持たないので無視するキーワード
+const ANNOTATIONS: [&str; 6] = [
+    "title",
+    "description",
+    "$comment",
+    "examples",
+    "default",
+    "deprecated",
+];
+
+pub fn content_type_schema(content_type: &content_types::Model, fields: &[fields::Model]) -> Value {
+    let mut schema = data_schema(fields);
+    let object = schema
+        .as_object_mut()
+        .expect("data_schema returns an object");
+    object.insert("$schema".to_string(), json!(DIALECT));
+    object.insert("title".to_string(), json!(content_type.name));
+    schema
+}
+
+pub struct ImportedContentType {
+    pub name: String,
+    pub fields: Vec<NewField>,
+}
+
+// JSON Schemaからコンテンツタイプを組み立てる。対応していない構文はすべてエラーとして返す
+pub fn parse_content_type_schema(schema: &Value) -> Result<ImportedContentType, Vec<String>> {
+    let mut errors = Vec::new();
+
+    let Some(root) = schema.as_object() else {
+        return Err(vec!["$: the schema must be a JSON object".to_string()]);
+    };
+
+    for key in root.keys() {
+        if !matches!(
+            key.as_str(),
+            "$schema" | "$id" | "type" | "properties" | "required" | "additionalProperties"
+        ) && !ANNOTATIONS.contains(&key.as_str())
+        {
+            errors.push(format!("$.{}: unsupported keyword", key));
+        }
+    }
+
+    if let Some(dialect) = root.get("$schema") {
+        if dialect.as_str() != Some(DIALECT) {
+            errors.push(format!("$.$schema: only {} is supported", DIALECT));
+        }
+    }
+
+    // 宣言していないプロパティは保存しないので、真偽値以外は強制できない
+    if let Some(additional) = root.get("additionalProperties") {
+        if !additional.is_boolean() {
+            errors.push(
+                "$.additionalProperties: only true or false is supported, not a schema".to_string(),
+            );
+        }
+    }
+
+    if root.get("type").and_then(Value::as_str) != Some("object") {
+        errors.push("$.type: the root schema must have \"type\": \"object\"".to_string());
+    }
+
+    let name = match root.get("title").and_then(Value::as_str) {
+        Some(title) if !title.trim().is_empty() => title.trim().to_string(),
+        _ => {
+            errors.push(
+                "$.title: a non-empty title is required as the content type name".to_string(),
+            );
+            String::new()
+        }
+    };
+
+    let mut required = HashSet::new();
+    match root.get("required") {
+        None => {}
+        Some(Value::Array(names)) => {
+            for name in names {
+                match name.as_str() {
+                    Some(name) => {
+                        required.insert(name.to_string());
+                    }
+                    None => errors.push("$.required: entries must be strings".to_string()),
+                }
+            }
+        }
+        Some(_) => errors.push("$.required: must be an array of property names".to_string()),
+    }
+
+    let mut fields = Vec::new();
+    match root.get("properties") {
+        None => {}
+        Some(Value::Object(properties)) => {
+            for (property, property_schema) in properties {
+                match parse_field_type(property, property_schema) {
+                    Ok(field_type) => fields.push(NewField {
+                        display_name: property.clone(),
+                        field_type,
+                        required: required.contains(property),
+                    }),
+                    Err(e) => errors.push(e),
+                }
+            }
+            for name in &required {
+                if !properties.contains_key(name) {
+                    errors.push(format!(
+                        "$.required: \"{}\" is not declared in properties",
+                        name
+                    ));
+                }
+            }
+        }
+        Some(_) => errors.push("$.properties: must be an object".to_string()),
+    }
+
+    if errors.is_empty() {
+        Ok(ImportedContentType { name, fields })
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse_field_type(property: &str, schema: &Value) -> Result<FieldType, String> {
+    let path = format!("$.properties.{}", property);
+    let Some(schema) = schema.as_object() else {
+        return Err(format!("{}: must be a schema object", path));
+    };
+
+    for key in schema.keys() {
+        if key != "type" && key != "format" && !ANNOTATIONS.contains(&key.as_str()) {
+            return Err(format!("{}.{}: unsupported keyword", path, key));
+        }
+    }
+
+    let format = schema.get("format").and_then(Value::as_str);
+    match (schema.get("type").and_then(Value::as_str), format) {
+        (Some("string"), Some("date-time")) => Ok(FieldType::Date),
+        (Some("string"), None) => Ok(FieldType::Text),
+        (Some("number") | Some("integer"), None) => Ok(FieldType::Number),
+        (Some("boolean"), None) => Ok(FieldType::Boolean),
+        (Some("string"), Some(format)) => Err(format!(
+            "{}.format: \"{}\" is not supported, only \"date-time\"",
+            path, format
+        )),
+        (Some(ty), Some(_)) => Err(format!(
+            "{}.format: not supported for type \"{}\"",
+            path, ty
+        )),
+        (Some(ty), None) => Err(format!(
+            "{}.type: \"{}\" cannot be mapped to a field type",
+            path, ty
+        )),
+        (None, _) => Err(format!("{}.type: a single type name is required", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(display_id: &str, field_type: FieldType, required: bool) -> fields::Model {
+        fields::Model {
+            id: 1,
+            content_type_id: 1,
+            display_id: display_id.to_string(),
+            field_type: field_type.to_string(),
+            required,
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+        }
+    }
+
+    fn parse_errors(schema: Value) -> Vec<String> {
+        match parse_content_type_schema(&schema) {
+            Ok(_) => panic!("{} should not be accepted", schema),
+            Err(errors) => errors,
+        }
+    }
+
+    #[test]
+    fn exported_schemas_import_as_the_same_fields() {
+        let content_type = content_types::Model {
+            id: 1,
+            name: "posts".to_string(),
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+            service_id: Some("service".to_string()),
+        };
+        let fields = vec![
+            field("title", FieldType::Text, true),
+            field("views", FieldType::Number, false),
+            field("published_at", FieldType::Date, false),
+            field("draft", FieldType::Boolean, true),
+        ];
+
+        let imported =
+            parse_content_type_schema(&content_type_schema(&content_type, &fields)).unwrap();
+        assert_eq!(imported.name, "posts");
+        let mut imported: Vec<_> = imported
+            .fields
+            .iter()
+            .map(|f| (f.display_name.as_str(), f.field_type, f.required))
+            .collect();
+        imported.sort_by_key(|f| f.0);
+        assert_eq!(
+            imported,
+            vec![
+                ("draft", FieldType::Boolean, true),
+                ("published_at", FieldType::Date, false),
+                ("title", FieldType::Text, true),
+                ("views", FieldType::Number, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn integers_and_annotations_are_accepted() {
+        let imported = parse_content_type_schema(&json!({
+            "title": " articles ",
+            "description": "ignored",
+            "type": "object",
+            "properties": { "rank": { "type": "integer", "title": "Rank" } },
+            "additionalProperties": false
+        }))
+        .unwrap();
+        assert_eq!(imported.name, "articles");
+        assert_eq!(imported.fields[0].field_type, FieldType::Number);
+        assert!(!imported.fields[0].required);
+    }
+
+    #[test]
+    fn additional_properties_must_be_a_boolean() {
+        let errors = parse_errors(json!({
+            "title": "posts",
+            "type": "object",
+            "additionalProperties": { "type": "string" }
+        }));
+        assert_eq!(
+            errors,
+            vec!["$.additionalProperties: only true or false is supported, not a schema"]
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let errors = parse_errors(json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "array",
+            "allOf": [],
+            "required": ["missing"],
+            "properties": {
+                "email": { "type": "string", "format": "email" },
+                "tags": { "type": "array" },
+                "either": { "type": ["string", "null"] },
+                "nested": { "type": "object", "properties": {} }
+            }
+        }));
+        for expected in [
+            "$.allOf: unsupported keyword",
+            "$.$schema: only https://json-schema.org/draft/2020-12/schema is supported",
+            "$.type: the root schema must have \"type\": \"object\"",
+            "$.title: a non-empty title is required as the content type name",
+            "$.properties.email.format: \"email\" is not supported, only \"date-time\"",
+            "$.properties.tags.type: \"array\" cannot be mapped to a field type",
+            "$.properties.either.type: a single type name is required",
+            "$.properties.nested.properties: unsupported keyword",
+            "$.required: \"missing\" is not declared in properties",
+        ] {
+            assert!(
+                errors.iter().any(|e| e == expected),
+                "{:?} does not contain {}",
+                errors,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn non_object_schemas_are_rejected() {
+        assert_eq!(
+            parse_errors(json!(["not", "a", "schema"])),
+            vec!["$: the schema must be a JSON object"]
+        );
+    }
+}
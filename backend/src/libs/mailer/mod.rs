@@ -0,0 +1,100 @@
+mod templates;
+mod transport;
+
+pub use templates::{Locale, Template};
+pub use transport::{FileTransport, MailTransport, SmtpMailTransport, StdoutTransport};
+
+use crate::config::{MailConfig, MailTransportConfig};
+use anyhow::anyhow;
+use axum::http::HeaderMap;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// 送信待ちにできるメールの数。溢れた場合は送信せずにエラーを返す
+const QUEUE_CAPACITY: usize = 256;
+const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+// テンプレートからメールを作ってキューに積む。送信はバックグラウンドのタスクが行うので、
+// SMTPサーバーが遅くてもリクエストは待たされない
+#[derive(Clone)]
+pub struct Mailer {
+    from: String,
+    default_locale: Locale,
+    queue: mpsc::Sender<Email>,
+}
+
+impl Mailer {
+    pub fn start(config: &MailConfig) -> anyhow::Result<Mailer> {
+        let transport: Arc<dyn MailTransport> = match &config.transport {
+            MailTransportConfig::Smtp(smtp) => Arc::new(SmtpMailTransport::new(smtp)?),
+            MailTransportConfig::File(file) => Arc::new(FileTransport::new(&file.path)),
+            MailTransportConfig::Stdout => Arc::new(StdoutTransport),
+        };
+        Ok(Self::with_transport(config, transport))
+    }
+
+    pub fn with_transport(config: &MailConfig, transport: Arc<dyn MailTransport>) -> Mailer {
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(deliver_queued(receiver, transport));
+        Mailer {
+            from: config.from.clone(),
+            default_locale: config.default_locale,
+            queue,
+        }
+    }
+
+    // キューに積まれてまだ送信されていないメールの数
+    pub fn queue_depth(&self) -> usize {
+        self.queue.max_capacity() - self.queue.capacity()
+    }
+
+    // Accept-Languageで対応している言語があればそれを、なければ既定の言語を使う
+    pub fn locale(&self, headers: &HeaderMap) -> Locale {
+        Locale::from_headers(headers).unwrap_or(self.default_locale)
+    }
+
+    pub fn send(&self, to: &str, locale: Locale, template: Template) -> anyhow::Result<()> {
+        let (subject, text, html) = template.render(locale);
+        let email = Email {
+            from: self.from.clone(),
+            to: to.to_string(),
+            subject,
+            text,
+            html,
+        };
+        self.queue
+            .try_send(email)
+            .map_err(|e| anyhow!("the mail queue is not accepting mail: {}", e))
+    }
+}
+
+async fn deliver_queued(mut receiver: mpsc::Receiver<Email>, transport: Arc<dyn MailTransport>) {
+    while let Some(email) = receiver.recv().await {
+        for attempt in 1..=MAX_ATTEMPTS {
+            match transport.deliver(&email).await {
+                Ok(()) => break,
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    log::warn!(
+                        "failed to send mail to {} (attempt {}): {:#}",
+                        email.to,
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                }
+                Err(e) => log::error!("gave up sending mail to {}: {:#}", email.to, e),
+            }
+        }
+    }
+}
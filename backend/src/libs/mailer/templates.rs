@@ -0,0 +1,162 @@
+use axum::http::{header::ACCEPT_LANGUAGE, HeaderMap};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Locale {
+    Ja,
+    En,
+}
+
+impl std::str::FromStr for Locale {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let primary = s.split(['-', '_']).next().unwrap_or_default();
+        match primary.to_ascii_lowercase().as_str() {
+            "ja" => Ok(Locale::Ja),
+            "en" => Ok(Locale::En),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Locale {
+    // Accept-Languageのうち、対応している言語でqが最も大きいもの
+    pub fn from_headers(headers: &HeaderMap) -> Option<Locale> {
+        let accept_language = headers.get(ACCEPT_LANGUAGE)?.to_str().ok()?;
+        let mut best: Option<(Locale, f32)> = None;
+        for range in accept_language.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let Ok(locale) = parts.next().unwrap_or_default().parse::<Locale>() else {
+                continue;
+            };
+            let q = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((locale, q));
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+}
+
+pub enum Template<'a> {
+    PasswordReset {
+        link: &'a str,
+        ttl_minutes: i64,
+    },
+    EmailVerification {
+        link: &'a str,
+        ttl_hours: i64,
+    },
+    ServiceInvitation {
+        service_name: &'a str,
+        role: &'a str,
+        link: &'a str,
+        ttl_days: i64,
+    },
+}
+
+struct Source {
+    subject: &'static str,
+    text: &'static str,
+    html: &'static str,
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// テンプレート中の{{name}}を置き換える。HTMLに入れる値はエスケープする
+fn fill(template: &str, values: &[(&str, String)], html: bool) -> String {
+    let mut filled = template.to_string();
+    for (name, value) in values {
+        let value = if html {
+            escape_html(value)
+        } else {
+            value.clone()
+        };
+        filled = filled.replace(&format!("{{{{{}}}}}", name), &value);
+    }
+    filled
+}
+
+impl Template<'_> {
+    fn source(&self, locale: Locale) -> Source {
+        match (self, locale) {
+            (Template::PasswordReset { .. }, Locale::Ja) => Source {
+                subject: "パスワードの再設定",
+                text: include_str!("../../../templates/mail/password_reset.ja.txt"),
+                html: include_str!("../../../templates/mail/password_reset.ja.html"),
+            },
+            (Template::PasswordReset { .. }, Locale::En) => Source {
+                subject: "Reset your password",
+                text: include_str!("../../../templates/mail/password_reset.en.txt"),
+                html: include_str!("../../../templates/mail/password_reset.en.html"),
+            },
+            (Template::EmailVerification { .. }, Locale::Ja) => Source {
+                subject: "メールアドレスの確認",
+                text: include_str!("../../../templates/mail/email_verification.ja.txt"),
+                html: include_str!("../../../templates/mail/email_verification.ja.html"),
+            },
+            (Template::EmailVerification { .. }, Locale::En) => Source {
+                subject: "Verify your email address",
+                text: include_str!("../../../templates/mail/email_verification.en.txt"),
+                html: include_str!("../../../templates/mail/email_verification.en.html"),
+            },
+            (Template::ServiceInvitation { .. }, Locale::Ja) => Source {
+                subject: "{{service_name}}への招待",
+                text: include_str!("../../../templates/mail/service_invitation.ja.txt"),
+                html: include_str!("../../../templates/mail/service_invitation.ja.html"),
+            },
+            (Template::ServiceInvitation { .. }, Locale::En) => Source {
+                subject: "Invitation to {{service_name}}",
+                text: include_str!("../../../templates/mail/service_invitation.en.txt"),
+                html: include_str!("../../../templates/mail/service_invitation.en.html"),
+            },
+        }
+    }
+
+    fn values(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Template::PasswordReset { link, ttl_minutes } => vec![
+                ("link", link.to_string()),
+                ("ttl_minutes", ttl_minutes.to_string()),
+            ],
+            Template::EmailVerification { link, ttl_hours } => vec![
+                ("link", link.to_string()),
+                ("ttl_hours", ttl_hours.to_string()),
+            ],
+            Template::ServiceInvitation {
+                service_name,
+                role,
+                link,
+                ttl_days,
+            } => vec![
+                ("service_name", service_name.to_string()),
+                ("role", role.to_string()),
+                ("link", link.to_string()),
+                ("ttl_days", ttl_days.to_string()),
+            ],
+        }
+    }
+
+    // 件名、テキスト、HTMLを返す
+    pub fn render(&self, locale: Locale) -> (String, String, String) {
+        let source = self.source(locale);
+        let values = self.values();
+        (
+            fill(source.subject, &values, false),
+            fill(source.text, &values, false),
+            fill(source.html, &values, true),
+        )
+    }
+}
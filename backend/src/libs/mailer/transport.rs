@@ -0,0 +1,104 @@
+use super::Email;
+use crate::config::{SmtpConfig, SmtpTls};
+use anyhow::Context;
+use async_trait::async_trait;
+use lettre::message::MultiPart;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn deliver(&self, email: &Email) -> anyhow::Result<()>;
+}
+
+pub struct SmtpMailTransport {
+    transport: SmtpTransport,
+}
+
+impl SmtpMailTransport {
+    pub fn new(config: &SmtpConfig) -> anyhow::Result<SmtpMailTransport> {
+        let mut builder = match config.tls {
+            SmtpTls::Wrapper => SmtpTransport::relay(&config.host)?,
+            SmtpTls::Starttls => SmtpTransport::starttls_relay(&config.host)?,
+            SmtpTls::None => SmtpTransport::builder_dangerous(&config.host),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if !config.username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ));
+        }
+        Ok(SmtpMailTransport {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpMailTransport {
+    async fn deliver(&self, email: &Email) -> anyhow::Result<()> {
+        let message = Message::builder()
+            .from(email.from.parse().context("invalid sender")?)
+            .to(email.to.parse().context("invalid recipient")?)
+            .subject(&email.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                email.text.clone(),
+                email.html.clone(),
+            ))?;
+        // lettreのSmtpTransportは同期APIなので、ランタイムのスレッドを塞がないようにする
+        let transport = self.transport.clone();
+        tokio::task::spawn_blocking(move || transport.send(&message)).await??;
+        Ok(())
+    }
+}
+
+// 送信する代わりにJSON Linesのファイルへ追記する
+pub struct FileTransport {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileTransport {
+    pub fn new(path: &Path) -> FileTransport {
+        FileTransport {
+            path: path.to_path_buf(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileTransport {
+    async fn deliver(&self, email: &Email) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(email)?;
+        line.push('\n');
+        let _lock = self.lock.lock().unwrap();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+// 送信する代わりに標準出力へ表示する
+pub struct StdoutTransport;
+
+#[async_trait]
+impl MailTransport for StdoutTransport {
+    async fn deliver(&self, email: &Email) -> anyhow::Result<()> {
+        println!(
+            "----- mail -----\nFrom: {}\nTo: {}\nSubject: {}\n\n{}\n----------------",
+            email.from, email.to, email.subject, email.text
+        );
+        Ok(())
+    }
+}
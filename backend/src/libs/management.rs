@@ -0,0 +1,465 @@
+use crate::libs::api_keys::{issue_key, replace_role_keys};
+use crate::libs::generate_random_key::generate_key;
+use crate::libs::membership::{add_member, MemberIdentity, MemberRole};
+use crate::models::prelude::{
+    ApiKeys, ContentItems, ContentTypes, Fields, RecoveryCodes, RolePermissions, Roles,
+    ServiceInvitations, ServiceMembers, Services, Users,
+};
+use crate::models::{
+    api_keys, content_items, content_types, fields, recovery_codes, role_permissions, roles,
+    service_invitations, service_members, services, users,
+};
+use crate::router_comp::content_router::NewField;
+use crate::router_comp::service_router::Permission;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    IntoActiveModel, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+// HTTPハンドラとCLIで共有する管理操作
+
+// サービスの作成時に作る、全権限を持つロールの名前。このロールは変更・削除できない
+pub const ADMIN_ROLE: &str = "Admin";
+
+const ALL_PERMISSIONS: [Permission; 5] = [
+    Permission::Post,
+    Permission::Get,
+    Permission::Put,
+    Permission::Patch,
+    Permission::Delete,
+];
+
+async fn insert_role<C: ConnectionTrait>(
+    db: &C,
+    service_id: &str,
+    name: &str,
+    permissions: &HashSet<Permission>,
+    is_admin: bool,
+) -> Result<roles::Model, DbErr> {
+    let role = roles::ActiveModel {
+        id: Default::default(),
+        name: Set(name.to_string()),
+        service_id: Set(service_id.to_string()),
+        is_admin: Set(is_admin),
+    }
+    .insert(db)
+    .await?;
+
+    for permission in permissions {
+        role_permissions::ActiveModel {
+            role_id: Set(role.id),
+            permission: Set(permission.to_string()),
+        }
+        .insert(db)
+        .await?;
+    }
+
+    Ok(role)
+}
+
+// サービスと、全権限を持つAdminロールを作成し、AdminロールのAPIキーを返す。
+// ownerを指定するとそのメンバーをオーナーとして登録する
+pub async fn create_service(
+    db: &DatabaseConnection,
+    name: String,
+    owner: Option<&MemberIdentity>,
+) -> Result<(services::Model, String), DbErr> {
+    let txn = db.begin().await?;
+
+    let service = services::ActiveModel {
+        id: Set(generate_key(16)),
+        name: Set(name),
+        deleted_at: Set(None),
+        plan: Set(None),
+    }
+    .insert(&txn)
+    .await?;
+
+    let role = insert_role(
+        &txn,
+        &service.id,
+        ADMIN_ROLE,
+        &ALL_PERMISSIONS.iter().cloned().collect(),
+        true,
+    )
+    .await?;
+    let api_key = issue_key(&txn, role.id, None, None).await?;
+
+    if let Some(owner) = owner {
+        add_member(&txn, &service.id, owner, MemberRole::Owner).await?;
+    }
+
+    txn.commit().await?;
+    Ok((service, api_key.key))
+}
+
+// サービスを削除済みにする。データは猶予期間が過ぎてパージされるまで残る
+pub async fn delete_service(
+    db: &DatabaseConnection,
+    service_id: &str,
+) -> Result<Option<DateTimeWithTimeZone>, DbErr> {
+    let deleted_at: DateTimeWithTimeZone = chrono::Utc::now().into();
+    let result = Services::update_many()
+        .col_expr(services::Column::DeletedAt, Expr::value(deleted_at))
+        .filter(services::Column::Id.eq(service_id))
+        .filter(services::Column::DeletedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok((result.rows_affected > 0).then_some(deleted_at))
+}
+
+pub async fn restore_service(db: &DatabaseConnection, service_id: &str) -> Result<bool, DbErr> {
+    let result = Services::update_many()
+        .col_expr(
+            services::Column::DeletedAt,
+            Expr::value(Option::<DateTimeWithTimeZone>::None),
+        )
+        .filter(services::Column::Id.eq(service_id))
+        .filter(services::Column::DeletedAt.is_not_null())
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+// プランを変える。Noneなら設定の既定のプランに戻す
+pub async fn set_service_plan(
+    db: &DatabaseConnection,
+    service_id: &str,
+    plan: Option<String>,
+) -> Result<bool, DbErr> {
+    let result = Services::update_many()
+        .col_expr(services::Column::Plan, Expr::value(plan))
+        .filter(services::Column::Id.eq(service_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+pub async fn purge_service(db: &DatabaseConnection, service_id: &str) -> Result<bool, DbErr> {
+    purge_service_deleted_before(db, service_id, None).await
+}
+
+// サービスとそのデータを完全に削除する。外部キーにcascadeのないテーブルがあるので、
+// 参照する側から順に削除する。deleted_beforeを指定したときは、行をロックしてから
+// まだその日時より前に削除されたままか確かめるので、直前に復元されたサービスは消さない
+async fn purge_service_deleted_before(
+    db: &DatabaseConnection,
+    service_id: &str,
+    deleted_before: Option<DateTimeWithTimeZone>,
+) -> Result<bool, DbErr> {
+    let txn = db.begin().await?;
+    let mut service = Services::find_by_id(service_id.to_string()).lock_exclusive();
+    if let Some(deleted_before) = deleted_before {
+        service = service.filter(services::Column::DeletedAt.lt(deleted_before));
+    }
+    if service.one(&txn).await?.is_none() {
+        return Ok(false);
+    }
+
+    let content_type_ids: Vec<i32> = ContentTypes::find()
+        .filter(content_types::Column::ServiceId.eq(service_id))
+        .all(&txn)
+        .await?
+        .iter()
+        .map(|content_type| content_type.id)
+        .collect();
+    ContentItems::delete_many()
+        .filter(content_items::Column::ContentTypeId.is_in(content_type_ids.clone()))
+        .exec(&txn)
+        .await?;
+    Fields::delete_many()
+        .filter(fields::Column::ContentTypeId.is_in(content_type_ids))
+        .exec(&txn)
+        .await?;
+    ContentTypes::delete_many()
+        .filter(content_types::Column::ServiceId.eq(service_id))
+        .exec(&txn)
+        .await?;
+
+    let role_ids: Vec<i32> = Roles::find()
+        .filter(roles::Column::ServiceId.eq(service_id))
+        .all(&txn)
+        .await?
+        .iter()
+        .map(|role| role.id)
+        .collect();
+    ApiKeys::delete_many()
+        .filter(api_keys::Column::RoleId.is_in(role_ids.clone()))
+        .exec(&txn)
+        .await?;
+    RolePermissions::delete_many()
+        .filter(role_permissions::Column::RoleId.is_in(role_ids))
+        .exec(&txn)
+        .await?;
+    Roles::delete_many()
+        .filter(roles::Column::ServiceId.eq(service_id))
+        .exec(&txn)
+        .await?;
+
+    ServiceInvitations::delete_many()
+        .filter(service_invitations::Column::ServiceId.eq(service_id))
+        .exec(&txn)
+        .await?;
+    ServiceMembers::delete_many()
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .exec(&txn)
+        .await?;
+    let result = Services::delete_by_id(service_id.to_string())
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+    Ok(result.rows_affected > 0)
+}
+
+// deleted_beforeより前に削除されたサービスをパージし、パージしたサービスのIDを返す
+pub async fn purge_deleted_services(
+    db: &DatabaseConnection,
+    deleted_before: DateTimeWithTimeZone,
+) -> Result<Vec<String>, DbErr> {
+    let services = Services::find()
+        .filter(services::Column::DeletedAt.lt(deleted_before))
+        .order_by_asc(services::Column::DeletedAt)
+        .all(db)
+        .await?;
+    let mut purged = Vec::new();
+    for service in services {
+        if purge_service_deleted_before(db, &service.id, Some(deleted_before)).await? {
+            purged.push(service.id);
+        }
+    }
+    Ok(purged)
+}
+
+// サービスのAdminロールの有効なキーをすべて失効させ、新しいキーを発行する
+pub async fn rotate_service_key(
+    db: &DatabaseConnection,
+    service_id: &str,
+) -> Result<Option<String>, DbErr> {
+    let Some(role) = Roles::find()
+        .filter(roles::Column::ServiceId.eq(service_id))
+        .filter(roles::Column::IsAdmin.eq(true))
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    Ok(replace_role_keys(db, role.id)
+        .await?
+        .map(|issued| issued.key))
+}
+
+pub async fn create_role(
+    db: &DatabaseConnection,
+    service_id: &str,
+    name: &str,
+    permissions: &HashSet<Permission>,
+) -> Result<(roles::Model, String), DbErr> {
+    let txn = db.begin().await?;
+    let role = insert_role(&txn, service_id, name, permissions, false).await?;
+    let api_key = issue_key(&txn, role.id, None, None).await?;
+    txn.commit().await?;
+    Ok((role, api_key.key))
+}
+
+// 同じ名前のロールがあっても、サービスの作成時に作ったものだけを保護する
+pub fn is_admin_role(role: &roles::Model) -> bool {
+    role.is_admin
+}
+
+fn permission_set(permissions: &[role_permissions::Model]) -> HashSet<Permission> {
+    permissions
+        .iter()
+        .filter_map(|p| Permission::from_str(&p.permission).ok())
+        .collect()
+}
+
+pub async fn find_role(
+    db: &DatabaseConnection,
+    service_id: &str,
+    role_id: i32,
+) -> Result<Option<(roles::Model, HashSet<Permission>)>, DbErr> {
+    let role = Roles::find_by_id(role_id)
+        .filter(roles::Column::ServiceId.eq(service_id))
+        .find_with_related(RolePermissions)
+        .all(db)
+        .await?;
+    Ok(role
+        .into_iter()
+        .next()
+        .map(|(role, permissions)| (role, permission_set(&permissions))))
+}
+
+// 名前と権限を変更する。権限は渡された集合で置き換える
+pub async fn update_role(
+    db: &DatabaseConnection,
+    role_id: i32,
+    name: Option<String>,
+    permissions: Option<&HashSet<Permission>>,
+) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    if let Some(name) = name {
+        Roles::update_many()
+            .col_expr(roles::Column::Name, name.into())
+            .filter(roles::Column::Id.eq(role_id))
+            .exec(&txn)
+            .await?;
+    }
+    if let Some(permissions) = permissions {
+        RolePermissions::delete_many()
+            .filter(role_permissions::Column::RoleId.eq(role_id))
+            .exec(&txn)
+            .await?;
+        for permission in permissions {
+            role_permissions::ActiveModel {
+                role_id: Set(role_id),
+                permission: Set(permission.to_string()),
+            }
+            .insert(&txn)
+            .await?;
+        }
+    }
+    txn.commit().await
+}
+
+pub async fn list_roles(
+    db: &DatabaseConnection,
+    service_id: &str,
+) -> Result<Vec<(roles::Model, HashSet<Permission>)>, DbErr> {
+    let roles = Roles::find()
+        .filter(roles::Column::ServiceId.eq(service_id))
+        .order_by_asc(roles::Column::Id)
+        .find_with_related(RolePermissions)
+        .all(db)
+        .await?;
+
+    Ok(roles
+        .into_iter()
+        .map(|(role, permissions)| (role, permission_set(&permissions)))
+        .collect())
+}
+
+// ロールの有効なキーをすべて失効させ、新しいキーを発行する
+pub async fn rotate_role_key(
+    db: &DatabaseConnection,
+    role_id: i32,
+) -> Result<Option<String>, DbErr> {
+    Ok(replace_role_keys(db, role_id)
+        .await?
+        .map(|issued| issued.key))
+}
+
+pub async fn delete_role(db: &DatabaseConnection, role_id: i32) -> Result<bool, DbErr> {
+    let txn = db.begin().await?;
+    RolePermissions::delete_many()
+        .filter(role_permissions::Column::RoleId.eq(role_id))
+        .exec(&txn)
+        .await?;
+    let result = Roles::delete_by_id(role_id).exec(&txn).await?;
+    txn.commit().await?;
+    Ok(result.rows_affected > 0)
+}
+
+// ローカルアカウントをメンバーとして登録するための識別子
+pub async fn local_member_identity(
+    db: &DatabaseConnection,
+    username: &str,
+) -> Result<Option<MemberIdentity>, DbErr> {
+    let user = Users::find()
+        .filter(users::Column::Username.eq(username))
+        .one(db)
+        .await?;
+    Ok(user.map(|user| MemberIdentity {
+        provider: "local".to_string(),
+        subject: user.id.to_string(),
+        email: Some(user.email),
+    }))
+}
+
+pub async fn list_members(
+    db: &DatabaseConnection,
+    service_id: &str,
+) -> Result<Vec<service_members::Model>, DbErr> {
+    ServiceMembers::find()
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .order_by_asc(service_members::Column::Id)
+        .all(db)
+        .await
+}
+
+pub async fn remove_member(
+    db: &DatabaseConnection,
+    service_id: &str,
+    member_id: i32,
+) -> Result<bool, DbErr> {
+    let result = ServiceMembers::delete_many()
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .filter(service_members::Column::Id.eq(member_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+// 認証アプリとリカバリーコードを両方失った利用者のために、管理者が二段階認証を解除する
+pub async fn reset_two_factor(db: &DatabaseConnection, username: &str) -> Result<bool, DbErr> {
+    let txn = db.begin().await?;
+    let Some(user) = Users::find()
+        .filter(users::Column::Username.eq(username))
+        .one(&txn)
+        .await?
+    else {
+        return Ok(false);
+    };
+    let mut user = user.into_active_model();
+    user.totp_secret = Set(None);
+    user.totp_enabled_at = Set(None);
+    user.totp_last_step = Set(None);
+    user.totp_failures = Set(0);
+    user.totp_failed_at = Set(None);
+    let user = user.update(&txn).await?;
+    RecoveryCodes::delete_many()
+        .filter(recovery_codes::Column::UserId.eq(user.id))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    Ok(true)
+}
+
+pub async fn create_content_type(
+    db: &DatabaseConnection,
+    service_id: &str,
+    name: String,
+) -> Result<content_types::Model, DbErr> {
+    content_types::ActiveModel {
+        id: Default::default(),
+        name: Set(name),
+        created_at: Default::default(),
+        updated_at: Default::default(),
+        service_id: Set(Some(service_id.to_string())),
+    }
+    .insert(db)
+    .await
+}
+
+pub async fn create_field(
+    db: &DatabaseConnection,
+    content_type_id: i32,
+    new_field: NewField,
+) -> Result<fields::Model, DbErr> {
+    fields::ActiveModel {
+        id: Default::default(),
+        content_type_id: Set(content_type_id),
+        display_id: Set(new_field.display_name),
+        field_type: Set(new_field.field_type.to_string()),
+        required: Set(new_field.required),
+        created_at: Default::default(),
+        updated_at: Default::default(),
+    }
+    .insert(db)
+    .await
+}
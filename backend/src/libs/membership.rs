@@ -0,0 +1,168 @@
+use crate::libs::auth::Principal;
+use crate::models::prelude::{ServiceMembers, Services};
+use crate::models::{service_members, services};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// サービスのメンバーのロール。後に宣言したものほど強く、上位のロールは下位のロールの操作をすべて行える
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberRole {
+    // メンバーの一覧を見られる
+    Viewer,
+    // コンテンツを編集できる
+    Editor,
+    // ロール・招待・オーナー以外のメンバーを管理できる
+    Admin,
+    // サービスの削除とオーナーの管理ができる
+    Owner,
+}
+
+impl fmt::Display for MemberRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemberRole::Viewer => write!(f, "viewer"),
+            MemberRole::Editor => write!(f, "editor"),
+            MemberRole::Admin => write!(f, "admin"),
+            MemberRole::Owner => write!(f, "owner"),
+        }
+    }
+}
+
+impl FromStr for MemberRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(MemberRole::Viewer),
+            "editor" => Ok(MemberRole::Editor),
+            "admin" => Ok(MemberRole::Admin),
+            "owner" => Ok(MemberRole::Owner),
+            _ => Err(format!(
+                "invalid role {}: expected owner, admin, editor or viewer",
+                s
+            )),
+        }
+    }
+}
+
+impl MemberRole {
+    // DBのCHECK制約で値は限られている
+    pub fn of(member: &service_members::Model) -> MemberRole {
+        member.role.parse().unwrap_or(MemberRole::Viewer)
+    }
+}
+
+// メンバーとして登録する利用者。認証プロバイダとその中のsubjectで識別する
+#[derive(Clone, Debug)]
+pub struct MemberIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+impl From<&Principal> for MemberIdentity {
+    fn from(principal: &Principal) -> Self {
+        MemberIdentity {
+            provider: principal.provider.to_string(),
+            subject: principal.subject.clone(),
+            email: principal.email.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MembershipError {
+    // メンバーでなければサービスの存在も明かさない
+    NotFound,
+    Forbidden(MemberRole),
+    Db(DbErr),
+}
+
+impl From<DbErr> for MembershipError {
+    fn from(e: DbErr) -> Self {
+        MembershipError::Db(e)
+    }
+}
+
+impl IntoResponse for MembershipError {
+    fn into_response(self) -> Response {
+        match self {
+            MembershipError::NotFound => {
+                (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response()
+            }
+            MembershipError::Forbidden(required) => (
+                StatusCode::FORBIDDEN,
+                format!("This requires the {} role on the service", required),
+            )
+                .into_response(),
+            MembershipError::Db(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to check the membership: {}", e),
+            )
+                .into_response(),
+        }
+    }
+}
+
+pub async fn find_member<C: ConnectionTrait>(
+    db: &C,
+    principal: &Principal,
+    service_id: &str,
+) -> Result<Option<service_members::Model>, DbErr> {
+    ServiceMembers::find()
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .filter(service_members::Column::Provider.eq(principal.provider))
+        .filter(service_members::Column::Subject.eq(principal.subject.as_str()))
+        .one(db)
+        .await
+}
+
+// 利用者がrequired以上のロールを持つメンバーであれば、そのメンバーの行を返す。
+// 削除済みのサービスは存在しないものとして扱う
+pub async fn require_role<C: ConnectionTrait>(
+    db: &C,
+    principal: &Principal,
+    service_id: &str,
+    required: MemberRole,
+) -> Result<service_members::Model, MembershipError> {
+    let member = ServiceMembers::find()
+        .inner_join(Services)
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .filter(service_members::Column::Provider.eq(principal.provider))
+        .filter(service_members::Column::Subject.eq(principal.subject.as_str()))
+        .filter(services::Column::DeletedAt.is_null())
+        .one(db)
+        .await?;
+    let Some(member) = member else {
+        return Err(MembershipError::NotFound);
+    };
+    if MemberRole::of(&member) < required {
+        return Err(MembershipError::Forbidden(required));
+    }
+    Ok(member)
+}
+
+pub async fn add_member<C: ConnectionTrait>(
+    db: &C,
+    service_id: &str,
+    identity: &MemberIdentity,
+    role: MemberRole,
+) -> Result<service_members::Model, DbErr> {
+    service_members::ActiveModel {
+        id: Default::default(),
+        service_id: Set(service_id.to_string()),
+        provider: Set(identity.provider.clone()),
+        subject: Set(identity.subject.clone()),
+        email: Set(identity.email.clone()),
+        role: Set(role.to_string()),
+        created_at: Default::default(),
+    }
+    .insert(db)
+    .await
+}
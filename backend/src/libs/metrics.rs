@@ -0,0 +1,307 @@
+use crate::libs::auth::JwksStatus;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 応答時間のヒストグラムの境界(秒)
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// ルートのパターンが分からないリクエストはパスごとに分けず、この値でまとめる
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct RequestKey {
+    method: String,
+    // 実際のパスではなくルートのパターン。IDごとに系列が増えないようにする
+    route: String,
+    status: u16,
+}
+
+#[derive(Default)]
+struct Histogram {
+    // LATENCY_BUCKETSのそれぞれ以下に収まった数(累積ではない)
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct JobStatus {
+    last_run: Option<SystemTime>,
+    last_success: Option<SystemTime>,
+    last_duration: Duration,
+    runs: u64,
+    failures: u64,
+}
+
+// データベースの接続プールの状態。書き出すときに呼び出し側が渡す
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+}
+
+// 書き出すときに集める、その時点の値
+pub struct Snapshot {
+    pub pool: PoolStatus,
+    // OIDCを使っていない場合はNone
+    pub jwks: Option<JwksStatus>,
+    // 送信待ちのメールの数。mailセクションが設定されていない場合はNone。
+    // Webhookの送信はまだないのでキューとしてはこれだけを出す。追加したらcms_webhook_queue_depthも出す
+    pub mail_queue_depth: Option<usize>,
+}
+
+// /metricsで公開する値。プロセスごとに持つので、複数台で動かす場合は台ごとに集める
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<HashMap<RequestKey, Histogram>>,
+    jobs: Mutex<BTreeMap<&'static str, JobStatus>>,
+}
+
+impl Metrics {
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let key = RequestKey {
+            method: method.to_string(),
+            route: route.to_string(),
+            status,
+        };
+        self.requests
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    // バックグラウンドの定期実行が一回終わったときに呼ぶ
+    pub fn job_finished(&self, job: &'static str, started: Instant, succeeded: bool) {
+        let now = SystemTime::now();
+        let mut jobs = self.jobs.lock().unwrap();
+        let status = jobs.entry(job).or_default();
+        status.last_run = Some(now);
+        status.last_duration = started.elapsed();
+        status.runs += 1;
+        if succeeded {
+            status.last_success = Some(now);
+        } else {
+            status.failures += 1;
+        }
+    }
+
+    // Prometheusのテキスト形式で書き出す
+    pub fn render(&self, snapshot: &Snapshot) -> String {
+        let mut out = String::new();
+        self.render_requests(&mut out);
+        render_pool(&mut out, &snapshot.pool);
+        if let Some(jwks) = &snapshot.jwks {
+            render_jwks(&mut out, jwks);
+        }
+        if let Some(depth) = snapshot.mail_queue_depth {
+            header(
+                &mut out,
+                "cms_mail_queue_depth",
+                "gauge",
+                "Emails waiting to be delivered.",
+            );
+            let _ = writeln!(out, "cms_mail_queue_depth {}", depth);
+        }
+        self.render_jobs(&mut out);
+        out
+    }
+
+    fn render_requests(&self, out: &mut String) {
+        let name = "cms_http_request_duration_seconds";
+        header(
+            out,
+            name,
+            "histogram",
+            "Time taken to respond to API requests.",
+        );
+        let requests = self.requests.lock().unwrap();
+        let mut keys: Vec<_> = requests.keys().collect();
+        keys.sort();
+        for key in keys {
+            let histogram = &requests[key];
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{}\"",
+                escape(&key.method),
+                escape(&key.route),
+                key.status
+            );
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name, labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                name, labels, histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+    }
+
+    fn render_jobs(&self, out: &mut String) {
+        let jobs = self.jobs.lock().unwrap();
+        header(
+            out,
+            "cms_job_runs_total",
+            "counter",
+            "Completed runs of a background job.",
+        );
+        for (job, status) in jobs.iter() {
+            let _ = writeln!(out, "cms_job_runs_total{{job=\"{}\"}} {}", job, status.runs);
+        }
+        header(
+            out,
+            "cms_job_failures_total",
+            "counter",
+            "Runs of a background job that failed.",
+        );
+        for (job, status) in jobs.iter() {
+            let _ = writeln!(
+                out,
+                "cms_job_failures_total{{job=\"{}\"}} {}",
+                job, status.failures
+            );
+        }
+        header(
+            out,
+            "cms_job_last_run_timestamp_seconds",
+            "gauge",
+            "When a background job last finished.",
+        );
+        for (job, status) in jobs.iter() {
+            if let Some(last_run) = status.last_run {
+                let _ = writeln!(
+                    out,
+                    "cms_job_last_run_timestamp_seconds{{job=\"{}\"}} {}",
+                    job,
+                    unix_seconds(last_run)
+                );
+            }
+        }
+        header(
+            out,
+            "cms_job_last_success_timestamp_seconds",
+            "gauge",
+            "When a background job last finished without errors.",
+        );
+        for (job, status) in jobs.iter() {
+            if let Some(last_success) = status.last_success {
+                let _ = writeln!(
+                    out,
+                    "cms_job_last_success_timestamp_seconds{{job=\"{}\"}} {}",
+                    job,
+                    unix_seconds(last_success)
+                );
+            }
+        }
+        header(
+            out,
+            "cms_job_last_duration_seconds",
+            "gauge",
+            "How long the last run of a background job took.",
+        );
+        for (job, status) in jobs.iter() {
+            let _ = writeln!(
+                out,
+                "cms_job_last_duration_seconds{{job=\"{}\"}} {}",
+                job,
+                status.last_duration.as_secs_f64()
+            );
+        }
+    }
+}
+
+fn render_pool(out: &mut String, pool: &PoolStatus) {
+    header(
+        out,
+        "cms_db_pool_connections",
+        "gauge",
+        "Open database connections by state.",
+    );
+    let idle = pool.idle as u64;
+    let in_use = u64::from(pool.size).saturating_sub(idle);
+    let _ = writeln!(out, "cms_db_pool_connections{{state=\"idle\"}} {}", idle);
+    let _ = writeln!(
+        out,
+        "cms_db_pool_connections{{state=\"in_use\"}} {}",
+        in_use
+    );
+    header(
+        out,
+        "cms_db_pool_max_connections",
+        "gauge",
+        "Maximum number of database connections in the pool.",
+    );
+    let _ = writeln!(out, "cms_db_pool_max_connections {}", pool.max_connections);
+}
+
+fn render_jwks(out: &mut String, jwks: &JwksStatus) {
+    header(
+        out,
+        "cms_jwks_keys_loaded",
+        "gauge",
+        "Whether signing keys from the identity provider are available (1) or not (0).",
+    );
+    let _ = writeln!(out, "cms_jwks_keys_loaded {}", u8::from(jwks.keys_loaded));
+    header(
+        out,
+        "cms_jwks_refresh_failures_total",
+        "counter",
+        "Failed attempts to fetch the JWKS.",
+    );
+    let _ = writeln!(out, "cms_jwks_refresh_failures_total {}", jwks.failures);
+    if let Some(last_success) = jwks.last_success {
+        header(
+            out,
+            "cms_jwks_last_refresh_timestamp_seconds",
+            "gauge",
+            "When the JWKS was last fetched successfully.",
+        );
+        let _ = writeln!(
+            out,
+            "cms_jwks_last_refresh_timestamp_seconds {}",
+            unix_seconds(last_success)
+        );
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// ラベルの値に使えない文字をエスケープする
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
@@ -1 +1,20 @@
+pub mod api_keys;
+pub mod audit;
+pub mod auth;
+pub mod content_csv;
+pub mod content_model;
 pub mod generate_random_key;
+pub mod json_schema;
+pub mod mailer;
+pub mod management;
+pub mod membership;
+pub mod metrics;
+pub mod openapi;
+pub mod rate_limit;
+pub mod retention;
+pub mod schema_version;
+pub mod service_archive;
+pub mod token_hash;
+pub mod trash;
+pub mod typescript;
+pub mod usage;
@@ -0,0 +1,1764 @@
+use crate::libs::content_model::{data_schema, type_name, ContentModel};
+use crate::models::services;
+use crate::router_comp::auth_router::SESSION_COOKIE;
+use serde_json::{json, Map, Value};
+
+const OPENAPI_VERSION: &str = "3.1.0";
+
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } }
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn content_item_schema(data: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": ["string", "null"], "format": "uuid" },
+            "data": data
+        },
+        "required": ["data"]
+    })
+}
+
+// content_router.rsのルートをサービスのコンテンツモデルに合わせて記述する
+pub fn service_document(service: &services::Model, content_model: &ContentModel) -> Value {
+    let mut schemas = Map::new();
+    let mut paths = Map::new();
+    let mut data_refs = Vec::new();
+
+    schemas.insert(
+        "ContentType".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "integer" },
+                "name": { "type": "string" },
+                "fields": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "display_id": { "type": "string" },
+                            "field_type": { "$ref": "#/components/schemas/FieldType" },
+                            "required": { "type": "boolean" }
+                        }
+                    }
+                }
+            },
+            "required": ["id", "name", "fields"]
+        }),
+    );
+    schemas.insert(
+        "FieldType".to_string(),
+        json!({ "type": "string", "enum": ["Text", "Number", "Date", "Boolean"] }),
+    );
+
+    for (content_type, fields) in content_model {
+        let name = type_name(content_type);
+        let item_name = format!("{}Item", name);
+        schemas.insert(name.clone(), data_schema(fields));
+        schemas.insert(item_name.clone(), content_item_schema(schema_ref(&name)));
+        data_refs.push(schema_ref(&name));
+
+        paths.insert(
+            format!("/{}/content_items", content_type.id),
+            json!({
+                "get": {
+                    "summary": format!("List {} items", content_type.name),
+                    "operationId": format!("list{}", name),
+                    "responses": {
+                        "200": {
+                            "description": "Content items",
+                            "content": { "application/json": { "schema": {
+                                "type": "array",
+                                "items": schema_ref(&item_name)
+                            } } }
+                        },
+                        "404": text_response("No content items")
+                    }
+                },
+                "post": {
+                    "summary": format!("Create a {} item", content_type.name),
+                    "operationId": format!("create{}", name),
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": { "data": schema_ref(&name) },
+                            "required": ["data"]
+                        } } }
+                    },
+                    "responses": {
+                        "201": text_response("Content item created"),
+                        "400": text_response("Data does not match the content type")
+                    }
+                }
+            }),
+        );
+        paths.insert(
+            format!("/{}/content_items.csv", content_type.id),
+            json!({
+                "get": {
+                    "summary": format!("Export {} items as CSV", content_type.name),
+                    "operationId": format!("export{}Csv", name),
+                    "responses": {
+                        "200": {
+                            "description": "An id column followed by one column per field. Importing the file again updates the same items.",
+                            "content": { "text/csv": { "schema": { "type": "string" } } }
+                        }
+                    }
+                }
+            }),
+        );
+        paths.insert(
+            format!("/{}/content_items/import", content_type.id),
+            json!({
+                "post": {
+                    "summary": format!("Import {} items from CSV", content_type.name),
+                    "operationId": format!("import{}Csv", name),
+                    "description": "Columns are matched to fields by name. Query parameters of the form `map.<header>=<field>` map other column names onto fields; other query parameters are ignored. A row whose id column matches an existing item of this content type replaces that item's data; other rows create new items.",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "text/csv": { "schema": { "type": "string" } } }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Every row was imported",
+                            "content": { "application/json": { "schema": schema_ref("CsvImportReport") } }
+                        },
+                        "200": {
+                            "description": "Valid rows were imported, the others are listed in errors",
+                            "content": { "application/json": { "schema": schema_ref("CsvImportReport") } }
+                        },
+                        "400": text_response("The CSV or the column mapping is invalid")
+                    }
+                }
+            }),
+        );
+        paths.insert(
+            format!("/{}/fields", content_type.id),
+            json!({
+                "post": {
+                    "summary": format!("Add a field to {}", content_type.name),
+                    "operationId": format!("createFieldOf{}", name),
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": {
+                                "display_name": { "type": "string" },
+                                "field_type": schema_ref("FieldType"),
+                                "required": { "type": "boolean" }
+                            },
+                            "required": ["display_name", "field_type", "required"]
+                        } } }
+                    },
+                    "responses": { "201": text_response("Field created") }
+                }
+            }),
+        );
+    }
+
+    schemas.insert(
+        "CsvImportReport".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "imported": { "type": "integer", "description": "Rows created or updated" },
+                "updated": { "type": "integer", "description": "Rows that replaced an existing item" },
+                "ignored_columns": { "type": "array", "items": { "type": "string" } },
+                "errors": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "row": { "type": "integer" },
+                            "errors": { "type": "array", "items": { "type": "string" } }
+                        }
+                    }
+                }
+            }
+        }),
+    );
+
+    // コンテンツアイテムIDだけでは型が決まらないので、いずれかの型として記述する
+    let any_data = if data_refs.is_empty() {
+        json!({ "type": "object" })
+    } else {
+        json!({ "oneOf": data_refs })
+    };
+    schemas.insert(
+        "TrashedItem".to_string(),
+        json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "content_type_id": { "type": "integer" },
+                "data": any_data.clone(),
+                "deleted_at": { "type": "string", "format": "date-time" },
+                "deleted_by_key_id": { "type": ["integer", "null"] }
+            },
+            "required": ["id", "content_type_id", "data", "deleted_at"]
+        }),
+    );
+    schemas.insert("ContentItem".to_string(), content_item_schema(any_data));
+
+    paths.insert(
+        "/content_types".to_string(),
+        json!({
+            "post": {
+                "summary": "Create a content type",
+                "operationId": "createContentType",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": { "name": { "type": "string" } },
+                        "required": ["name"]
+                    } } }
+                },
+                "responses": { "201": text_response("Content type created") }
+            }
+        }),
+    );
+    paths.insert(
+        "/content_types/import".to_string(),
+        json!({
+            "post": {
+                "summary": "Create a content type from a JSON Schema",
+                "operationId": "importContentType",
+                "description": "The title becomes the content type name and each property a field. Only string (optionally with format date-time), number, integer and boolean properties are supported.",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": { "type": "object" } } }
+                },
+                "responses": {
+                    "201": text_response("Content type created"),
+                    "400": text_response("The schema uses keywords or types that cannot be imported")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/content_types/{content_type_id}/schema".to_string(),
+        json!({
+            "get": {
+                "summary": "Get a content type as a JSON Schema",
+                "operationId": "getContentTypeSchema",
+                "parameters": [{
+                    "name": "content_type_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer" }
+                }],
+                "responses": {
+                    "200": {
+                        "description": "A JSON Schema (draft 2020-12) describing the item data",
+                        "content": { "application/json": { "schema": { "type": "object" } } }
+                    },
+                    "404": text_response("Content type not found")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/content_types/{content_type_id}".to_string(),
+        json!({
+            "get": {
+                "summary": "Get a content type with its fields",
+                "operationId": "getContentType",
+                "parameters": [{
+                    "name": "content_type_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer" }
+                }],
+                "responses": {
+                    "200": {
+                        "description": "Content type",
+                        "content": { "application/json": { "schema": schema_ref("ContentType") } }
+                    },
+                    "404": text_response("Content type not found")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/content_items/{content_item_id}".to_string(),
+        json!({
+            "parameters": [{
+                "name": "content_item_id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string", "format": "uuid" }
+            }],
+            "get": {
+                "summary": "Get a content item",
+                "operationId": "getContentItem",
+                "responses": {
+                    "200": {
+                        "description": "Content item",
+                        "content": { "application/json": { "schema": schema_ref("ContentItem") } }
+                    },
+                    "404": { "description": "Content item not found" }
+                }
+            },
+            "patch": {
+                "summary": "Update a content item",
+                "operationId": "updateContentItem",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": schema_ref("ContentItem") } }
+                },
+                "responses": {
+                    "200": text_response("Content item updated"),
+                    "400": text_response("Data does not match the content type")
+                }
+            },
+            "delete": {
+                "summary": "Move a content item to the trash",
+                "operationId": "deleteContentItem",
+                "responses": {
+                    "200": text_response("Content item moved to the trash"),
+                    "404": text_response("Content item not found")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/trash".to_string(),
+        json!({
+            "get": {
+                "summary": "List deleted content items that can still be restored",
+                "operationId": "listTrash",
+                "responses": {
+                    "200": {
+                        "description": "Deleted content items, most recently deleted first",
+                        "content": { "application/json": { "schema": {
+                            "type": "array",
+                            "items": schema_ref("TrashedItem")
+                        } } }
+                    }
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/trash/{content_item_id}".to_string(),
+        json!({
+            "parameters": [{
+                "name": "content_item_id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string", "format": "uuid" }
+            }],
+            "delete": {
+                "summary": "Permanently delete a content item in the trash",
+                "operationId": "deleteTrashedItem",
+                "responses": {
+                    "200": text_response("Content item permanently deleted"),
+                    "404": text_response("Content item not found in the trash")
+                }
+            }
+        }),
+    );
+    paths.insert(
+        "/trash/{content_item_id}/restore".to_string(),
+        json!({
+            "parameters": [{
+                "name": "content_item_id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string", "format": "uuid" }
+            }],
+            "post": {
+                "summary": "Restore a content item from the trash",
+                "operationId": "restoreTrashedItem",
+                "responses": {
+                    "200": text_response("Content item restored"),
+                    "404": text_response("Content item not found in the trash")
+                }
+            }
+        }),
+    );
+
+    // どの呼び出しもキーのレート制限とサービスの月間上限を受ける
+    for operation in paths
+        .values_mut()
+        .filter_map(Value::as_object_mut)
+        .flat_map(|path| path.values_mut())
+    {
+        if let Some(responses) = operation
+            .get_mut("responses")
+            .and_then(Value::as_object_mut)
+        {
+            responses.insert(
+                "429".to_string(),
+                text_response(
+                    "Rate limit or monthly quota exceeded; retry after the Retry-After header",
+                ),
+            );
+        }
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": format!("{} content API", service.name),
+            "description": "Responses carry X-RateLimit-Limit, X-RateLimit-Remaining and X-RateLimit-Reset for the API key, and X-Quota-Limit and X-Quota-Remaining when the service's plan has a monthly quota.",
+            "version": "1.0.0"
+        },
+        "servers": [{ "url": format!("/api/services/{}", service.id) }],
+        "security": [{ "ApiKey": [] }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "ApiKey": { "type": "apiKey", "in": "header", "name": "x-api-key" }
+            }
+        }
+    })
+}
+
+// 管理APIは固定なので、サービスに依存しないドキュメントを返す
+// サービスのメンバーと招待の管理
+fn member_paths() -> Value {
+    json!({
+    "/service/{service_id}/members": {
+        "get": {
+            "summary": "List the members of a service",
+            "operationId": "listMembers",
+            "parameters": [{
+                "name": "service_id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" }
+            }],
+            "responses": {
+                "200": {
+                    "description": "Members in the order they joined",
+                    "content": { "application/json": { "schema": {
+                        "type": "array",
+                        "items": { "$ref": "#/components/schemas/Member" }
+                    } } }
+                },
+                "404": text_response("Service not found, or the caller is not a member")
+            }
+        }
+    },
+    "/service/{service_id}/members/{member_id}": {
+        "parameters": [
+            {
+                "name": "service_id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" }
+            },
+            {
+                "name": "member_id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "integer" }
+            }
+        ],
+        "patch": {
+            "summary": "Change a member's role",
+            "description": "Requires admin. Making or unmaking an owner requires owner.",
+            "operationId": "updateMember",
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": {
+                    "type": "object",
+                    "properties": { "role": { "$ref": "#/components/schemas/MemberRole" } },
+                    "required": ["role"]
+                } } }
+            },
+            "responses": {
+                "200": {
+                    "description": "The updated member",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Member" } } }
+                },
+                "403": text_response("The caller's role is not high enough"),
+                "404": text_response("Service or member not found"),
+                "409": text_response("The service would be left without an owner")
+            }
+        },
+        "delete": {
+            "summary": "Remove a member, or leave the service",
+            "description": "Requires admin, or owner to remove an owner. Any member can remove themselves.",
+            "operationId": "removeMember",
+            "responses": {
+                "200": text_response("Member removed"),
+                "403": text_response("The caller's role is not high enough"),
+                "404": text_response("Service or member not found"),
+                "409": text_response("The service would be left without an owner")
+            }
+        }
+    },
+    "/service/{service_id}/invitations": {
+        "parameters": [{
+            "name": "service_id",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" }
+        }],
+        "get": {
+            "summary": "List pending invitations",
+            "operationId": "listInvitations",
+            "responses": {
+                "200": {
+                    "description": "Invitations that are neither accepted nor expired",
+                    "content": { "application/json": { "schema": {
+                        "type": "array",
+                        "items": { "$ref": "#/components/schemas/Invitation" }
+                    } } }
+                },
+                "403": text_response("Requires the admin member role"),
+                "404": text_response("Service not found, or the caller is not a member")
+            }
+        },
+        "post": {
+            "summary": "Invite someone to the service by email",
+            "description": "The link in the email is valid for 7 days. Inviting an owner requires owner.",
+            "operationId": "createInvitation",
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": {
+                    "type": "object",
+                    "properties": {
+                        "email": { "type": "string", "format": "email" },
+                        "role": { "$ref": "#/components/schemas/MemberRole" }
+                    },
+                    "required": ["email", "role"]
+                } } }
+            },
+            "responses": {
+                "201": {
+                    "description": "Invitation created and email queued",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Invitation" } } }
+                },
+                "400": text_response("Invalid email address"),
+                "403": text_response("The caller's role is not high enough"),
+                "404": text_response("Service not found, or the caller is not a member"),
+                "503": text_response("Mail is not configured")
+            }
+        }
+    },
+    "/service/{service_id}/invitations/{invitation_id}": {
+        "delete": {
+            "summary": "Revoke a pending invitation",
+            "operationId": "revokeInvitation",
+            "parameters": [
+                {
+                    "name": "service_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "invitation_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer" }
+                }
+            ],
+            "responses": {
+                "200": text_response("Invitation revoked"),
+                "403": text_response("Requires the admin member role"),
+                "404": text_response("Invitation not found")
+            }
+        }
+    },
+    "/service/invitations/accept": {
+        "post": {
+            "summary": "Accept an invitation with the token from the invitation email",
+            "description": "The caller's email address must be the one the invitation was sent to.",
+            "operationId": "acceptInvitation",
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": {
+                    "type": "object",
+                    "properties": { "token": { "type": "string" } },
+                    "required": ["token"]
+                } } }
+            },
+            "responses": {
+                "201": {
+                    "description": "The caller's new membership",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Member" } } }
+                },
+                "400": text_response("The token is invalid, used or expired"),
+                "403": text_response("Sent to a different email address, or the email address is not verified"),
+                "409": text_response("Already a member of the service")
+            }
+        }
+    }
+    })
+}
+
+// ロールとそのAPIキーの管理
+fn role_paths() -> Value {
+    json!({
+        "/service/{service_id}/roles": {
+            "parameters": [{
+                "name": "service_id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" }
+            }],
+            "get": {
+                "summary": "List the roles of a service with their permissions",
+                "operationId": "listRoles",
+                "responses": {
+                    "200": {
+                        "description": "Roles in the order they were created",
+                        "content": { "application/json": { "schema": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/Role" }
+                        } } }
+                    },
+                    "404": text_response("Service not found, or the caller is not a member")
+                }
+            },
+            "post": {
+                "summary": "Create a role",
+                "operationId": "createRole",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "permissions": {
+                                "type": "array",
+                                "uniqueItems": true,
+                                "items": { "$ref": "#/components/schemas/Permission" }
+                            }
+                        },
+                        "required": ["name", "permissions"]
+                    } } }
+                },
+                "responses": {
+                    "201": text_response("API key of the new role"),
+                    "400": text_response("The name is empty"),
+                    "401": text_response("Missing or invalid credentials"),
+                    "403": text_response("Requires the admin member role"),
+                    "404": text_response("Service not found, or the caller is not a member"),
+                    "409": text_response("The name is Admin")
+                }
+            }
+        },
+        "/service/{service_id}/roles/{role_id}": {
+            "parameters": [
+                {
+                    "name": "service_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "role_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer" }
+                }
+            ],
+            "get": {
+                "summary": "Get a role with its permissions",
+                "operationId": "getRole",
+                "responses": {
+                    "200": {
+                        "description": "The role",
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Role" } } }
+                    },
+                    "404": text_response("Service or role not found")
+                }
+            },
+            "patch": {
+                "summary": "Rename a role or replace its permissions",
+                "operationId": "updateRole",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "permissions": {
+                                "type": "array",
+                                "uniqueItems": true,
+                                "items": { "$ref": "#/components/schemas/Permission" }
+                            }
+                        }
+                    } } }
+                },
+                "responses": {
+                    "200": {
+                        "description": "The updated role",
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Role" } } }
+                    },
+                    "400": text_response("The name is empty"),
+                    "403": text_response("Requires the admin member role"),
+                    "404": text_response("Service or role not found"),
+                    "409": text_response("The role is the Admin role, or the new name is Admin")
+                }
+            },
+            "delete": {
+                "summary": "Delete a role and its API keys",
+                "operationId": "deleteRole",
+                "responses": {
+                    "200": text_response("Role deleted"),
+                    "403": text_response("Requires the admin member role"),
+                    "404": text_response("Service or role not found"),
+                    "409": text_response("The Admin role cannot be deleted")
+                }
+            }
+        },
+        "/service/{service_id}/roles/{role_id}/keys": {
+            "parameters": [
+                {
+                    "name": "service_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "role_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer" }
+                }
+            ],
+            "get": {
+                "summary": "List the API keys of a role",
+                "operationId": "listApiKeys",
+                "responses": {
+                    "200": {
+                        "description": "Keys in the order they were issued, including revoked ones",
+                        "content": { "application/json": { "schema": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/ApiKey" }
+                        } } }
+                    },
+                    "403": text_response("Requires the admin member role"),
+                    "404": text_response("Service or role not found")
+                }
+            },
+            "post": {
+                "summary": "Issue another API key for a role",
+                "operationId": "issueApiKey",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "expires_at": { "type": "string", "format": "date-time" }
+                        }
+                    } } }
+                },
+                "responses": {
+                    "201": {
+                        "description": "The new key; the plaintext key is shown only this once",
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/IssuedApiKey" } } }
+                    },
+                    "400": text_response("expires_at is in the past"),
+                    "403": text_response("Requires the admin member role"),
+                    "404": text_response("Service or role not found")
+                }
+            }
+        },
+        "/service/{service_id}/roles/{role_id}/keys/{key_id}": {
+            "delete": {
+                "summary": "Revoke an API key",
+                "operationId": "revokeApiKey",
+                "parameters": [
+                    {
+                        "name": "service_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    },
+                    {
+                        "name": "role_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer" }
+                    },
+                    {
+                        "name": "key_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer" }
+                    }
+                ],
+                "responses": {
+                    "200": text_response("API key revoked"),
+                    "403": text_response("Requires the admin member role"),
+                    "404": text_response("Service, role or active key not found")
+                }
+            }
+        },
+        "/service/{service_id}/roles/{role_id}/keys/{key_id}/rotate": {
+            "post": {
+                "summary": "Replace an API key with a new one",
+                "description": "The old key keeps working for grace_seconds (default 0) and is then rejected.",
+                "operationId": "rotateApiKey",
+                "parameters": [
+                    {
+                        "name": "service_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    },
+                    {
+                        "name": "role_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer" }
+                    },
+                    {
+                        "name": "key_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer" }
+                    }
+                ],
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "expires_at": { "type": "string", "format": "date-time" },
+                            "grace_seconds": { "type": "integer", "minimum": 0, "default": 0 }
+                        }
+                    } } }
+                },
+                "responses": {
+                    "201": {
+                        "description": "The new key; the plaintext key is shown only this once",
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/IssuedApiKey" } } }
+                    },
+                    "400": text_response("expires_at is in the past"),
+                    "403": text_response("Requires the admin member role"),
+                    "404": text_response("Service, role or active key not found")
+                }
+            }
+        }
+    })
+}
+
+// 監査ログの一覧とエクスポートに共通の絞り込み条件
+fn audit_filter_parameters() -> Vec<Value> {
+    let mut parameters = vec![json!({
+        "name": "service_id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" }
+    })];
+    for (name, description) in [
+        ("actor_type", "user, api_key or anonymous"),
+        (
+            "actor_id",
+            "provider:subject for users, the key ID for API keys",
+        ),
+        ("action", "e.g. content_item.update"),
+        ("target_type", "e.g. content_item"),
+        ("target_id", "ID of the changed resource"),
+        ("request_id", "Value of the X-Request-Id response header"),
+    ] {
+        parameters.push(json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "description": description,
+            "schema": { "type": "string" }
+        }));
+    }
+    for (name, description) in [
+        ("since", "Entries recorded at or after this time"),
+        ("until", "Entries recorded before this time"),
+    ] {
+        parameters.push(json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "description": description,
+            "schema": { "type": "string", "format": "date-time" }
+        }));
+    }
+    parameters
+}
+
+fn audit_paths() -> Value {
+    let filter = audit_filter_parameters();
+    let mut list_parameters = filter.clone();
+    list_parameters.push(json!({
+        "name": "cursor",
+        "in": "query",
+        "required": false,
+        "description": "next_cursor of the previous page",
+        "schema": { "type": "integer" }
+    }));
+    list_parameters.push(json!({
+        "name": "limit",
+        "in": "query",
+        "required": false,
+        "schema": { "type": "integer", "default": 50, "minimum": 1, "maximum": 500 }
+    }));
+    json!({
+    "/service/{service_id}/audit_logs": {
+        "get": {
+            "summary": "List the service's audit log, newest first",
+            "description": "Requires admin.",
+            "operationId": "listAuditLogs",
+            "parameters": list_parameters,
+            "responses": {
+                "200": {
+                    "description": "A page of entries",
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "entries": {
+                                "type": "array",
+                                "items": { "$ref": "#/components/schemas/AuditLog" }
+                            },
+                            "next_cursor": { "type": ["integer", "null"] }
+                        },
+                        "required": ["entries", "next_cursor"]
+                    } } }
+                },
+                "403": text_response("Requires the admin member role"),
+                "404": text_response("Service not found, or the caller is not a member")
+            }
+        }
+    },
+    "/service/{service_id}/audit_logs/export": {
+        "get": {
+            "summary": "Export the service's audit log as NDJSON, oldest first",
+            "description": "Requires admin.",
+            "operationId": "exportAuditLogs",
+            "parameters": filter,
+            "responses": {
+                "200": {
+                    "description": "One AuditLog entry per line",
+                    "content": { "application/x-ndjson": { "schema": { "type": "string" } } }
+                },
+                "403": text_response("Requires the admin member role"),
+                "404": text_response("Service not found, or the caller is not a member")
+            }
+        }
+    }
+    })
+}
+
+fn audit_log_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "integer" },
+            "created_at": { "type": "string", "format": "date-time" },
+            "request_id": { "type": "string" },
+            "actor_type": { "type": "string", "enum": ["user", "api_key", "anonymous"] },
+            "actor_id": { "type": ["string", "null"] },
+            "actor_role_id": { "type": ["integer", "null"] },
+            "service_id": { "type": ["string", "null"] },
+            "action": { "type": "string" },
+            "target_type": { "type": "string" },
+            "target_id": { "type": ["string", "null"] },
+            "before": { "description": "The target before the change" },
+            "after": { "description": "The target after the change" }
+        },
+        "required": ["id", "created_at", "request_id", "actor_type", "action", "target_type"]
+    })
+}
+
+fn usage_paths() -> Value {
+    json!({
+    "/service/{service_id}/usage": {
+        "get": {
+            "summary": "Content API usage of the service per day and content type",
+            "description": "Counts are written in batches, so the latest calls may not be included yet. Requests whose path has no content type are reported with a null content_type_id.",
+            "operationId": "getServiceUsage",
+            "parameters": [
+                {
+                    "name": "service_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "from",
+                    "in": "query",
+                    "required": false,
+                    "description": "First day (UTC), 30 days before to by default",
+                    "schema": { "type": "string", "format": "date" }
+                },
+                {
+                    "name": "to",
+                    "in": "query",
+                    "required": false,
+                    "description": "Last day (UTC), today by default; at most 366 days after from",
+                    "schema": { "type": "string", "format": "date" }
+                }
+            ],
+            "responses": {
+                "200": {
+                    "description": "Totals for the range and one entry per day and content type",
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "from": { "type": "string", "format": "date" },
+                            "to": { "type": "string", "format": "date" },
+                            "total": { "$ref": "#/components/schemas/UsageCounts" },
+                            "days": {
+                                "type": "array",
+                                "items": {
+                                    "allOf": [
+                                        { "$ref": "#/components/schemas/UsageCounts" },
+                                        {
+                                            "type": "object",
+                                            "properties": {
+                                                "day": { "type": "string", "format": "date" },
+                                                "content_type_id": { "type": ["integer", "null"] }
+                                            },
+                                            "required": ["day", "content_type_id"]
+                                        }
+                                    ]
+                                }
+                            }
+                        },
+                        "required": ["from", "to", "total", "days"]
+                    } } }
+                },
+                "400": text_response("The range is invalid"),
+                "404": text_response("Service not found, or the caller is not a member")
+            }
+        }
+    }
+    })
+}
+
+fn usage_counts_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "requests": { "type": "integer" },
+            "client_errors": { "type": "integer", "description": "Responses with a 4xx status" },
+            "server_errors": { "type": "integer", "description": "Responses with a 5xx status" },
+            "bytes_in": { "type": "integer", "description": "Request bodies of known length" },
+            "bytes_out": { "type": "integer", "description": "Response bodies of known length" }
+        },
+        "required": ["requests", "client_errors", "server_errors", "bytes_in", "bytes_out"]
+    })
+}
+
+pub fn management_document() -> Value {
+    let mut document = json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "Headless CMS management API",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "servers": [{ "url": "/api" }],
+        "paths": {
+            "/health": {
+                "get": {
+                    "summary": "Health check",
+                    "operationId": "healthCheck",
+                    "security": [],
+                    "responses": { "200": text_response("OK!") }
+                }
+            },
+            "/service": {
+                "get": {
+                    "summary": "List the services the caller is a member of",
+                    "operationId": "listServices",
+                    "parameters": [{
+                        "name": "deleted",
+                        "in": "query",
+                        "required": false,
+                        "description": "List deleted services that can still be restored instead",
+                        "schema": { "type": "boolean", "default": false }
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "Services with the caller's member role",
+                            "content": { "application/json": { "schema": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "id": { "type": "string" },
+                                        "name": { "type": "string" },
+                                        "role": { "$ref": "#/components/schemas/MemberRole" },
+                                        "deleted_at": { "type": "string", "format": "date-time" }
+                                    },
+                                    "required": ["id", "name", "role"]
+                                }
+                            } } }
+                        },
+                        "401": text_response("Missing or invalid credentials")
+                    }
+                },
+                "post": {
+                    "summary": "Create a service with an Admin role; the caller becomes its owner",
+                    "operationId": "createService",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": { "name": { "type": "string" } },
+                            "required": ["name"]
+                        } } }
+                    },
+                    "responses": {
+                        "201": text_response("Service created with its API key and ID"),
+                        "401": text_response("Missing or invalid credentials"),
+                        "403": text_response("The local account's email address is not verified")
+                    }
+                }
+            },
+            "/service/{service_id}/export": {
+                "get": {
+                    "summary": "Export the service's schema, roles and content as NDJSON",
+                    "operationId": "exportService",
+                    "parameters": [{
+                        "name": "service_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "One archive record per line, starting with a header record",
+                            "content": { "application/x-ndjson": { "schema": { "type": "string" } } }
+                        },
+                        "403": text_response("Requires the admin member role"),
+                        "404": text_response("Service not found, or the caller is not a member")
+                    }
+                }
+            },
+            "/service/{service_id}/import": {
+                "post": {
+                    "summary": "Import an export archive into the service with new IDs",
+                    "operationId": "importService",
+                    "parameters": [
+                        {
+                            "name": "service_id",
+                            "in": "path",
+                            "required": true,
+                            "schema": { "type": "string" }
+                        },
+                        {
+                            "name": "dry_run",
+                            "in": "query",
+                            "required": false,
+                            "schema": { "type": "boolean", "default": false }
+                        }
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/x-ndjson": { "schema": { "type": "string" } } }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Dry run report, nothing was written",
+                            "content": { "application/json": { "schema": { "type": "object" } } }
+                        },
+                        "201": {
+                            "description": "Import report with the ID mapping and new role API keys; the archived Admin role is not imported and the service keeps its own",
+                            "content": { "application/json": { "schema": { "type": "object" } } }
+                        },
+                        "400": text_response("The archive is invalid"),
+                        "403": text_response("Requires the admin member role"),
+                        "404": text_response("Service not found, or the caller is not a member")
+                    }
+                }
+            },
+            "/services/services/{service_id}": {
+                "delete": {
+                    "summary": "Delete a service; it can be restored until the retention period ends",
+                    "operationId": "deleteService",
+                    "parameters": [{
+                        "name": "service_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": text_response("Service deleted, with the time until which it can be restored"),
+                        "403": text_response("Requires the owner member role"),
+                        "404": text_response("Service not found, or the caller is not a member")
+                    }
+                }
+            },
+            "/service/{service_id}/restore": {
+                "post": {
+                    "summary": "Restore a deleted service before it is purged",
+                    "operationId": "restoreService",
+                    "parameters": [{
+                        "name": "service_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": text_response("Service restored"),
+                        "403": text_response("Requires the owner member role"),
+                        "404": text_response("Service not found, or the caller is not a member"),
+                        "409": text_response("The service is not deleted")
+                    }
+                }
+            },
+            "/services/{service_id}/openapi.json": {
+                "get": {
+                    "summary": "OpenAPI document of the service's content API",
+                    "operationId": "getServiceOpenApi",
+                    "security": [{ "ApiKey": [] }],
+                    "parameters": [{
+                        "name": "service_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "OpenAPI 3.1 document",
+                            "content": { "application/json": { "schema": { "type": "object" } } }
+                        },
+                        "404": text_response("Service not found")
+                    }
+                }
+            },
+            "/services/{service_id}/types.d.ts": {
+                "get": {
+                    "summary": "TypeScript declarations of the service's content types",
+                    "operationId": "getServiceTypeScript",
+                    "security": [{ "ApiKey": [] }],
+                    "parameters": [{
+                        "name": "service_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "A .d.ts file",
+                            "content": { "application/typescript": { "schema": { "type": "string" } } }
+                        },
+                        "404": text_response("Service not found")
+                    }
+                }
+            },
+            "/auth/register": {
+                "post": {
+                    "summary": "Create a local account",
+                    "operationId": "register",
+                    "security": [],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": {
+                                "username": { "type": "string" },
+                                "email": { "type": "string", "format": "email" },
+                                "password": { "type": "string" }
+                            },
+                            "required": ["username", "email", "password"]
+                        } } }
+                    },
+                    "responses": { "201": text_response("Account created") }
+                }
+            },
+            "/auth/login": {
+                "post": {
+                    "summary": "Log in and receive the session cookie",
+                    "operationId": "login",
+                    "security": [],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": {
+                                "username": { "type": "string" },
+                                "password": { "type": "string" }
+                            },
+                            "required": ["username", "password"]
+                        } } }
+                    },
+                    "responses": {
+                        "200": { "description": "Logged in; the session cookie is set" },
+                        "202": {
+                            "description": "Two-factor authentication is enabled; send a code to /auth/login/totp",
+                            "content": { "application/json": { "schema": {
+                                "type": "object",
+                                "properties": {
+                                    "two_factor_required": { "type": "boolean" },
+                                    "challenge": { "type": "string" }
+                                },
+                                "required": ["two_factor_required", "challenge"]
+                            } } }
+                        },
+                        "400": { "description": "Unknown user" },
+                        "401": { "description": "Wrong password" }
+                    }
+                }
+            },
+            "/auth/login/totp": {
+                "post": {
+                    "summary": "Finish logging in with an authenticator code or a recovery code",
+                    "description": "The challenge from /auth/login is valid for 5 minutes. Each code and recovery code works once.",
+                    "operationId": "loginTotp",
+                    "security": [],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": {
+                                "challenge": { "type": "string" },
+                                "code": { "type": "string" }
+                            },
+                            "required": ["challenge", "code"]
+                        } } }
+                    },
+                    "responses": {
+                        "200": { "description": "Logged in; the session cookie is set" },
+                        "400": text_response("The challenge is invalid or expired; log in again"),
+                        "401": text_response("Wrong code"),
+                        "429": text_response("Too many wrong codes; retry after the Retry-After header")
+                    }
+                }
+            },
+            "/auth/totp": {
+                "post": {
+                    "summary": "Start enrolling an authenticator app",
+                    "description": "Two-factor authentication is enabled once a code is confirmed with /auth/totp/activate.",
+                    "operationId": "enrollTotp",
+                    "responses": {
+                        "200": {
+                            "description": "The secret and an otpauth:// URI for the authenticator app",
+                            "content": { "application/json": { "schema": {
+                                "type": "object",
+                                "properties": {
+                                    "secret": { "type": "string" },
+                                    "otpauth_uri": { "type": "string" }
+                                },
+                                "required": ["secret", "otpauth_uri"]
+                            } } }
+                        },
+                        "400": text_response("Not a local account"),
+                        "409": text_response("Two-factor authentication is already enabled")
+                    }
+                }
+            },
+            "/auth/totp/activate": {
+                "post": {
+                    "summary": "Enable two-factor authentication with a code from the authenticator app",
+                    "operationId": "activateTotp",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": { "code": { "type": "string" } },
+                            "required": ["code"]
+                        } } }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Enabled; the recovery codes are shown only this once",
+                            "content": { "application/json": { "schema": {
+                                "type": "object",
+                                "properties": {
+                                    "recovery_codes": { "type": "array", "items": { "type": "string" } }
+                                },
+                                "required": ["recovery_codes"]
+                            } } }
+                        },
+                        "400": text_response("Wrong code, or enrollment was not started"),
+                        "409": text_response("Two-factor authentication is already enabled")
+                    }
+                }
+            },
+            "/auth/logout": {
+                "post": {
+                    "summary": "End the current session",
+                    "operationId": "logout",
+                    "security": [{ "SessionCookie": [] }],
+                    "responses": { "200": { "description": "Logged out; the session cookie is removed" } }
+                }
+            },
+            "/auth/forgot_password": {
+                "post": {
+                    "summary": "Email a password reset link",
+                    "description": "Responds the same way whether or not the address is registered.",
+                    "operationId": "forgotPassword",
+                    "security": [],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": { "type": "string", "format": "email" } } }
+                    },
+                    "responses": {
+                        "200": text_response("Accepted; a link is sent if the address is registered"),
+                        "503": text_response("Mail is not configured")
+                    }
+                }
+            },
+            "/auth/reset-password": {
+                "post": {
+                    "summary": "Choose a new password with the token from the reset link",
+                    "description": "The token can be used once. All of the user's sessions are signed out.",
+                    "operationId": "resetPassword",
+                    "security": [],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": {
+                                "token": { "type": "string" },
+                                "password": { "type": "string" }
+                            },
+                            "required": ["token", "password"]
+                        } } }
+                    },
+                    "responses": {
+                        "200": text_response("Password changed"),
+                        "400": text_response("The token is invalid, used or expired")
+                    }
+                }
+            },
+            "/auth/auth_check": {
+                "get": {
+                    "summary": "Check whether the session cookie is valid",
+                    "operationId": "authCheck",
+                    "security": [{ "SessionCookie": [] }],
+                    "responses": {
+                        "200": text_response("Logged in"),
+                        "403": text_response("Not logged in")
+                    }
+                }
+            },
+            "/auth/verify-email": {
+                "post": {
+                    "summary": "Verify the email address with the token from the verification link",
+                    "operationId": "verifyEmail",
+                    "security": [],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "object",
+                            "properties": { "token": { "type": "string" } },
+                            "required": ["token"]
+                        } } }
+                    },
+                    "responses": {
+                        "200": text_response("Email address verified"),
+                        "400": text_response("The token is invalid or expired")
+                    }
+                }
+            },
+            "/auth/resend-verification": {
+                "post": {
+                    "summary": "Send the verification email again",
+                    "description": "Allowed once a minute per account.",
+                    "operationId": "resendVerification",
+                    "responses": {
+                        "200": text_response("Verification email queued"),
+                        "400": text_response("Already verified, or not a local account"),
+                        "429": {
+                            "description": "Sent too recently",
+                            "headers": { "Retry-After": { "schema": { "type": "integer" } } },
+                            "content": { "text/plain": { "schema": { "type": "string" } } }
+                        },
+                        "503": text_response("Mail is not configured")
+                    }
+                }
+            },
+            "/auth/sessions": {
+                "get": {
+                    "summary": "List the signed-in user's active sessions",
+                    "operationId": "listSessions",
+                    "responses": {
+                        "200": {
+                            "description": "Sessions, most recently used first",
+                            "content": { "application/json": { "schema": {
+                                "type": "array",
+                                "items": { "$ref": "#/components/schemas/Session" }
+                            } } }
+                        },
+                        "400": text_response("Not signed in with a local account")
+                    }
+                }
+            },
+            "/auth/sessions/{session_id}": {
+                "delete": {
+                    "summary": "Sign out one of the user's sessions",
+                    "operationId": "revokeSession",
+                    "parameters": [{
+                        "name": "session_id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer" }
+                    }],
+                    "responses": {
+                        "200": text_response("Session revoked"),
+                        "400": text_response("Not signed in with a local account"),
+                        "404": text_response("Session not found")
+                    }
+                }
+            },
+            "/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "operationId": "getManagementOpenApi",
+                    "security": [],
+                    "responses": {
+                        "200": {
+                            "description": "OpenAPI 3.1 document",
+                            "content": { "application/json": { "schema": { "type": "object" } } }
+                        }
+                    }
+                }
+            }
+        },
+        "security": [{ "Bearer": [] }, { "SessionCookie": [] }],
+        "components": {
+            "schemas": {
+                "Permission": {
+                    "type": "string",
+                    "enum": ["Post", "Get", "Put", "Patch", "Delete"]
+                },
+                "Session": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "current": { "type": "boolean" },
+                        "created_at": { "type": "string", "format": "date-time" },
+                        "last_seen_at": { "type": "string", "format": "date-time" },
+                        "expires_at": { "type": "string", "format": "date-time" },
+                        "user_agent": { "type": ["string", "null"] },
+                        "ip_address": { "type": ["string", "null"] }
+                    },
+                    "required": ["id", "current", "created_at", "last_seen_at", "expires_at"]
+                },
+                "Role": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "name": { "type": "string" },
+                        "permissions": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/Permission" }
+                        }
+                    },
+                    "required": ["id", "name", "permissions"]
+                },
+                "ApiKey": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "role_id": { "type": "integer" },
+                        "name": { "type": ["string", "null"] },
+                        "prefix": { "type": "string", "description": "The first characters of the key" },
+                        "created_at": { "type": "string", "format": "date-time" },
+                        "expires_at": { "type": ["string", "null"], "format": "date-time" },
+                        "revoked_at": { "type": ["string", "null"], "format": "date-time" },
+                        "last_used_at": { "type": ["string", "null"], "format": "date-time" }
+                    },
+                    "required": ["id", "role_id", "prefix", "created_at"]
+                },
+                "IssuedApiKey": {
+                    "allOf": [
+                        { "$ref": "#/components/schemas/ApiKey" },
+                        {
+                            "type": "object",
+                            "properties": { "key": { "type": "string" } },
+                            "required": ["key"]
+                        }
+                    ]
+                },
+                "MemberRole": {
+                    "type": "string",
+                    "enum": ["owner", "admin", "editor", "viewer"]
+                },
+                "Member": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "service_id": { "type": "string" },
+                        "provider": { "type": "string" },
+                        "subject": { "type": "string" },
+                        "email": { "type": ["string", "null"] },
+                        "role": { "$ref": "#/components/schemas/MemberRole" },
+                        "created_at": { "type": "string", "format": "date-time" }
+                    },
+                    "required": ["id", "service_id", "provider", "subject", "role", "created_at"]
+                },
+                "Invitation": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "email": { "type": "string" },
+                        "role": { "$ref": "#/components/schemas/MemberRole" },
+                        "invited_by": { "type": ["integer", "null"] },
+                        "created_at": { "type": "string", "format": "date-time" },
+                        "expires_at": { "type": "string", "format": "date-time" }
+                    },
+                    "required": ["id", "email", "role", "created_at", "expires_at"]
+                }
+            },
+            "securitySchemes": {
+                "Bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                "SessionCookie": { "type": "apiKey", "in": "cookie", "name": SESSION_COOKIE },
+                "ApiKey": { "type": "apiKey", "in": "header", "name": "x-api-key" }
+            }
+        }
+    });
+    if let Some(paths) = document["paths"].as_object_mut() {
+        for extra in [member_paths(), role_paths(), audit_paths(), usage_paths()] {
+            if let Value::Object(extra) = extra {
+                paths.extend(extra);
+            }
+        }
+    }
+    document["components"]["schemas"]["AuditLog"] = audit_log_schema();
+    document["components"]["schemas"]["UsageCounts"] = usage_counts_schema();
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{content_types, fields};
+
+    fn content_model() -> ContentModel {
+        let now = chrono::Utc::now();
+        let content_type = content_types::Model {
+            id: 7,
+            name: "blog posts".to_string(),
+            created_at: now.into(),
+            updated_at: now.into(),
+            service_id: Some("service".to_string()),
+        };
+        let field = |id, display_id: &str, field_type: &str, required| fields::Model {
+            id,
+            content_type_id: 7,
+            display_id: display_id.to_string(),
+            field_type: field_type.to_string(),
+            required,
+            created_at: now.into(),
+            updated_at: now.into(),
+        };
+        vec![(
+            content_type,
+            vec![
+                field(1, "title", "Text", true),
+                field(2, "published_at", "Date", false),
+            ],
+        )]
+    }
+
+    fn service() -> services::Model {
+        services::Model {
+            id: "service".to_string(),
+            name: "Blog".to_string(),
+            deleted_at: None,
+            plan: None,
+        }
+    }
+
+    // ドキュメント内の$refがすべてcomponents.schemasを指しているか確かめる
+    fn assert_refs_resolve(document: &Value, node: &Value) {
+        match node {
+            Value::Object(object) => {
+                if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                    let name = reference
+                        .strip_prefix("#/components/schemas/")
+                        .unwrap_or_else(|| panic!("unexpected $ref {}", reference));
+                    assert!(
+                        document["components"]["schemas"].get(name).is_some(),
+                        "{} is not defined",
+                        reference
+                    );
+                }
+                for value in object.values() {
+                    assert_refs_resolve(document, value);
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    assert_refs_resolve(document, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn service_documents_describe_each_content_type() {
+        let document = service_document(&service(), &content_model());
+        assert_eq!(document["openapi"], OPENAPI_VERSION);
+        assert_eq!(document["servers"][0]["url"], "/api/services/service");
+        assert_eq!(
+            document["components"]["schemas"]["BlogPosts7"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string" },
+                    "published_at": { "type": "string", "format": "date-time" }
+                },
+                "required": ["title"]
+            })
+        );
+        let items = &document["paths"]["/7/content_items"];
+        assert_eq!(items["get"]["operationId"], "listBlogPosts7");
+        assert_eq!(items["post"]["operationId"], "createBlogPosts7");
+        assert!(document["paths"]["/7/content_items.csv"]["get"].is_object());
+        assert!(document["paths"]["/7/fields"]["post"].is_object());
+        assert_refs_resolve(&document, &document);
+    }
+
+    #[test]
+    fn every_service_operation_documents_rate_limiting() {
+        let document = service_document(&service(), &content_model());
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                if method == "parameters" {
+                    continue;
+                }
+                assert!(
+                    operation["responses"]["429"].is_object(),
+                    "{} {} has no 429 response",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn services_without_content_types_still_describe_content_items() {
+        let document = service_document(&service(), &Vec::new());
+        assert_eq!(
+            document["components"]["schemas"]["ContentItem"]["properties"]["data"],
+            json!({ "type": "object" })
+        );
+        assert_refs_resolve(&document, &document);
+    }
+
+    #[test]
+    fn management_document_references_are_defined() {
+        let document = management_document();
+        assert_eq!(document["openapi"], OPENAPI_VERSION);
+        assert!(document["paths"]
+            .as_object()
+            .is_some_and(|paths| !paths.is_empty()));
+        assert_refs_resolve(&document, &document);
+    }
+}
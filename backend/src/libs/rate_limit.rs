@@ -0,0 +1,120 @@
+use crate::config::PlanLimits;
+use chrono::{Datelike, TimeZone};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// これより多くのキーのバケットを持ったら、満杯に戻ったものを捨てる
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    // この時刻を過ぎれば満杯に戻っているので、捨てても結果は変わらない
+    full_at: Instant,
+}
+
+// トークンバケットの判定結果。X-RateLimit-*ヘッダに使う
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitStatus {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // バケットが満杯に戻るまでの秒数
+    pub reset_secs: u64,
+    // 拒否したとき、次のリクエストを受け付けられるまでの秒数
+    pub retry_after_secs: u64,
+}
+
+// APIキーごとのトークンバケット。プロセスごとに持つので、複数台で動かすと台数分まで受け付ける
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<i32, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn check(&self, key_id: i32, plan: &PlanLimits) -> RateLimitStatus {
+        let now = Instant::now();
+        let capacity = f64::from(plan.burst);
+        let per_second = f64::from(plan.requests_per_minute) / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+        let bucket = buckets.entry(key_id).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+            full_at: now,
+        });
+        // プランが変わって容量が減った場合も、容量を超えては貯めない
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated_at = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let until_full = Duration::from_secs_f64((capacity - bucket.tokens) / per_second);
+        bucket.full_at = now + until_full;
+        RateLimitStatus {
+            allowed,
+            limit: plan.burst,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: until_full.as_secs_f64().ceil() as u64,
+            retry_after_secs: if allowed {
+                0
+            } else {
+                ((1.0 - bucket.tokens) / per_second).ceil().max(1.0) as u64
+            },
+        }
+    }
+}
+
+// 今月(UTC)のリクエスト数
+#[derive(Clone, Copy, Debug)]
+pub struct QuotaUsage {
+    pub quota: u64,
+    pub used: u64,
+}
+
+// 今月のリクエストを一件数える。上限に達していれば数えずにNoneを返す
+pub async fn count_monthly_request(
+    pool: &PgPool,
+    service_id: &str,
+    quota: u64,
+) -> Result<Option<QuotaUsage>, sqlx::Error> {
+    // 上限との比較と加算を一つの文で行うので、同時に呼ばれても上限を超えて数えない
+    let used = sqlx::query_scalar::<_, i64>(
+        r#"INSERT INTO service_monthly_requests (service_id, month, requests)
+        VALUES ($1, date_trunc('month', now() AT TIME ZONE 'UTC')::date, 1)
+        ON CONFLICT (service_id, month) DO UPDATE
+        SET requests = service_monthly_requests.requests + 1
+        WHERE service_monthly_requests.requests < $2
+        RETURNING requests
+    "#,
+    )
+    .bind(service_id)
+    .bind(i64::try_from(quota).unwrap_or(i64::MAX))
+    .fetch_optional(pool)
+    .await?;
+    Ok(used.map(|used| QuotaUsage {
+        quota,
+        used: used as u64,
+    }))
+}
+
+// 翌月の初め(UTC)までの秒数。月間上限に達したときのRetry-Afterに使う
+pub fn seconds_until_next_month() -> u64 {
+    let now = chrono::Utc::now();
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    let next = chrono::Utc
+        .with_ymd_and_hms(year, month, 1, 0, 0, 0)
+        .single()
+        .unwrap_or(now);
+    (next - now).num_seconds().max(1) as u64
+}
@@ -0,0 +1,44 @@
+use crate::config::RetentionConfig;
+use crate::libs::management::purge_deleted_services;
+use crate::libs::metrics::Metrics;
+use crate::libs::trash::purge_trash;
+use sea_orm::DatabaseConnection;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn days_ago(days: u32) -> chrono::DateTime<chrono::Utc> {
+    chrono::Utc::now() - chrono::Duration::days(i64::from(days))
+}
+
+// 削除から保持期間が過ぎたサービスとゴミ箱のアイテムを定期的に完全削除するタスクを起動する
+pub fn spawn_purge_job(db: DatabaseConnection, config: RetentionConfig, metrics: Arc<Metrics>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.purge_interval_secs));
+        loop {
+            interval.tick().await;
+            let started = Instant::now();
+            let mut succeeded = true;
+            // 失敗しても次の実行で再試行する
+            match purge_deleted_services(&db, days_ago(config.deleted_service_days).into()).await {
+                Ok(purged) => {
+                    for service_id in purged {
+                        log::info!("purged deleted service {}", service_id);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("failed to purge deleted services: {}", e);
+                    succeeded = false;
+                }
+            }
+            match purge_trash(&db, days_ago(config.deleted_content_item_days).into()).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("purged {} content items from the trash", count),
+                Err(e) => {
+                    log::warn!("failed to purge the trash: {}", e);
+                    succeeded = false;
+                }
+            }
+            metrics.job_finished("purge", started, succeeded);
+        }
+    });
+}
@@ -0,0 +1,69 @@
+use anyhow::bail;
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+fn latest_known_version() -> i64 {
+    MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default()
+}
+
+// このバイナリが知らないマイグレーションがDBに適用済みなら、スキーマが新しすぎるので起動しない
+pub async fn ensure_schema_not_ahead(pool: &PgPool) -> anyhow::Result<()> {
+    let initialized: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    if !initialized {
+        return Ok(());
+    }
+
+    let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations")
+        .fetch_all(pool)
+        .await?;
+    let mut unknown: Vec<i64> = applied
+        .into_iter()
+        .filter(|version| MIGRATOR.iter().all(|m| m.version != *version))
+        .collect();
+    unknown.sort_unstable();
+
+    if let Some(newest) = unknown.last() {
+        bail!(
+            "the database schema is ahead of this binary: migration {} is applied but the latest known migration is {} (unknown versions: {:?})",
+            newest,
+            latest_known_version(),
+            unknown
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn run_migrations(pool: &PgPool) -> anyhow::Result<()> {
+    ensure_schema_not_ahead(pool).await?;
+    MIGRATOR.run(pool).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_latest_known_version_is_the_newest_migration_file() {
+        let newest = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+            .unwrap()
+            .filter_map(|entry| {
+                let name = entry.unwrap().file_name().into_string().unwrap();
+                name.split('_').next()?.parse::<i64>().ok()
+            })
+            .max()
+            .unwrap();
+        assert_eq!(latest_known_version(), newest);
+    }
+}
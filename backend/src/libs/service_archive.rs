@@ -0,0 +1,567 @@
+use crate::libs::api_keys::issue_key;
+use crate::libs::management::ADMIN_ROLE;
+use crate::models::prelude::{ContentItems, ContentTypes, Fields, RolePermissions, Roles};
+use crate::models::{content_items, content_types, fields, role_permissions, roles, services};
+use crate::router_comp::content_router::FieldType;
+use crate::router_comp::service_router::Permission;
+use futures::stream::{self, Stream, StreamExt};
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+pub const ARCHIVE_FORMAT: &str = "headless-cms-service";
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const CONTENT_ITEM_PAGE_SIZE: u64 = 500;
+
+// アーカイブはNDJSONで、1行に1レコードを書く。先頭行は必ずHeader
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveRecord {
+    Header {
+        format: String,
+        version: u32,
+        service_id: String,
+        service_name: String,
+    },
+    ContentType {
+        id: i32,
+        name: String,
+    },
+    Field {
+        id: i32,
+        content_type_id: i32,
+        display_id: String,
+        field_type: FieldType,
+        required: bool,
+    },
+    // APIキーは書き出さない。取り込み時に新しいキーを発行する
+    Role {
+        id: i32,
+        name: String,
+        permissions: HashSet<Permission>,
+        // サービスの作成時に作ったAdminロール。取り込み先にも必ずあるので取り込まない
+        #[serde(default)]
+        admin: bool,
+    },
+    ContentItem {
+        id: Uuid,
+        content_type_id: i32,
+        data: serde_json::Value,
+        created_at: DateTimeWithTimeZone,
+        updated_at: DateTimeWithTimeZone,
+    },
+}
+
+impl ArchiveRecord {
+    pub fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).expect("archive records always serialize");
+        line.push('\n');
+        line
+    }
+}
+
+// スキーマ部分をまとめて読み込み、コンテンツアイテムはページごとに読み込みながら返す
+pub async fn export_service(
+    db: &DatabaseConnection,
+    service: &services::Model,
+) -> Result<impl Stream<Item = Result<ArchiveRecord, DbErr>>, DbErr> {
+    let mut records = vec![ArchiveRecord::Header {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        service_id: service.id.clone(),
+        service_name: service.name.clone(),
+    }];
+
+    let content_types = ContentTypes::find()
+        .filter(content_types::Column::ServiceId.eq(service.id.as_str()))
+        .order_by_asc(content_types::Column::Id)
+        .find_with_related(Fields)
+        .order_by_asc(fields::Column::Id)
+        .all(db)
+        .await?;
+
+    let content_type_ids: Vec<i32> = content_types.iter().map(|(ct, _)| ct.id).collect();
+
+    for (content_type, fields) in content_types {
+        records.push(ArchiveRecord::ContentType {
+            id: content_type.id,
+            name: content_type.name,
+        });
+        for field in fields {
+            let Ok(field_type) = FieldType::from_str(&field.field_type) else {
+                return Err(DbErr::Custom(format!(
+                    "field {} has an unknown field type {}",
+                    field.id, field.field_type
+                )));
+            };
+            records.push(ArchiveRecord::Field {
+                id: field.id,
+                content_type_id: field.content_type_id,
+                display_id: field.display_id,
+                field_type,
+                required: field.required,
+            });
+        }
+    }
+
+    let roles = Roles::find()
+        .filter(roles::Column::ServiceId.eq(service.id.as_str()))
+        .order_by_asc(roles::Column::Id)
+        .find_with_related(RolePermissions)
+        .all(db)
+        .await?;
+
+    for (role, permissions) in roles {
+        records.push(ArchiveRecord::Role {
+            id: role.id,
+            name: role.name,
+            admin: role.is_admin,
+            permissions: permissions
+                .iter()
+                .filter_map(|p| Permission::from_str(&p.permission).ok())
+                .collect(),
+        });
+    }
+
+    let db = db.clone();
+    let items = stream::unfold(Some(0u64), move |offset| {
+        let db = db.clone();
+        let content_type_ids = content_type_ids.clone();
+        async move {
+            let offset = offset?;
+            let page = ContentItems::find()
+                .filter(content_items::Column::ContentTypeId.is_in(content_type_ids))
+                // ゴミ箱のアイテムはエクスポートしない
+                .filter(content_items::Column::DeletedAt.is_null())
+                .order_by_asc(content_items::Column::ContentTypeId)
+                .order_by_asc(content_items::Column::Id)
+                .offset(offset)
+                .limit(CONTENT_ITEM_PAGE_SIZE)
+                .all(&db)
+                .await;
+            match page {
+                Ok(page) if page.is_empty() => None,
+                Ok(page) => {
+                    let next = if (page.len() as u64) < CONTENT_ITEM_PAGE_SIZE {
+                        None
+                    } else {
+                        Some(offset + CONTENT_ITEM_PAGE_SIZE)
+                    };
+                    let records = page.into_iter().map(|item| {
+                        Ok(ArchiveRecord::ContentItem {
+                            id: item.id,
+                            content_type_id: item.content_type_id,
+                            data: item.data,
+                            created_at: item.created_at,
+                            updated_at: item.updated_at,
+                        })
+                    });
+                    Some((stream::iter(records.collect::<Vec<_>>()), next))
+                }
+                Err(e) => Some((stream::iter(vec![Err(e)]), None)),
+            }
+        }
+    })
+    .flatten();
+
+    Ok(stream::iter(records.into_iter().map(Ok)).chain(items))
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    // アーカイブの内容の問題。何も書き込まずに終わる
+    Invalid(Vec<String>),
+    Database(DbErr),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Invalid(errors) => write!(f, "invalid archive:\n{}", errors.join("\n")),
+            ImportError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<DbErr> for ImportError {
+    fn from(e: DbErr) -> Self {
+        ImportError::Database(e)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportedRole {
+    pub name: String,
+    pub api_key: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub source_service_id: String,
+    pub content_types: BTreeMap<i32, i32>,
+    pub fields: usize,
+    pub roles: Vec<ImportedRole>,
+    pub content_items: BTreeMap<Uuid, Uuid>,
+}
+
+pub fn parse_archive(archive: &str) -> Result<Vec<ArchiveRecord>, ImportError> {
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, line) in archive.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<ArchiveRecord>(line) {
+            Ok(record) => records.push(record),
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+
+    match records.first() {
+        Some(ArchiveRecord::Header {
+            format, version, ..
+        }) => {
+            if format != ARCHIVE_FORMAT {
+                errors.push(format!("line 1: unknown archive format {}", format));
+            } else if *version > ARCHIVE_VERSION {
+                errors.push(format!(
+                    "line 1: archive version {} is newer than the supported version {}",
+                    version, ARCHIVE_VERSION
+                ));
+            }
+        }
+        _ => errors.push("line 1: the archive must start with a header record".to_string()),
+    }
+
+    if errors.is_empty() {
+        Ok(records)
+    } else {
+        Err(ImportError::Invalid(errors))
+    }
+}
+
+// 既存のサービスにアーカイブを取り込む。IDはすべて振り直し、dry_runの場合は最後にロールバックする
+pub async fn import_service(
+    db: &DatabaseConnection,
+    service_id: &str,
+    records: Vec<ArchiveRecord>,
+    dry_run: bool,
+) -> Result<ImportReport, ImportError> {
+    let mut source_service_id = String::new();
+    let mut new_content_types = Vec::new();
+    let mut new_fields = Vec::new();
+    let mut new_roles = Vec::new();
+    let mut new_items = Vec::new();
+
+    for record in records {
+        match record {
+            ArchiveRecord::Header { service_id, .. } => source_service_id = service_id,
+            ArchiveRecord::ContentType { .. } => new_content_types.push(record),
+            ArchiveRecord::Field { .. } => new_fields.push(record),
+            ArchiveRecord::Role { .. } => new_roles.push(record),
+            ArchiveRecord::ContentItem { .. } => new_items.push(record),
+        }
+    }
+
+    let txn = db.begin().await?;
+    let mut errors = Vec::new();
+    let mut report = ImportReport {
+        dry_run,
+        source_service_id,
+        content_types: BTreeMap::new(),
+        fields: 0,
+        roles: Vec::new(),
+        content_items: BTreeMap::new(),
+    };
+
+    for record in new_content_types {
+        let ArchiveRecord::ContentType { id, name } = record else {
+            continue;
+        };
+        let content_type = content_types::ActiveModel {
+            id: Default::default(),
+            name: Set(name),
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            service_id: Set(Some(service_id.to_string())),
+        }
+        .insert(&txn)
+        .await?;
+        if report.content_types.insert(id, content_type.id).is_some() {
+            errors.push(format!("content type {} appears more than once", id));
+        }
+    }
+
+    // コンテンツアイテムの検証に使うため、取り込んだフィールドを新しいコンテンツタイプIDごとに保持する
+    let mut fields_by_type: HashMap<i32, Vec<fields::Model>> = HashMap::new();
+    for record in new_fields {
+        let ArchiveRecord::Field {
+            id,
+            content_type_id,
+            display_id,
+            field_type,
+            required,
+        } = record
+        else {
+            continue;
+        };
+        let Some(&new_content_type_id) = report.content_types.get(&content_type_id) else {
+            errors.push(format!(
+                "field {} refers to unknown content type {}",
+                id, content_type_id
+            ));
+            continue;
+        };
+        let field = fields::ActiveModel {
+            id: Default::default(),
+            content_type_id: Set(new_content_type_id),
+            display_id: Set(display_id),
+            field_type: Set(field_type.to_string()),
+            required: Set(required),
+            created_at: Default::default(),
+            updated_at: Default::default(),
+        }
+        .insert(&txn)
+        .await?;
+        fields_by_type
+            .entry(new_content_type_id)
+            .or_default()
+            .push(field);
+        report.fields += 1;
+    }
+
+    for record in new_roles {
+        let ArchiveRecord::Role {
+            name,
+            permissions,
+            admin,
+            ..
+        } = record
+        else {
+            continue;
+        };
+        // 取り込み先のAdminロールは全権限を持ったまま、キーも変えずに残す。
+        // admin列がない以前のアーカイブでは名前で見分ける
+        if admin || name == ADMIN_ROLE {
+            continue;
+        }
+        let role = roles::ActiveModel {
+            id: Default::default(),
+            name: Set(name.clone()),
+            service_id: Set(service_id.to_string()),
+            is_admin: Set(false),
+        }
+        .insert(&txn)
+        .await?;
+        for permission in permissions {
+            role_permissions::ActiveModel {
+                role_id: Set(role.id),
+                permission: Set(permission.to_string()),
+            }
+            .insert(&txn)
+            .await?;
+        }
+        let api_key = issue_key(&txn, role.id, None, None).await?.key;
+        report.roles.push(ImportedRole { name, api_key });
+    }
+
+    for record in new_items {
+        let ArchiveRecord::ContentItem {
+            id,
+            content_type_id,
+            data,
+            created_at,
+            updated_at,
+        } = record
+        else {
+            continue;
+        };
+        let Some(&new_content_type_id) = report.content_types.get(&content_type_id) else {
+            errors.push(format!(
+                "content item {} refers to unknown content type {}",
+                id, content_type_id
+            ));
+            continue;
+        };
+        //データ型の検証
+        for field in fields_by_type
+            .get(&new_content_type_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+        {
+            match data.get(&field.display_id) {
+                Some(value) if !field.field_type_matches(value) => errors.push(format!(
+                    "content item {}: {} does not match {}",
+                    id, field.display_id, field.field_type
+                )),
+                None if field.required => errors.push(format!(
+                    "content item {}: required field {} is missing",
+                    id, field.display_id
+                )),
+                _ => {}
+            }
+        }
+        let new_id = Uuid::new_v4();
+        content_items::ActiveModel {
+            id: Set(new_id),
+            content_type_id: Set(new_content_type_id),
+            data: Set(data),
+            created_at: Set(created_at),
+            updated_at: Set(updated_at),
+            deleted_at: Default::default(),
+            deleted_by_key_id: Default::default(),
+        }
+        .insert(&txn)
+        .await?;
+        if report.content_items.insert(id, new_id).is_some() {
+            errors.push(format!("content item {} appears more than once", id));
+        }
+    }
+
+    if !errors.is_empty() {
+        txn.rollback().await?;
+        return Err(ImportError::Invalid(errors));
+    }
+
+    if dry_run {
+        txn.rollback().await?;
+    } else {
+        txn.commit().await?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(format: &str, version: u32) -> String {
+        ArchiveRecord::Header {
+            format: format.to_string(),
+            version,
+            service_id: "source".to_string(),
+            service_name: "Source".to_string(),
+        }
+        .to_line()
+    }
+
+    fn invalid(archive: &str) -> Vec<String> {
+        match parse_archive(archive) {
+            Err(ImportError::Invalid(errors)) => errors,
+            other => panic!("expected an invalid archive, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn records_round_trip_through_lines() {
+        let archive = [
+            header(ARCHIVE_FORMAT, ARCHIVE_VERSION),
+            ArchiveRecord::ContentType {
+                id: 1,
+                name: "posts".to_string(),
+            }
+            .to_line(),
+            // 空行は読み飛ばす
+            "\n".to_string(),
+            ArchiveRecord::Field {
+                id: 2,
+                content_type_id: 1,
+                display_id: "title".to_string(),
+                field_type: FieldType::Text,
+                required: true,
+            }
+            .to_line(),
+            ArchiveRecord::Role {
+                id: 3,
+                name: "Reader".to_string(),
+                permissions: HashSet::from([Permission::Get]),
+                admin: false,
+            }
+            .to_line(),
+            // admin列がない以前のアーカイブも読める
+            "{\"kind\":\"role\",\"id\":4,\"name\":\"Admin\",\"permissions\":[\"Get\"]}\n"
+                .to_string(),
+        ]
+        .concat();
+
+        let records = parse_archive(&archive).unwrap();
+        assert_eq!(records.len(), 5);
+        assert!(matches!(
+            &records[0],
+            ArchiveRecord::Header { service_id, .. } if service_id == "source"
+        ));
+        assert!(matches!(
+            &records[2],
+            ArchiveRecord::Field {
+                field_type: FieldType::Text,
+                required: true,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &records[3],
+            ArchiveRecord::Role { permissions, .. } if permissions.contains(&Permission::Get)
+        ));
+        assert!(matches!(
+            &records[4],
+            ArchiveRecord::Role { name, admin: false, .. } if name == "Admin"
+        ));
+    }
+
+    #[test]
+    fn archives_must_start_with_a_known_header() {
+        let content_type = ArchiveRecord::ContentType {
+            id: 1,
+            name: "posts".to_string(),
+        }
+        .to_line();
+        assert_eq!(
+            invalid(&content_type),
+            vec!["line 1: the archive must start with a header record"]
+        );
+        assert_eq!(
+            invalid(""),
+            vec!["line 1: the archive must start with a header record"]
+        );
+        assert_eq!(
+            invalid(&header("something-else", 1)),
+            vec!["line 1: unknown archive format something-else"]
+        );
+        assert_eq!(
+            invalid(&header(ARCHIVE_FORMAT, ARCHIVE_VERSION + 1)),
+            vec![format!(
+                "line 1: archive version {} is newer than the supported version {}",
+                ARCHIVE_VERSION + 1,
+                ARCHIVE_VERSION
+            )]
+        );
+    }
+
+    #[test]
+    fn every_unreadable_line_is_reported() {
+        let archive = [
+            header(ARCHIVE_FORMAT, ARCHIVE_VERSION),
+            "{\"kind\":\"content_type\",\"id\":1}\n".to_string(),
+            "not json\n".to_string(),
+            "{\"kind\":\"field\",\"id\":1,\"content_type_id\":1,\"display_id\":\"a\",\"field_type\":\"Color\",\"required\":false}\n".to_string(),
+        ]
+        .concat();
+        let errors = invalid(&archive);
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("line 2: "));
+        assert!(errors[1].starts_with("line 3: "));
+        assert!(errors[2].starts_with("line 4: "));
+    }
+}
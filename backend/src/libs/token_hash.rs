@@ -0,0 +1,8 @@
+use sha2::{Digest, Sha256};
+
+// メールで送るトークンなどはDBに平文で残さず、SHA-256のhexで保存して照合する。
+// 平文はメールやAPIの応答で利用者に一度渡すだけなので、DBが漏れてもトークンとしては使えない。
+// 十分な長さの乱数なのでbcryptのような遅いハッシュは不要で、インデックスで検索できる
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
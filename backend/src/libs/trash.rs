@@ -0,0 +1,140 @@
+use crate::models::content_items;
+use crate::models::content_types;
+use crate::models::prelude::{ContentItems, ContentTypes};
+use sea_orm::prelude::*;
+use sea_orm::sea_query::{Expr, Query, SimpleExpr};
+use sea_orm::{QueryOrder, QuerySelect, TransactionTrait};
+use serde::Serialize;
+use uuid::Uuid;
+
+// ゴミ箱のアイテム。削除した日時とキーを添えて返す
+#[derive(Serialize, Debug)]
+pub struct TrashedItem {
+    pub id: Uuid,
+    pub content_type_id: i32,
+    pub data: Json,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    pub deleted_by_key_id: Option<i32>,
+}
+
+impl From<content_items::Model> for TrashedItem {
+    fn from(item: content_items::Model) -> Self {
+        TrashedItem {
+            id: item.id,
+            content_type_id: item.content_type_id,
+            data: item.data,
+            deleted_at: item.deleted_at,
+            deleted_by_key_id: item.deleted_by_key_id,
+        }
+    }
+}
+
+// サービスのコンテンツタイプに属するアイテム
+fn in_service(service_id: &str) -> SimpleExpr {
+    content_items::Column::ContentTypeId.in_subquery(
+        Query::select()
+            .column(content_types::Column::Id)
+            .from(ContentTypes)
+            .and_where(content_types::Column::ServiceId.eq(service_id))
+            .to_owned(),
+    )
+}
+
+// アイテムをゴミ箱に移し、移す前のアイテムを返す。見つからないか、すでにゴミ箱にあればNone
+pub async fn trash_item(
+    db: &DatabaseConnection,
+    service_id: &str,
+    item_id: Uuid,
+    key_id: i32,
+) -> Result<Option<content_items::Model>, DbErr> {
+    let txn = db.begin().await?;
+    let Some(item) = ContentItems::find_by_id(item_id)
+        .filter(content_items::Column::DeletedAt.is_null())
+        .filter(in_service(service_id))
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    ContentItems::update_many()
+        .col_expr(
+            content_items::Column::DeletedAt,
+            Expr::value(chrono::Utc::now()),
+        )
+        .col_expr(content_items::Column::DeletedByKeyId, Expr::value(key_id))
+        .filter(content_items::Column::Id.eq(item.id))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    Ok(Some(item))
+}
+
+// 新しく削除したものから順に返す
+pub async fn list_trash(
+    db: &DatabaseConnection,
+    service_id: &str,
+) -> Result<Vec<content_items::Model>, DbErr> {
+    ContentItems::find()
+        .filter(content_items::Column::DeletedAt.is_not_null())
+        .filter(in_service(service_id))
+        .order_by_desc(content_items::Column::DeletedAt)
+        .order_by_asc(content_items::Column::Id)
+        .all(db)
+        .await
+}
+
+pub async fn restore_item(
+    db: &DatabaseConnection,
+    service_id: &str,
+    item_id: Uuid,
+) -> Result<bool, DbErr> {
+    let result = ContentItems::update_many()
+        .col_expr(
+            content_items::Column::DeletedAt,
+            Expr::value(Option::<DateTimeWithTimeZone>::None),
+        )
+        .col_expr(
+            content_items::Column::DeletedByKeyId,
+            Expr::value(Option::<i32>::None),
+        )
+        .filter(content_items::Column::Id.eq(item_id))
+        .filter(content_items::Column::DeletedAt.is_not_null())
+        .filter(in_service(service_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+// ゴミ箱にあるアイテムだけを完全に削除し、削除したアイテムを返す
+pub async fn delete_item(
+    db: &DatabaseConnection,
+    service_id: &str,
+    item_id: Uuid,
+) -> Result<Option<content_items::Model>, DbErr> {
+    let txn = db.begin().await?;
+    let Some(item) = ContentItems::find_by_id(item_id)
+        .filter(content_items::Column::DeletedAt.is_not_null())
+        .filter(in_service(service_id))
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    ContentItems::delete_by_id(item.id).exec(&txn).await?;
+    txn.commit().await?;
+    Ok(Some(item))
+}
+
+// deleted_beforeより前にゴミ箱に移したアイテムを完全に削除し、削除した件数を返す
+pub async fn purge_trash(
+    db: &DatabaseConnection,
+    deleted_before: DateTimeWithTimeZone,
+) -> Result<u64, DbErr> {
+    let result = ContentItems::delete_many()
+        .filter(content_items::Column::DeletedAt.lt(deleted_before))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
@@ -0,0 +1,160 @@
+use crate::libs::content_model::{type_name, ContentModel};
+use crate::models::services;
+use crate::router_comp::content_router::FieldType;
+use std::fmt::Write;
+use std::str::FromStr;
+
+fn ts_type(field_type: Option<FieldType>) -> &'static str {
+    match field_type {
+        Some(FieldType::Text) => "string",
+        Some(FieldType::Number) => "number",
+        // DateはRFC 3339形式の文字列として保存される
+        Some(FieldType::Date) => "ISODateString",
+        Some(FieldType::Boolean) => "boolean",
+        None => "unknown",
+    }
+}
+
+fn property_name(name: &str) -> String {
+    let is_identifier = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        name.to_string()
+    } else {
+        serde_json::to_string(name).expect("strings always serialize")
+    }
+}
+
+// サービスのコンテンツモデルから.d.tsファイルの内容を生成する
+pub fn render_declarations(service: &services::Model, content_model: &ContentModel) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "// Generated by headless-cms for service {} ({}). Do not edit by hand.",
+        serde_json::to_string(&service.name).expect("strings always serialize"),
+        service.id
+    );
+    out.push('\n');
+    out.push_str(
+        "/** RFC 3339 / ISO 8601 date-time string, e.g. \"2023-06-01T09:00:00+09:00\". */\n",
+    );
+    out.push_str("export type ISODateString = string;\n\n");
+    out.push_str("export interface ContentItem<T> {\n  id: string | null;\n  data: T;\n}\n");
+
+    for (content_type, fields) in content_model {
+        out.push('\n');
+        let _ = writeln!(
+            out,
+            "/** {} (content_type_id: {}) */",
+            content_type.name.replace("*/", "*\\/"),
+            content_type.id
+        );
+        let _ = writeln!(out, "export interface {} {{", type_name(content_type));
+        for field in fields {
+            let _ = writeln!(
+                out,
+                "  {}{}: {};",
+                property_name(&field.display_id),
+                if field.required { "" } else { "?" },
+                ts_type(FieldType::from_str(&field.field_type).ok())
+            );
+        }
+        out.push_str("}\n");
+    }
+
+    out.push_str("\nexport interface ContentTypes {\n");
+    for (content_type, _) in content_model {
+        let _ = writeln!(out, "  {}: {};", content_type.id, type_name(content_type));
+    }
+    out.push_str("}\n");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{content_types, fields};
+
+    fn field(display_id: &str, field_type: &str, required: bool) -> fields::Model {
+        fields::Model {
+            id: 1,
+            content_type_id: 2,
+            display_id: display_id.to_string(),
+            field_type: field_type.to_string(),
+            required,
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+        }
+    }
+
+    #[test]
+    fn declarations_describe_each_content_type() {
+        let service = services::Model {
+            id: "abc".to_string(),
+            name: "My \"Blog\"".to_string(),
+            deleted_at: None,
+            plan: None,
+        };
+        let content_type = content_types::Model {
+            id: 2,
+            name: "posts */ alert()".to_string(),
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+            service_id: Some("abc".to_string()),
+        };
+        let content_model = vec![(
+            content_type,
+            vec![
+                field("title", "Text", true),
+                field("views", "Number", false),
+                field("published_at", "Date", true),
+                field("draft", "Boolean", false),
+                field("cover-image", "Text", false),
+                field("legacy", "Color", false),
+            ],
+        )];
+
+        assert_eq!(
+            render_declarations(&service, &content_model),
+            r#"// Generated by headless-cms for service "My \"Blog\"" (abc). Do not edit by hand.
+
+/** RFC 3339 / ISO 8601 date-time string, e.g. "2023-06-01T09:00:00+09:00". */
+export type ISODateString = string;
+
+export interface ContentItem<T> {
+  id: string | null;
+  data: T;
+}
+
+/** posts *\/ alert() (content_type_id: 2) */
+export interface PostsAlert2 {
+  title: string;
+  views?: number;
+  published_at: ISODateString;
+  draft?: boolean;
+  "cover-image"?: string;
+  legacy?: unknown;
+}
+
+export interface ContentTypes {
+  2: PostsAlert2;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn only_identifiers_are_left_unquoted() {
+        assert_eq!(property_name("title"), "title");
+        assert_eq!(property_name("_id$2"), "_id$2");
+        assert_eq!(property_name("2nd"), "\"2nd\"");
+        assert_eq!(property_name("タイトル"), "\"タイトル\"");
+        assert_eq!(property_name(""), "\"\"");
+    }
+}
@@ -0,0 +1,185 @@
+use crate::libs::metrics::Metrics;
+use chrono::NaiveDate;
+use http::StatusCode;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct UsageKey {
+    service_id: String,
+    day: NaiveDate,
+    content_type_id: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, sqlx::FromRow)]
+pub struct UsageCounts {
+    pub requests: i64,
+    pub client_errors: i64,
+    pub server_errors: i64,
+    pub bytes_in: i64,
+    pub bytes_out: i64,
+}
+
+impl UsageCounts {
+    fn add(&mut self, other: &UsageCounts) {
+        self.requests += other.requests;
+        self.client_errors += other.client_errors;
+        self.server_errors += other.server_errors;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+// リクエストごとに書き込まず、メモリ上で数えてからflushでまとめて書き込む
+#[derive(Default)]
+pub struct UsageRecorder {
+    pending: Mutex<HashMap<UsageKey, UsageCounts>>,
+}
+
+impl UsageRecorder {
+    pub fn record(
+        &self,
+        service_id: &str,
+        content_type_id: Option<i32>,
+        status: StatusCode,
+        bytes_in: u64,
+        bytes_out: u64,
+    ) {
+        let key = UsageKey {
+            service_id: service_id.to_string(),
+            day: chrono::Utc::now().date_naive(),
+            content_type_id,
+        };
+        let counts = UsageCounts {
+            requests: 1,
+            client_errors: i64::from(status.is_client_error()),
+            server_errors: i64::from(status.is_server_error()),
+            bytes_in: bytes_in as i64,
+            bytes_out: bytes_out as i64,
+        };
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .add(&counts);
+    }
+
+    // 溜まった集計を一つの文で加算する。失敗したら次のflushで書き込めるよう戻しておく
+    pub async fn flush(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let pending = mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut service_ids = Vec::with_capacity(pending.len());
+        let mut days = Vec::with_capacity(pending.len());
+        let mut content_type_ids = Vec::with_capacity(pending.len());
+        let mut counts = Vec::with_capacity(pending.len());
+        for (key, value) in &pending {
+            service_ids.push(key.service_id.clone());
+            days.push(key.day);
+            content_type_ids.push(key.content_type_id);
+            counts.push(*value);
+        }
+        // 集計の間に完全に削除されたサービスの分は捨てる
+        let result = sqlx::query(
+            r#"INSERT INTO service_usage_daily
+            (service_id, day, content_type_id, requests, client_errors, server_errors, bytes_in, bytes_out)
+        SELECT usage.*
+        FROM UNNEST($1::text[], $2::date[], $3::integer[], $4::bigint[], $5::bigint[], $6::bigint[], $7::bigint[], $8::bigint[])
+            AS usage (service_id, day, content_type_id, requests, client_errors, server_errors, bytes_in, bytes_out)
+        WHERE EXISTS (SELECT 1 FROM services WHERE services.id = usage.service_id)
+        ON CONFLICT (service_id, day, (COALESCE(content_type_id, 0))) DO UPDATE
+        SET requests = service_usage_daily.requests + EXCLUDED.requests,
+            client_errors = service_usage_daily.client_errors + EXCLUDED.client_errors,
+            server_errors = service_usage_daily.server_errors + EXCLUDED.server_errors,
+            bytes_in = service_usage_daily.bytes_in + EXCLUDED.bytes_in,
+            bytes_out = service_usage_daily.bytes_out + EXCLUDED.bytes_out
+    "#,
+        )
+        .bind(service_ids)
+        .bind(days)
+        .bind(content_type_ids)
+        .bind(counts.iter().map(|c| c.requests).collect::<Vec<_>>())
+        .bind(counts.iter().map(|c| c.client_errors).collect::<Vec<_>>())
+        .bind(counts.iter().map(|c| c.server_errors).collect::<Vec<_>>())
+        .bind(counts.iter().map(|c| c.bytes_in).collect::<Vec<_>>())
+        .bind(counts.iter().map(|c| c.bytes_out).collect::<Vec<_>>())
+        .execute(pool)
+        .await;
+
+        if let Err(e) = result {
+            let mut current = self.pending.lock().unwrap();
+            for (key, value) in pending {
+                current.entry(key).or_default().add(&value);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+// 一定間隔で集計を書き込むタスクを起動する。停止時に書き込まれていない分は失われる
+pub fn spawn_flush_job(
+    recorder: Arc<UsageRecorder>,
+    pool: PgPool,
+    interval_secs: u64,
+    metrics: Arc<Metrics>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+            let started = Instant::now();
+            let result = recorder.flush(&pool).await;
+            if let Err(e) = &result {
+                log::warn!("failed to write usage metrics: {}", e);
+            }
+            metrics.job_finished("usage_flush", started, result.is_ok());
+        }
+    });
+}
+
+// 一日・一コンテンツタイプ分の利用量
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct DailyUsage {
+    pub day: NaiveDate,
+    pub content_type_id: Option<i32>,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+// fromからtoまで(両端を含む)の利用量を日付、コンテンツタイプの順に返す
+pub async fn daily_usage(
+    pool: &PgPool,
+    service_id: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<DailyUsage>, sqlx::Error> {
+    sqlx::query_as::<_, DailyUsage>(
+        r#"SELECT day, content_type_id, requests, client_errors, server_errors, bytes_in, bytes_out
+        FROM service_usage_daily
+        WHERE service_id = $1 AND day BETWEEN $2 AND $3
+        ORDER BY day, content_type_id NULLS FIRST
+    "#,
+    )
+    .bind(service_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+}
+
+pub fn total(usage: &[DailyUsage]) -> UsageCounts {
+    let mut total = UsageCounts::default();
+    for day in usage {
+        total.add(&day.counts);
+    }
+    total
+}
@@ -1,102 +1,116 @@
-mod libs;
-mod models;
-mod router;
-mod router_comp;
-
-use std::env;
-use crate::router::create_router;
-use anyhow::Error;
-use axum::extract::FromRef;
-use axum_extra::extract::cookie::Key;
-use hyper_tls::HttpsConnector;
-use jsonwebtoken::jwk::JwkSet;
-use sea_orm::{DatabaseConnection, SqlxPostgresConnector};
+use headless_cms::config::Config;
+use headless_cms::libs::auth;
+use headless_cms::libs::mailer::Mailer;
+use headless_cms::libs::metrics::Metrics;
+use headless_cms::libs::rate_limit::RateLimiter;
+use headless_cms::libs::retention::spawn_purge_job;
+use headless_cms::libs::schema_version::run_migrations;
+use headless_cms::libs::usage::{spawn_flush_job, UsageRecorder};
+use headless_cms::router::create_router;
+use headless_cms::AppState;
+use clap::Parser;
+use sea_orm::SqlxPostgresConnector;
 use sqlx::postgres::PgPoolOptions;
-use sqlx::PgPool;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
-use hyper::body::to_bytes;
-use hyper::Client;
 
 use tracing_subscriber::fmt;
 
-#[derive(Clone)]
-pub struct AppState {
-    postgres: DatabaseConnection,
-    pgpool: PgPool,
-    key: Key,
-    smtp_email: String,
-    smtp_password: String,
-    domain: String,
-    authority: String,
-    client_id: String,
-    audience: String,
-    issuer: String,
-    jwks: JwkSet,
-}
-
-impl FromRef<AppState> for Key {
-    fn from_ref(state: &AppState) -> Self {
-        state.key.clone()
-    }
+#[derive(Parser)]
+#[command(name = "headless-cms", about = "Run the headless CMS server")]
+struct Cli {
+    /// TOML configuration file (defaults to CMS_CONFIG, then ./config.toml if it exists)
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Print the effective configuration with secrets redacted and exit
+    #[arg(long)]
+    print_config: bool,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if cli.print_config {
+        print!("{}", config.redacted().to_toml());
+        return Ok(());
+    }
+
     fmt::init();
-    let db_address = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
     let postgres = PgPoolOptions::new()
-        .max_connections(5)
-        .idle_timeout(Some(Duration::from_secs(1)))
-        .connect(&db_address)
+        .max_connections(config.database.max_connections)
+        .idle_timeout(Some(Duration::from_secs(config.database.idle_timeout_secs)))
+        .connect(&config.database.url)
         .await
         .expect("Failed to connect to Postgres!");
 
     let conn = SqlxPostgresConnector::from_sqlx_postgres_pool(postgres.clone());
 
-    sqlx::migrate!()
-        .run(&postgres)
+    run_migrations(&postgres)
         .await
         .expect("Failed to run migrations!");
 
-    let smtp_email = std::env::var("SMTP_EMAIL").expect("You need to set your SMTP_EMAIL secret!");
-
-    let smtp_password =
-        std::env::var("SMTP_PASSWORD").expect("You need to set your SMTP_PASSWORD secret!");
-
-    let domain = std::env::var("DOMAIN").expect("You need to set your DOMAIN secret!");
-
-    let authority = std::env::var("AUTHORITY").expect("AUTHORITY must be set");
-
-    let audience = std::env::var("AUDIENCE").expect("AUDIENCE must be set");
-
-    let issuer = std::env::var("ISSUER").expect("ISSUER must be set");
-    let client_id = std::env::var("AUTH0_CLIENT_ID").expect("CLIENT_ID must be set");
-    let client_secret = std::env::var("AUTH0_CLIENT_SECRET").expect("CLIENT_SECRET must be set");
-    let jwks = get_jwks(&authority).await.expect("failed to fetch jwks");
+    if config.session.keys.is_empty() {
+        println!("session.keys is not set; sessions will not survive a restart (generate one with `openssl rand -base64 64`)");
+    }
+    let keys = Arc::new(auth::SessionKeys::from_config(&config.session));
+    let auth = match &config.auth {
+        Some(auth_config) => Some(
+            auth::from_config(auth_config, &conn, &keys)
+                .await
+                .expect("failed to set up authentication"),
+        ),
+        None => {
+            println!("auth is not configured; the management API only accepts session cookies");
+            None
+        }
+    };
+    let mailer = match &config.mail {
+        Some(mail_config) => {
+            Some(Mailer::start(mail_config).expect("failed to set up the mail transport"))
+        }
+        None => {
+            println!("mail is not configured; password reset emails are disabled");
+            None
+        }
+    };
 
+    let metrics = Arc::new(Metrics::default());
+    spawn_purge_job(conn.clone(), config.retention.clone(), metrics.clone());
+    let usage = Arc::new(UsageRecorder::default());
+    spawn_flush_job(
+        usage.clone(),
+        postgres.clone(),
+        config.usage.flush_interval_secs,
+        metrics.clone(),
+    );
+
+    let port = config.server.port;
     let state = AppState {
         postgres: conn,
         pgpool: postgres,
-        key: Key::generate(),
-        smtp_email,
-        smtp_password,
-        domain,
-        authority,
-        client_id,
-        audience,
-        issuer,
-        jwks,
+        keys,
+        config: Arc::new(config),
+        auth,
+        mailer,
+        rate_limiter: Arc::new(RateLimiter::default()),
+        usage,
+        metrics,
     };
 
     let router = create_router(state);
 
-    let port = std::env::var("PORT")
-        .unwrap_or_else(|_| "8080".to_string())
-        .parse::<u16>()
-        .expect("PORT must be a number!");
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     println!("Listening on Port: {}", port);
     axum::Server::bind(&addr)
@@ -106,18 +120,3 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
-
-async fn get_jwks(authority: &str) -> anyhow::Result<JwkSet, Error> {
-    //fetch jwks
-    let jwks_uri = format!("{}{}", authority, "/.well-known/jwks.json")
-        .parse()
-        .expect("Invalid uri");
-
-    let https = HttpsConnector::new();
-    let client = Client::builder().build::<_, hyper::Body>(https);
-    let response = client.get(jwks_uri).await.expect("failed to fetch jwks");
-
-    let body_bytes = to_bytes(response.into_body()).await?;
-    let jwks: JwkSet = serde_json::from_slice(&body_bytes)?;
-    Ok(jwks)
-}
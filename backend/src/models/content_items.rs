@@ -13,10 +13,20 @@ pub struct Model {
     pub data: Json,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    pub deleted_by_key_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::api_keys::Entity",
+        from = "Column::DeletedByKeyId",
+        to = "super::api_keys::Column::Id",
+        on_update = "NoAction",
+        on_delete = "SetNull"
+    )]
+    ApiKeys,
     #[sea_orm(
         belongs_to = "super::content_types::Entity",
         from = "Column::ContentTypeId",
@@ -27,6 +37,12 @@ pub enum Relation {
     ContentTypes,
 }
 
+impl Related<super::api_keys::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKeys.def()
+    }
+}
+
 impl Related<super::content_types::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::ContentTypes.def()
@@ -2,11 +2,17 @@
 
 pub mod prelude;
 
+pub mod api_keys;
+pub mod audit_logs;
 pub mod content_items;
 pub mod content_types;
 pub mod fields;
+pub mod password_reset_tokens;
+pub mod recovery_codes;
 pub mod role_permissions;
 pub mod roles;
+pub mod service_invitations;
+pub mod service_members;
 pub mod services;
 pub mod sessions;
 pub mod users;
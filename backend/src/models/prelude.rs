@@ -1,10 +1,16 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.3
 
+pub use super::api_keys::Entity as ApiKeys;
+pub use super::audit_logs::Entity as AuditLogs;
 pub use super::content_items::Entity as ContentItems;
 pub use super::content_types::Entity as ContentTypes;
 pub use super::fields::Entity as Fields;
+pub use super::password_reset_tokens::Entity as PasswordResetTokens;
+pub use super::recovery_codes::Entity as RecoveryCodes;
 pub use super::role_permissions::Entity as RolePermissions;
 pub use super::roles::Entity as Roles;
+pub use super::service_invitations::Entity as ServiceInvitations;
+pub use super::service_members::Entity as ServiceMembers;
 pub use super::services::Entity as Services;
 pub use super::sessions::Entity as Sessions;
 pub use super::users::Entity as Users;
@@ -10,12 +10,13 @@ pub struct Model {
     pub id: i32,
     pub name: String,
     pub service_id: String,
-    #[sea_orm(column_type = "Text", unique)]
-    pub api_key: String,
+    pub is_admin: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::api_keys::Entity")]
+    ApiKeys,
     #[sea_orm(has_many = "super::role_permissions::Entity")]
     RolePermissions,
     #[sea_orm(
@@ -28,6 +29,12 @@ pub enum Relation {
     Services,
 }
 
+impl Related<super::api_keys::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKeys.def()
+    }
+}
+
 impl Related<super::role_permissions::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::RolePermissions.def()
@@ -10,8 +10,8 @@ pub struct Model {
     pub id: String,
     #[sea_orm(column_type = "Text")]
     pub name: String,
-    #[sea_orm(column_type = "Text", unique)]
-    pub api_key: String,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    pub plan: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -20,6 +20,10 @@ pub enum Relation {
     ContentTypes,
     #[sea_orm(has_many = "super::roles::Entity")]
     Roles,
+    #[sea_orm(has_many = "super::service_invitations::Entity")]
+    ServiceInvitations,
+    #[sea_orm(has_many = "super::service_members::Entity")]
+    ServiceMembers,
 }
 
 impl Related<super::content_types::Entity> for Entity {
@@ -34,4 +38,16 @@ impl Related<super::roles::Entity> for Entity {
     }
 }
 
+impl Related<super::service_invitations::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ServiceInvitations.def()
+    }
+}
+
+impl Related<super::service_members::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ServiceMembers.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -10,8 +10,12 @@ pub struct Model {
     pub id: i32,
     #[sea_orm(unique)]
     pub session_id: String,
-    #[sea_orm(unique)]
     pub user_id: i32,
+    pub created_at: DateTimeWithTimeZone,
+    pub expires_at: DateTimeWithTimeZone,
+    pub last_seen_at: DateTimeWithTimeZone,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
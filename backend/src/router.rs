@@ -1,15 +1,37 @@
-use crate::models::sessions;
 use crate::router_comp::content_router::update_content_item;
+use crate::libs::api_keys;
+use crate::libs::audit::RequestId;
+use crate::libs::metrics::{self, Metrics, PoolStatus, Snapshot};
+use crate::libs::rate_limit::{self, QuotaUsage, RateLimitStatus};
+use crate::libs::auth::{bearer_token, constant_time_eq, AuthProvider, LocalSessionProvider};
 use crate::router_comp::{
-    auth_router::{forgot_password, login, logout, register},
+    audit_router::{export_audit_logs, list_audit_logs},
+    auth_router::{
+        activate_totp, auth_check, enroll_totp, forgot_password, list_sessions, login, login_totp,
+        logout, register, resend_verification, reset_password, revoke_session, verify_email,
+    },
     content_router::{
         create_content_item, create_content_type, create_field, delete_content_item,
-        get_content_item, get_content_items, get_content_type,
+        delete_trashed_item, export_content_items_csv, get_content_item, get_content_items,
+        get_content_type, get_content_type_schema, get_trash, import_content_items_csv,
+        import_content_type, restore_trashed_item,
+    },
+    schema_router::{get_management_openapi, get_service_openapi, get_service_typescript},
+    member_router::{
+        accept_invitation, create_invitation, list_invitations, list_members, remove_member,
+        revoke_invitation, update_member,
+    },
+    service_router::{
+        create_role, create_service, delete_role, delete_service, export_service_archive,
+        get_role, import_service_archive, issue_api_key, list_api_keys, list_roles, list_services,
+        restore_service, revoke_api_key, rotate_api_key, update_role, Permission,
     },
-    service_router::{create_role, create_service, delete_service},
+    usage_router::get_service_usage,
 };
-use crate::{models, AppState};
-use axum::extract::Path;
+use crate::models::services;
+use crate::AppState;
+use axum::body::HttpBody;
+use axum::extract::{DefaultBodyLimit, MatchedPath, Path};
 use axum::{
     extract::State,
     http::{Request, StatusCode},
@@ -19,49 +41,94 @@ use axum::{
     Router,
 };
 
-use http::header::CONTENT_TYPE;
+use http::header::{CONTENT_TYPE, RETRY_AFTER};
 use http::{
     header::{ACCEPT, AUTHORIZATION, ORIGIN},
-    HeaderValue, Method,
+    HeaderMap, HeaderName, HeaderValue, Method,
 };
-use hyper::{Body, Client};
-use hyper::body::to_bytes;
-use hyper_tls::HttpsConnector;
-use jsonwebtoken::{Algorithm, decode, decode_header, DecodingKey, Validation};
 use log::info;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 
 use serde::Deserialize;
-use serde_json::Value;
 
-use crate::router_comp::auth_router::auth_check;
-use tower::limit::RateLimitLayer;
 use tower_http::cors::CorsLayer;
 use tower_http::services::ServeDir;
+use std::sync::Arc;
+use std::time::Instant;
 use uuid::Uuid;
 
 pub fn create_router(state: AppState) -> Router {
+    let static_dir = state.config.storage.static_dir.clone();
+    let metrics_router = Router::new()
+        .route("/metrics", get(export_metrics))
+        .with_state(state.clone());
     let api_router = api_router(state);
-    let dir_router = Router::new().nest_service("/", ServeDir::new("../frontend/dist"));
+    let dir_router = Router::new().nest_service("/", ServeDir::new(static_dir));
 
     //API ルーターを「/api」ルートにネスト。
-    Router::new().nest("/", dir_router).nest("/api", api_router)
+    Router::new()
+        .merge(metrics_router)
+        .nest("/", dir_router)
+        .nest("/api", api_router)
 }
 
 pub fn api_router(state: AppState) -> Router {
     let cors = CorsLayer::new()
         .allow_credentials(true)
-        .allow_methods(vec![Method::GET, Method::POST, Method::PUT, Method::DELETE])
-        .allow_headers(vec![ACCEPT, AUTHORIZATION, ORIGIN, CONTENT_TYPE])
-        .allow_origin(state.domain.parse::<HeaderValue>().unwrap());
+        .allow_methods(vec![
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_headers(vec![
+            ACCEPT,
+            AUTHORIZATION,
+            ORIGIN,
+            CONTENT_TYPE,
+            HeaderName::from_static(REQUEST_ID_HEADER),
+        ])
+        .expose_headers(vec![
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static(RATE_LIMIT_LIMIT_HEADER),
+            HeaderName::from_static(RATE_LIMIT_REMAINING_HEADER),
+            HeaderName::from_static(RATE_LIMIT_RESET_HEADER),
+            HeaderName::from_static(QUOTA_LIMIT_HEADER),
+            HeaderName::from_static(QUOTA_REMAINING_HEADER),
+            RETRY_AFTER,
+        ])
+        .allow_origin(
+            state
+                .config
+                .server
+                .cors_origins
+                .iter()
+                .map(|origin| origin.parse::<HeaderValue>().unwrap())
+                .collect::<Vec<_>>(),
+        );
 
     let content_router = Router::new()
         .route("/content_types", post(create_content_type))
+        .route("/content_types/import", post(import_content_type))
         .route("/content_types/:content_type_id", get(get_content_type))
+        .route(
+            "/content_types/:content_type_id/schema",
+            get(get_content_type_schema),
+        )
         .route("/:content_type_id/fields", post(create_field))
         .route("/:content_type_id/content_items", post(create_content_item))
         .route("/:content_type_id/content_items", get(get_content_items))
+        .route(
+            "/:content_type_id/content_items.csv",
+            get(export_content_items_csv),
+        )
+        .route(
+            "/:content_type_id/content_items/import",
+            post(import_content_items_csv),
+        )
         .route("/content_items/:content_item_id", get(get_content_item))
+        .route("/openapi.json", get(get_service_openapi))
+        .route("/types.d.ts", get(get_service_typescript))
         .route(
             "/content_items/:content_item_id",
             patch(update_content_item),
@@ -70,82 +137,208 @@ pub fn api_router(state: AppState) -> Router {
             "/content_items/:content_item_id",
             delete(delete_content_item),
         )
+        .route("/trash", get(get_trash))
+        .route("/trash/:content_item_id", delete(delete_trashed_item))
+        .route(
+            "/trash/:content_item_id/restore",
+            post(restore_trashed_item),
+        )
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
             validate_api_key,
         ));
     let create_service = Router::new()
-        .route("/", post(create_service))
+        .route("/", post(create_service).get(list_services))
         .route("/health", get(health_check))
-        .route("/:service_id/roles", post(create_role))
+        .route("/invitations/accept", post(accept_invitation))
+        .route("/:service_id/restore", post(restore_service))
+        .route("/:service_id/roles", post(create_role).get(list_roles))
+        .route(
+            "/:service_id/roles/:role_id",
+            get(get_role).patch(update_role).delete(delete_role),
+        )
+        .route(
+            "/:service_id/roles/:role_id/keys",
+            get(list_api_keys).post(issue_api_key),
+        )
+        .route(
+            "/:service_id/roles/:role_id/keys/:key_id",
+            delete(revoke_api_key),
+        )
+        .route(
+            "/:service_id/roles/:role_id/keys/:key_id/rotate",
+            post(rotate_api_key),
+        )
+        .route("/:service_id/members", get(list_members))
+        .route(
+            "/:service_id/members/:member_id",
+            patch(update_member).delete(remove_member),
+        )
+        .route(
+            "/:service_id/invitations",
+            post(create_invitation).get(list_invitations),
+        )
+        .route(
+            "/:service_id/invitations/:invitation_id",
+            delete(revoke_invitation),
+        )
+        .route("/:service_id/export", get(export_service_archive))
+        .route(
+            "/:service_id/import",
+            post(import_service_archive).layer(DefaultBodyLimit::max(64 * 1024 * 1024)),
+        )
+        .route("/:service_id/audit_logs", get(list_audit_logs))
+        .route("/:service_id/audit_logs/export", get(export_audit_logs))
+        .route("/:service_id/usage", get(get_service_usage))
         .route_layer(middleware::from_fn_with_state(state.clone(), validate_session));
 
+    let session_router = Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:session_id", delete(revoke_session))
+        .route("/resend-verification", post(resend_verification))
+        .route("/totp", post(enroll_totp))
+        .route("/totp/activate", post(activate_totp))
+        .route_layer(middleware::from_fn_with_state(state.clone(), validate_session));
+
+    let auth_router = Router::new()
+        .route("/register", post(register))
+        .route("/login", post(login))
+        .route("/login/totp", post(login_totp))
+        .route("/logout", post(logout))
+        .route("/forgot_password", post(forgot_password))
+        .route("/reset-password", post(reset_password))
+        .route("/verify-email", post(verify_email))
+        .route("/auth_check", get(auth_check))
+        .merge(session_router);
+
     let service_router = Router::new()
-        .route("/services/:service_id", delete(delete_service))
+        .route(
+            "/services/:service_id",
+            delete(delete_service)
+                .layer(middleware::from_fn_with_state(state.clone(), validate_session)),
+        )
         .nest("/:service_id", content_router);
 
     Router::new()
         .route("/health", get(health_check))
+        .route("/openapi.json", get(get_management_openapi))
+        .nest("/auth", auth_router)
         .nest("/service", create_service)
         .nest("/services", service_router)
+        .layer(middleware::from_fn_with_state(
+            state.metrics.clone(),
+            track_requests,
+        ))
         .with_state(state)
+        .layer(middleware::from_fn(assign_request_id))
         .layer(cors)
 }
 
 
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const RATE_LIMIT_LIMIT_HEADER: &str = "x-ratelimit-limit";
+const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
+const RATE_LIMIT_RESET_HEADER: &str = "x-ratelimit-reset";
+const QUOTA_LIMIT_HEADER: &str = "x-quota-limit";
+const QUOTA_REMAINING_HEADER: &str = "x-quota-remaining";
+
+// 呼び出し側がX-Request-Idを付けていれば引き継ぎ、なければ生成する。監査ログと応答ヘッダに使う
+async fn assign_request_id<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    request
+        .extensions_mut()
+        .insert(RequestId(request_id.clone()));
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
 pub async fn health_check() -> Response {
     (StatusCode::OK, "OK!").into_response()
 }
 
+// ルートのパターン、メソッド、ステータスごとに応答時間を数える
+async fn track_requests<B>(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| metrics::UNMATCHED_ROUTE.to_string());
+    let response = next.run(request).await;
+    metrics.observe_request(
+        method.as_str(),
+        &route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+
+// Prometheus用。metrics.bearer_tokenが設定されていればそのトークンを求める
+pub async fn export_metrics(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(expected) = &state.config.metrics.bearer_token {
+        let authorized = bearer_token(&headers)
+            .is_ok_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()));
+        if !authorized {
+            return (StatusCode::UNAUTHORIZED, "invalid metrics token".to_string()).into_response();
+        }
+    }
+
+    let snapshot = Snapshot {
+        pool: PoolStatus {
+            size: state.pgpool.size(),
+            idle: state.pgpool.num_idle(),
+            max_connections: state.config.database.max_connections,
+        },
+        jwks: state.auth.as_ref().and_then(|auth| auth.jwks_status()),
+        mail_queue_depth: state.mailer.as_ref().map(|mailer| mailer.queue_depth()),
+    };
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&snapshot),
+    )
+        .into_response()
+}
+
 pub async fn validate_session<B>(
     State(state): State<AppState>,
     // Request<B> と Next<B> は axum の関数からのミドルウェアに必要な型
-    request: Request<B>,
+    mut request: Request<B>,
     next: Next<B>,
 ) -> Response {
-    let jwks = &state.jwks;
-    //AUTHORIZATION ヘッダを取得
-    let Some(authorization_header) = request.headers().get("AUTHORIZATION") else {
-        return (StatusCode::UNAUTHORIZED, "no authorization header".to_string()).into_response() };
-
-    let Ok(authorization) = authorization_header.to_str() else { return StatusCode::UNAUTHORIZED.into_response() };
-
-    // jwt tokenだけ剥がす
-    let Some(jwt_token) = authorization.strip_prefix("Bearer ") else {
-        return (StatusCode::UNAUTHORIZED, "No Bearer".to_string()).into_response() };
-    // tokenをdecodeする
-    let Ok(header) = decode_header(jwt_token) else { return (StatusCode::UNAUTHORIZED, "failed to decode header".to_string()).into_response() };
-    //kidを取得
-    let Some(kid) = header.kid else { return (StatusCode::UNAUTHORIZED, "no valied kid".to_string()).into_response() };
-    //kidに対応するjwkを取得
-    let Some(jwk) = jwks.find(kid.as_str()) else { return (StatusCode::UNAUTHORIZED, "no valid jwk".to_string()).into_response() };
-    // jwkからDecodingKeyを生成
-    let decoding_key = DecodingKey::from_jwk(jwk).expect("failed to decode key");
-    // RS256を指定
-    let mut validation = Validation::new(Algorithm::RS256);
-    validation.set_audience(&[&state.audience]);
-    validation.set_issuer(&[&state.issuer]);
-
-    match decode::<Value>(jwt_token, &decoding_key, &validation) {
-        // JWTのデコードと検証を行う
-        Ok(_value) => {
-            //auth0にユーザ情報の問い合わせを行う
-            let userinfo_uri = format!("{}{}", state.issuer, "userinfo");
-
-            let https = HttpsConnector::new();
-            let client = Client::builder().build::<_, Body>(https);
-            let req = Request::builder()
-                .method(Method::GET)
-                .uri(userinfo_uri)
-                .header("content-type", "application/json")
-                .header("authorization", format!("Bearer {}", jwt_token))
-                .body(Body::empty()).expect("failed to build request");
-            let response = client.request(req).await.expect("failed to fetch userinfo");
-            let body_bytes = to_bytes(response.into_body()).await.expect("failed to read body");
-            let json_body: Value = serde_json::from_slice(&body_bytes).unwrap();
-            info!("{:?}", json_body);
-            next.run(request).await},
-        Err(_) => StatusCode::UNAUTHORIZED.into_response(),
+    // Authorizationヘッダがあれば設定された認証プロバイダで、なければセッションクッキーで認証する
+    let result = if request.headers().contains_key(AUTHORIZATION) {
+        let Some(auth) = &state.auth else {
+            return (StatusCode::SERVICE_UNAVAILABLE, "bearer authentication is not configured".to_string()).into_response() };
+        auth.authenticate(request.headers()).await
+    } else {
+        LocalSessionProvider::new(state.postgres.clone(), state.keys.clone())
+            .authenticate(request.headers())
+            .await
+    };
+
+    match result {
+        // 認証済みの利用者をハンドラから参照できるようにする
+        Ok(principal) => {
+            info!("authenticated {} user {}", principal.provider, principal.subject);
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        Err(e) => e.into_response(),
     }
 }
 
@@ -156,7 +349,7 @@ pub struct PathParams {
     pub content_item_id: Option<Uuid>,
 }
 
-async fn validate_api_key<B>(
+async fn validate_api_key<B: HttpBody>(
     State(state): State<AppState>,
     Path(params): Path<PathParams>,
     request: Request<B>,
@@ -169,45 +362,167 @@ async fn validate_api_key<B>(
         .and_then(|value| value.to_str().ok())
         .unwrap_or_default();
 
-    //作成されたAPIキーを見つける
-    let find_service = models::prelude::Services::find_by_id(&params.service_id)
-        .one(&state.postgres)
-        .await;
-
-    match find_service {
-        Ok(service) => {
-            //APIキーが一致したら、リクエストを次のミドルウェアに渡す
-            if api_key == service.unwrap().api_key {
-                // メソッドとサービスIDを使って、リクエストされたメソッドが許可されているか確認する
-
-                let method = request.method();
-
-                let has_permission = sqlx::query(
-                    r#"SELECT EXISTS (
-                    SELECT 1
-                    FROM roles r
-                    JOIN role_permissions rp ON r.id = rp.role_id
-                    WHERE r.service_id = $1 AND rp.permission = $2
-                )
-                "#,
-                )
-                .bind(params.service_id)
-                .bind(method.as_str())
-                .fetch_one(&state.pgpool)
-                .await;
-
-                match has_permission {
-                    Ok(_) => next.run(request).await, // 許可されている
-                    Err(_) => (
-                        StatusCode::FORBIDDEN,
-                        "メソッドが許可されていません".to_string(),
+    //サービスのロールに発行された、失効も期限切れもしていないキーを探す
+    let found = api_keys::authenticate(&state.postgres, &params.service_id, api_key).await;
+    let (key, service) = match found {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            return (StatusCode::FORBIDDEN, "APIキーが無効です".to_string()).into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("APIキーを確認できませんでした: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    // 認証できたリクエストは、制限や権限で拒否したものも含めて利用量に数える。
+    // 本文の大きさは長さが前もって分かるものだけを数える
+    let content_type_id = params
+        .content_type_id
+        .and_then(|id| i32::try_from(id).ok());
+    let bytes_in = request.body().size_hint().exact().unwrap_or(0);
+    let response = call_with_key(&state, key, &service, request, next).await;
+    state.usage.record(
+        &service.id,
+        content_type_id,
+        response.status(),
+        bytes_in,
+        response.body().size_hint().exact().unwrap_or(0),
+    );
+    response
+}
+
+// レート制限、権限、月間の上限を確認してからハンドラを呼ぶ
+async fn call_with_key<B>(
+    state: &AppState,
+    key: crate::models::api_keys::Model,
+    service: &services::Model,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    // サービスのプランに従って、キーごとの呼び出し回数を制限する
+    let plan = state.config.rate_limit.plan(service.plan.as_deref()).cloned();
+    let limit_status = plan
+        .as_ref()
+        .map(|plan| state.rate_limiter.check(key.id, plan));
+    if let Some(status) = limit_status.filter(|status| !status.allowed) {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            "リクエストが多すぎます。しばらく待ってから再度お試しください".to_string(),
+        )
+            .into_response();
+        insert_rate_limit_headers(response.headers_mut(), Some(&status), None);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(status.retry_after_secs));
+        return response;
+    }
+
+    // リクエストされたメソッドの権限をキーのロールが持っているか確認する
+    let forbidden = || {
+        (
+            StatusCode::FORBIDDEN,
+            "メソッドが許可されていません".to_string(),
+        )
+            .into_response()
+    };
+    let Some(permission) = Permission::for_method(request.method()) else {
+        return forbidden();
+    };
+    let has_permission = sqlx::query_scalar::<_, bool>(
+        r#"SELECT EXISTS (
+        SELECT 1
+        FROM role_permissions
+        WHERE role_id = $1 AND permission = $2
+    )
+    "#,
+    )
+    .bind(key.role_id)
+    .bind(permission.to_string())
+    .fetch_one(&state.pgpool)
+    .await;
+
+    match has_permission {
+        Ok(true) => {}
+        Ok(false) => return forbidden(),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("権限を確認できませんでした: {}", e),
+            )
+                .into_response()
+        }
+    }
+
+    // 権限のあるリクエストだけを月間の上限に数える
+    let quota = match plan.as_ref().and_then(|plan| plan.monthly_quota) {
+        Some(quota) => {
+            match rate_limit::count_monthly_request(&state.pgpool, &service.id, quota).await {
+                Ok(Some(usage)) => Some(usage),
+                Ok(None) => {
+                    let mut response = (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        "今月のリクエスト数の上限に達しました".to_string(),
                     )
-                        .into_response(), // 許可されていない
+                        .into_response();
+                    let usage = QuotaUsage { quota, used: quota };
+                    insert_rate_limit_headers(
+                        response.headers_mut(),
+                        limit_status.as_ref(),
+                        Some(&usage),
+                    );
+                    response.headers_mut().insert(
+                        RETRY_AFTER,
+                        HeaderValue::from(rate_limit::seconds_until_next_month()),
+                    );
+                    return response;
+                }
+                Err(e) => {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("リクエスト数を記録できませんでした: {}", e),
+                    )
+                        .into_response()
                 }
-            } else {
-                (StatusCode::FORBIDDEN).into_response()
             }
         }
-        Err(_) => (StatusCode::BAD_REQUEST).into_response(),
+        None => None,
+    };
+
+    // 最終利用日時の記録を待たずにリクエストを処理する
+    let db = state.postgres.clone();
+    let key_id = key.id;
+    tokio::spawn(async move {
+        if let Err(e) = api_keys::record_use(&db, key_id).await {
+            log::warn!("failed to record the use of API key {}: {}", key_id, e);
+        }
+    });
+    // 呼び出したキーをハンドラから参照できるようにする
+    request.extensions_mut().insert(key);
+    let mut response = next.run(request).await;
+    insert_rate_limit_headers(response.headers_mut(), limit_status.as_ref(), quota.as_ref());
+    response
+}
+
+// 残りの呼び出し回数をX-RateLimit-*とX-Quota-*ヘッダで返す
+fn insert_rate_limit_headers(
+    headers: &mut HeaderMap,
+    status: Option<&RateLimitStatus>,
+    quota: Option<&QuotaUsage>,
+) {
+    if let Some(status) = status {
+        headers.insert(RATE_LIMIT_LIMIT_HEADER, HeaderValue::from(status.limit));
+        headers.insert(RATE_LIMIT_REMAINING_HEADER, HeaderValue::from(status.remaining));
+        headers.insert(RATE_LIMIT_RESET_HEADER, HeaderValue::from(status.reset_secs));
+    }
+    if let Some(quota) = quota {
+        headers.insert(QUOTA_LIMIT_HEADER, HeaderValue::from(quota.quota));
+        headers.insert(
+            QUOTA_REMAINING_HEADER,
+            HeaderValue::from(quota.quota.saturating_sub(quota.used)),
+        );
     }
 }
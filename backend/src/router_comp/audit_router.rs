@@ -0,0 +1,117 @@
+use axum::{
+    body::StreamBody,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::libs::audit::{self, AuditFilter};
+use crate::libs::auth::Principal;
+use crate::libs::membership::{require_role, MemberRole};
+use crate::models::audit_logs;
+use crate::AppState;
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 500;
+
+#[derive(Deserialize)]
+pub struct AuditPage {
+    // 前のページのnext_cursor
+    cursor: Option<i64>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct AuditLogPage {
+    entries: Vec<audit_logs::Model>,
+    // 続きがなければnull
+    next_cursor: Option<i64>,
+}
+
+// 監査ログはサービスの管理者だけが読める
+async fn require_admin(
+    state: &AppState,
+    principal: &Principal,
+    service_id: &str,
+) -> Option<Response> {
+    require_role(&state.postgres, principal, service_id, MemberRole::Admin)
+        .await
+        .err()
+        .map(IntoResponse::into_response)
+}
+
+pub async fn list_audit_logs(
+    Path(service_id): Path<String>,
+    Query(filter): Query<AuditFilter>,
+    Query(page): Query<AuditPage>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Some(response) = require_admin(&state, &principal, &service_id).await {
+        return response;
+    }
+    let limit = page
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    match audit::list(
+        &state.postgres,
+        Some(&service_id),
+        &filter,
+        page.cursor,
+        limit,
+    )
+    .await
+    {
+        Ok(entries) => {
+            let next_cursor = if entries.len() as u64 == limit {
+                entries.last().map(|entry| entry.id)
+            } else {
+                None
+            };
+            Json(AuditLogPage {
+                entries,
+                next_cursor,
+            })
+            .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get audit logs: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+// 条件に合う記録を古い順に1行1件のJSONで返す
+pub async fn export_audit_logs(
+    Path(service_id): Path<String>,
+    Query(filter): Query<AuditFilter>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Some(response) = require_admin(&state, &principal, &service_id).await {
+        return response;
+    }
+    let disposition = format!("attachment; filename=\"{}-audit.ndjson\"", service_id);
+    let entries = audit::export(state.postgres.clone(), Some(service_id), filter);
+    let body = StreamBody::new(entries.map(|entry| {
+        entry.map(|entry| {
+            let mut line = serde_json::to_string(&entry).expect("audit logs always serialize");
+            line.push('\n');
+            line
+        })
+    }));
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
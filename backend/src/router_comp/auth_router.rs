@@ -1,27 +1,33 @@
-use crate::models::prelude::{Sessions, Users};
+use crate::libs::audit::{Actor, AuditEvent, AuditLog};
+use crate::libs::auth::{totp, AuthError, AuthProvider, LocalSessionProvider, Principal};
+use crate::libs::generate_random_key::generate_key;
+use crate::libs::mailer::{Mailer, Template};
+use crate::libs::token_hash::hash_token;
+use crate::models::password_reset_tokens::ActiveModel as PasswordResetTokenModel;
+use crate::models::prelude::{PasswordResetTokens, RecoveryCodes, Sessions, Users};
+use crate::models::recovery_codes::ActiveModel as RecoveryCodeModel;
 use crate::models::sessions::ActiveModel as SessionModel;
-use crate::models::sessions::Entity as SessionEntity;
 use crate::models::users::ActiveModel as UserModel;
-use crate::models::{sessions, users};
-use crate::{models, AppState};
+use crate::models::{password_reset_tokens, recovery_codes, sessions, users};
+use crate::AppState;
 use anyhow::Result;
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Path, State},
+    http::{header::RETRY_AFTER, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use axum_extra::extract::cookie::{Cookie, PrivateCookieJar, SameSite};
-use lettre::message::header::ContentType;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
-use rand::distributions::{Alphanumeric, DistString};
 use sea_orm::prelude::*;
+use sea_orm::sea_query::Expr;
 use sea_orm::ActiveValue::Set;
-use sea_orm::{sea_query, IntoActiveModel, NotSet};
-use serde::Deserialize;
+use sea_orm::{Condition, IntoActiveModel, NotSet, QueryOrder, TransactionTrait};
+use serde::{Deserialize, Serialize};
 use time::Duration;
 
+// セッションIDを入れる暗号化クッキーの名前
+pub const SESSION_COOKIE: &str = "foo";
+
 #[derive(Deserialize)]
 pub struct RegisterDetails {
     username: String,
@@ -35,26 +41,97 @@ pub struct LoginDetails {
     password: String,
 }
 
+// メールアドレス確認リンクの有効期間
+const VERIFICATION_TOKEN_TTL_HOURS: i64 = 24;
+// 確認メールの再送はこの間隔より頻繁には行わない
+const VERIFICATION_RESEND_INTERVAL_SECONDS: i64 = 60;
+const VERIFY_EMAIL_PURPOSE: &str = "verify_email";
+
+#[derive(Deserialize)]
+pub struct VerifyEmailDetails {
+    token: String,
+}
+
+fn send_verification_email(
+    state: &AppState,
+    mailer: &Mailer,
+    headers: &HeaderMap,
+    user_id: i32,
+    email: &str,
+) -> Result<()> {
+    let token = state.keys.sign_user_token(
+        VERIFY_EMAIL_PURPOSE,
+        user_id,
+        email,
+        chrono::Duration::hours(VERIFICATION_TOKEN_TTL_HOURS),
+    );
+    let link = state
+        .config
+        .server
+        .frontend_link(&format!("/verify-email?token={}", token));
+    let template = Template::EmailVerification {
+        link: &link,
+        ttl_hours: VERIFICATION_TOKEN_TTL_HOURS,
+    };
+    mailer.send(email, mailer.locale(headers), template)
+}
+
 pub async fn register(
     State(state): State<AppState>,
+    mut audit: AuditLog,
+    headers: HeaderMap,
     Json(new_user): Json<RegisterDetails>,
 ) -> impl IntoResponse {
     //空パスワードを回避する。ログイン時はハッシュ化されたパスワードを検証。
     let hashed_password = bcrypt::hash(new_user.password, 10).unwrap();
 
+    // メールを送れない構成では確認のしようがないので、最初から確認済みにする
+    let now: DateTimeWithTimeZone = chrono::Utc::now().into();
+    let (email_verified_at, verification_sent_at) = match state.mailer {
+        Some(_) => (None, Some(now)),
+        None => (Some(now), None),
+    };
+
     let user = UserModel {
         id: Default::default(),
         username: Set(new_user.username),
         email: Set(new_user.email),
         password: Set(hashed_password),
         createdat: NotSet,
+        email_verified_at: Set(email_verified_at),
+        verification_sent_at: Set(verification_sent_at),
+        totp_secret: NotSet,
+        totp_enabled_at: NotSet,
+        totp_last_step: NotSet,
+        totp_failures: NotSet,
+        totp_failed_at: NotSet,
     };
 
     let res = user.insert(&state.postgres);
 
     //作成成功したら Created status code, 失敗したら Internal Server Error status code を返す。
     match res.await {
-        Ok(_) => (StatusCode::CREATED, "作成されました".to_string()).into_response(),
+        Ok(user) => {
+            if let Some(mailer) = &state.mailer {
+                if let Err(e) =
+                    send_verification_email(&state, mailer, &headers, user.id, &user.email)
+                {
+                    eprintln!("{}", e);
+                }
+            }
+            audit.set_actor(Actor::local_user(user.id));
+            audit
+                .record(
+                    AuditEvent::new("user.register", "user")
+                        .target(user.id)
+                        .after(&serde_json::json!({
+                            "username": user.username,
+                            "email": user.email,
+                        })),
+                )
+                .await;
+            (StatusCode::CREATED, "作成されました".to_string()).into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("作成できませんでした: {}", e),
@@ -63,11 +140,268 @@ pub async fn register(
     }
 }
 
+pub async fn verify_email(
+    State(state): State<AppState>,
+    mut audit: AuditLog,
+    Json(details): Json<VerifyEmailDetails>,
+) -> Response {
+    // 登録後にメールアドレスが変わっていれば、古いアドレスに送ったリンクは使えない
+    let Some((user_id, email)) = state
+        .keys
+        .verify_user_token(VERIFY_EMAIL_PURPOSE, &details.token)
+    else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "リンクが無効か、有効期限が切れています".to_string(),
+        )
+            .into_response();
+    };
+
+    let result = Users::update_many()
+        .col_expr(
+            users::Column::EmailVerifiedAt,
+            Expr::cust("COALESCE(email_verified_at, CURRENT_TIMESTAMP)"),
+        )
+        .filter(users::Column::Id.eq(user_id))
+        .filter(users::Column::Email.eq(email))
+        .exec(&state.postgres)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected > 0 => {
+            audit.set_actor(Actor::local_user(user_id));
+            audit
+                .record(AuditEvent::new("user.verify_email", "user").target(user_id))
+                .await;
+            (StatusCode::OK, "メールアドレスを確認しました".to_string()).into_response()
+        }
+        Ok(_) => (
+            StatusCode::BAD_REQUEST,
+            "リンクが無効か、有効期限が切れています".to_string(),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("メールアドレスを確認できませんでした: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn resend_verification(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    headers: HeaderMap,
+) -> Response {
+    let Some(user_id) = local_user_id(&principal) else {
+        return not_local_account();
+    };
+    let Some(mailer) = state.mailer.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "メール送信が設定されていません".to_string(),
+        )
+            .into_response();
+    };
+    let failed = |e: DbErr| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("確認メールを送信できませんでした: {}", e),
+        )
+            .into_response()
+    };
+
+    // 前回の送信から間隔が空いている場合だけ送信時刻を更新する。複数台で動かしていても一度しか通らない
+    let now = chrono::Utc::now();
+    let interval = chrono::Duration::seconds(VERIFICATION_RESEND_INTERVAL_SECONDS);
+    let updated = Users::update_many()
+        .col_expr(users::Column::VerificationSentAt, Expr::value(now))
+        .filter(users::Column::Id.eq(user_id))
+        .filter(users::Column::EmailVerifiedAt.is_null())
+        .filter(
+            Condition::any()
+                .add(users::Column::VerificationSentAt.is_null())
+                .add(users::Column::VerificationSentAt.lte(now - interval)),
+        )
+        .exec(&state.postgres)
+        .await;
+    let updated = match updated {
+        Ok(result) => result.rows_affected > 0,
+        Err(e) => return failed(e),
+    };
+    let user = match Users::find_by_id(user_id).one(&state.postgres).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                "ユーザーが見つかりませんでした".to_string(),
+            )
+                .into_response()
+        }
+        Err(e) => return failed(e),
+    };
+
+    if user.email_verified_at.is_some() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "メールアドレスは確認済みです".to_string(),
+        )
+            .into_response();
+    }
+    if !updated {
+        let retry_after = user.verification_sent_at.map_or(1, |sent_at| {
+            (interval - (now - sent_at.with_timezone(&chrono::Utc))).num_seconds()
+        });
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, retry_after.max(1).to_string())],
+            "しばらく待ってから再送してください".to_string(),
+        )
+            .into_response();
+    }
+
+    audit
+        .record(AuditEvent::new("user.resend_verification", "user").target(user.id))
+        .await;
+    match send_verification_email(&state, &mailer, &headers, user.id, &user.email) {
+        Ok(()) => (StatusCode::OK, "確認メールを送信しました".to_string()).into_response(),
+        Err(e) => {
+            eprintln!("{}", e);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "確認メールを送信できませんでした".to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+// サービスの作成などはメールアドレスを確認したローカルアカウントに限る。
+// OIDCや固定トークンの利用者の確認はそれぞれのプロバイダに任せる
+pub async fn require_verified_email(
+    state: &AppState,
+    principal: &Principal,
+) -> Result<(), Response> {
+    let Some(user_id) = local_user_id(principal) else {
+        return Ok(());
+    };
+    match Users::find_by_id(user_id).one(&state.postgres).await {
+        Ok(Some(user)) if user.email_verified_at.is_some() => Ok(()),
+        Ok(_) => Err((
+            StatusCode::FORBIDDEN,
+            "メールアドレスを確認してください".to_string(),
+        )
+            .into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("ユーザーを取得できませんでした: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+// User-AgentとX-Forwarded-For(なければX-Real-IP)をセッションの端末情報として保存する
+fn client_metadata(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(512).collect::<String>())
+    };
+    let ip_address = header("x-forwarded-for")
+        .and_then(|value| value.split(',').next().map(|ip| ip.trim().to_string()))
+        .or_else(|| header("x-real-ip"));
+    (header("user-agent"), ip_address)
+}
+
+// パスワードを確認してから二段階目のコードを送るまでの猶予
+const LOGIN_CHALLENGE_TTL_MINUTES: i64 = 5;
+const LOGIN_TOTP_PURPOSE: &str = "login_totp";
+// 続けてこの回数コードを間違えると、しばらく二段階目を受け付けない
+const MAX_TOTP_FAILURES: i32 = 5;
+const TOTP_LOCKOUT_MINUTES: i64 = 15;
+
+#[derive(Serialize)]
+struct TwoFactorChallenge {
+    two_factor_required: bool,
+    challenge: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoginTotpDetails {
+    challenge: String,
+    // 認証アプリの6桁のコード、またはリカバリーコード
+    code: String,
+}
+
+// ランダムなセッションIDでセッションを作り、クッキーを返す。他の端末のセッションはそのまま残す
+async fn start_session(
+    state: &AppState,
+    jar: PrivateCookieJar,
+    headers: &HeaderMap,
+    mut audit: AuditLog,
+    user_id: i32,
+) -> Response {
+    // 期限切れのセッションはログインのついでに削除する
+    let _ = Sessions::delete_many()
+        .filter(sessions::Column::UserId.eq(user_id))
+        .filter(sessions::Column::ExpiresAt.lte(chrono::Utc::now()))
+        .exec(&state.postgres)
+        .await;
+
+    let session_id = generate_key(32);
+    let ttl_hours = state.config.session.ttl_hours as i64;
+    let (user_agent, ip_address) = client_metadata(headers);
+
+    let session = SessionModel {
+        id: Default::default(),
+        session_id: Set(session_id.clone()),
+        user_id: Set(user_id),
+        created_at: NotSet,
+        expires_at: Set((chrono::Utc::now() + chrono::Duration::hours(ttl_hours)).into()),
+        last_seen_at: NotSet,
+        user_agent: Set(user_agent),
+        ip_address: Set(ip_address),
+    };
+
+    match session.insert(&state.postgres).await {
+        Ok(session) => {
+            audit.set_actor(Actor::local_user(user_id));
+            audit
+                .record(
+                    AuditEvent::new("session.create", "session")
+                        .target(session.id)
+                        .after(&serde_json::json!({
+                            "user_agent": session.user_agent,
+                            "ip_address": session.ip_address,
+                        })),
+                )
+                .await;
+            let cookie = Cookie::build(SESSION_COOKIE, session_id)
+                .secure(false)
+                .same_site(SameSite::Lax)
+                .http_only(true)
+                .path("/")
+                .max_age(Duration::hours(ttl_hours))
+                .finish();
+            // ステータスコード200とクッキーを返す。
+            (jar.add(cookie), StatusCode::OK).into_response()
+        }
+        Err(e) => {
+            eprintln!("An error occurred: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 pub async fn login(
     State(state): State<AppState>,
     jar: PrivateCookieJar,
+    headers: HeaderMap,
+    audit: AuditLog,
     Json(login): Json<LoginDetails>,
-) -> Result<(PrivateCookieJar, StatusCode), StatusCode> {
+) -> Response {
     let user = Users::find()
         .filter(users::Column::Username.eq(&login.username))
         .one(&state.postgres)
@@ -75,62 +409,178 @@ pub async fn login(
 
     match user {
         Ok(Some(user)) => {
-            // bcryptがハッシュ値を認証できなかったら、BAD_REQUESTエラーを返す。
+            // bcryptがハッシュ値を認証できなかったら、UNAUTHORIZEDエラーを返す。
             match bcrypt::verify(&login.password, &user.password) {
-                Ok(bool) => {
-                    if !bool {
-                        return Err(StatusCode::UNAUTHORIZED);
-                    }
-                }
-                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+                Ok(true) => {}
+                Ok(false) => return StatusCode::UNAUTHORIZED.into_response(),
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
             }
 
-            // ランダムセッションIDを生成し、ハッシュマップエントリーに追加
-            let session_id = rand::random::<u64>().to_string();
-
-            let session = SessionModel {
-                id: Default::default(),
-                session_id: Set(session_id.clone()),
-                user_id: Set(user.id),
-            };
-
-            let result = SessionEntity::insert(session)
-                .on_conflict(
-                    // on conflict do update
-                    sea_query::OnConflict::column(sessions::Column::UserId)
-                        .update_column(sessions::Column::SessionId)
-                        .to_owned(),
+            // 二段階認証が有効なら、セッションはコードを確認してから作る
+            if user.totp_enabled_at.is_some() {
+                let challenge = state.keys.sign_user_token(
+                    LOGIN_TOTP_PURPOSE,
+                    user.id,
+                    &user.email,
+                    chrono::Duration::minutes(LOGIN_CHALLENGE_TTL_MINUTES),
+                );
+                return (
+                    StatusCode::ACCEPTED,
+                    Json(TwoFactorChallenge {
+                        two_factor_required: true,
+                        challenge,
+                    }),
                 )
-                .exec(&state.postgres);
-
-            match result.await {
-                Ok(_) => {
-                    let cookie = Cookie::build("foo", session_id)
-                        .secure(false)
-                        .same_site(SameSite::Lax)
-                        .http_only(true)
-                        .path("/")
-                        .max_age(Duration::WEEK)
-                        .finish();
-                    // ステータスコード200とクッキーを返す。
-                    Ok((jar.add(cookie), StatusCode::OK))
-                }
-                Err(e) => {
-                    eprintln!("An error occurred: {:?}", e);
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
-                }
+                    .into_response();
             }
+
+            start_session(&state, jar, &headers, audit, user.id).await
         }
-        Ok(None) => Err(StatusCode::BAD_REQUEST),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Ok(None) => StatusCode::BAD_REQUEST.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
+// TOTPのコードかリカバリーコードが正しければ、それを使用済みにしてtrueを返す
+async fn consume_second_factor(
+    state: &AppState,
+    user: &users::Model,
+    secret: &str,
+    code: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<bool, DbErr> {
+    if let Some(step) = totp::verify(secret, code, now.timestamp(), user.totp_last_step) {
+        // 同じコードが同時に送られても、時間ステップを進められるのは一方だけ
+        let result = Users::update_many()
+            .col_expr(users::Column::TotpLastStep, Expr::value(step))
+            .filter(users::Column::Id.eq(user.id))
+            .filter(
+                Condition::any()
+                    .add(users::Column::TotpLastStep.is_null())
+                    .add(users::Column::TotpLastStep.lt(step)),
+            )
+            .exec(&state.postgres)
+            .await?;
+        return Ok(result.rows_affected > 0);
+    }
+
+    let result = RecoveryCodes::update_many()
+        .col_expr(recovery_codes::Column::UsedAt, Expr::value(now))
+        .filter(recovery_codes::Column::UserId.eq(user.id))
+        .filter(
+            recovery_codes::Column::CodeHash.eq(hash_token(&totp::normalize_recovery_code(code))),
+        )
+        .filter(recovery_codes::Column::UsedAt.is_null())
+        .exec(&state.postgres)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+pub async fn login_totp(
+    State(state): State<AppState>,
+    jar: PrivateCookieJar,
+    headers: HeaderMap,
+    mut audit: AuditLog,
+    Json(details): Json<LoginTotpDetails>,
+) -> Response {
+    let login_again = || {
+        (
+            StatusCode::BAD_REQUEST,
+            "もう一度ログインしてください".to_string(),
+        )
+            .into_response()
+    };
+    let failed = |e: DbErr| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("ログインできませんでした: {}", e),
+        )
+            .into_response()
+    };
+    let Some((user_id, email)) = state
+        .keys
+        .verify_user_token(LOGIN_TOTP_PURPOSE, &details.challenge)
+    else {
+        return login_again();
+    };
+    let user = Users::find_by_id(user_id)
+        .filter(users::Column::Email.eq(email))
+        .one(&state.postgres)
+        .await;
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => return login_again(),
+        Err(e) => return failed(e),
+    };
+    // パスワードの確認後に二段階認証が解除された場合も、最初からやり直す
+    let (Some(secret), Some(_)) = (user.totp_secret.as_deref(), user.totp_enabled_at) else {
+        return login_again();
+    };
+
+    let now = chrono::Utc::now();
+    let lockout = chrono::Duration::minutes(TOTP_LOCKOUT_MINUTES);
+    if let Some(failed_at) = user.totp_failed_at {
+        let locked_until = failed_at.with_timezone(&chrono::Utc) + lockout;
+        if user.totp_failures >= MAX_TOTP_FAILURES && locked_until > now {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    RETRY_AFTER,
+                    (locked_until - now).num_seconds().max(1).to_string(),
+                )],
+                "しばらく待ってから再度お試しください".to_string(),
+            )
+                .into_response();
+        }
+    }
+
+    match consume_second_factor(&state, &user, secret, &details.code, now).await {
+        Ok(true) => {}
+        Ok(false) => {
+            // 前回の失敗からロックの期間が過ぎていれば数え直す
+            let _ = Users::update_many()
+                .col_expr(
+                    users::Column::TotpFailures,
+                    Expr::cust(&format!(
+                        "CASE WHEN totp_failed_at > CURRENT_TIMESTAMP - INTERVAL '{} minutes' THEN totp_failures + 1 ELSE 1 END",
+                        TOTP_LOCKOUT_MINUTES
+                    )),
+                )
+                .col_expr(users::Column::TotpFailedAt, Expr::value(now))
+                .filter(users::Column::Id.eq(user.id))
+                .exec(&state.postgres)
+                .await;
+            audit.set_actor(Actor::local_user(user.id));
+            audit
+                .record(AuditEvent::new("user.totp_failure", "user").target(user.id))
+                .await;
+            return (
+                StatusCode::UNAUTHORIZED,
+                "コードが正しくありません".to_string(),
+            )
+                .into_response();
+        }
+        Err(e) => return failed(e),
+    }
+
+    if user.totp_failures > 0 {
+        let _ = Users::update_many()
+            .col_expr(users::Column::TotpFailures, Expr::value(0))
+            .filter(users::Column::Id.eq(user.id))
+            .exec(&state.postgres)
+            .await;
+    }
+    start_session(&state, jar, &headers, audit, user.id).await
+}
+
 pub async fn logout(
     State(state): State<AppState>,
     jar: PrivateCookieJar,
+    headers: HeaderMap,
+    mut audit: AuditLog,
 ) -> Result<PrivateCookieJar, StatusCode> {
-    let Some(cookie) = jar.get("foo").map(|cookie| cookie.value().to_owned()) else {
+    // ローテーション前の鍵で暗号化されたクッキーも対象にする
+    let Some(cookie) = state.keys.session_id(&headers) else {
         return Ok(jar);
     };
 
@@ -141,114 +591,486 @@ pub async fn logout(
         .await;
 
     match target {
-        Ok(target) => {
+        Ok(Some(target)) => {
+            let (session_id, user_id) = (target.id, target.user_id);
             //ActiveModelを取得する
-            let delete_row: sessions::ActiveModel = target.unwrap().into_active_model();
+            let delete_row: sessions::ActiveModel = target.into_active_model();
             let delete_result = delete_row.delete(&state.postgres).await;
             match delete_result {
-                Ok(_) => Ok(jar.remove(Cookie::named("foo"))),
+                Ok(_) => {
+                    audit.set_actor(Actor::local_user(user_id));
+                    audit
+                        .record(AuditEvent::new("session.delete", "session").target(session_id))
+                        .await;
+                    Ok(jar.remove(Cookie::named(SESSION_COOKIE)))
+                }
                 Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
             }
         }
+        // セッションが既に無い場合もクッキーは削除する
+        Ok(None) => Ok(jar.remove(Cookie::named(SESSION_COOKIE))),
         Err(_) => Err(StatusCode::BAD_REQUEST),
     }
 }
 
+// パスワード再設定リンクの有効期間
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+#[derive(Deserialize)]
+pub struct ResetPasswordDetails {
+    token: String,
+    password: String,
+}
+
 pub async fn forgot_password(
     State(state): State<AppState>,
+    audit: AuditLog,
+    headers: HeaderMap,
     Json(email_recipient): Json<String>,
 ) -> Response {
-    let new_password = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
-    let hashed_password = bcrypt::hash(&new_password, 10).unwrap();
+    let Some(mailer) = state.mailer.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "メール送信が設定されていません".to_string(),
+        )
+            .into_response();
+    };
+    // メールアドレスが登録されているかどうかは応答から分からないようにする
+    let accepted = (
+        StatusCode::OK,
+        "登録されているメールアドレスであれば、パスワード再設定用のリンクを送信しました"
+            .to_string(),
+    )
+        .into_response();
 
-    //更新対象を取得する
-    let target = users::Entity::find()
+    let user = Users::find()
         .filter(users::Column::Email.eq(&email_recipient))
         .one(&state.postgres)
         .await;
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => return accepted,
+        Err(e) => {
+            eprintln!("{}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "パスワード再設定を受け付けられませんでした".to_string(),
+            )
+                .into_response();
+        }
+    };
 
-    //パスワードを更新する
-    match target {
-        Ok(target) => {
-            //ActiveModelを取得する
-            let mut update_row: users::ActiveModel = target
-                .expect("アクティブモデルへの変換失敗")
-                .into_active_model();
-            //hashed_passwordに更新する
-            update_row.password = Set(hashed_password);
-            let update_result = update_row.update(&state.postgres).await;
-            match update_result {
-                Ok(_) => {
-                    let credentials =
-                        Credentials::new(state.smtp_email.clone(), state.smtp_password);
-
-                    let message = format!("Hello! \n\n Your new password is: {}", new_password);
-
-                    let email = Message::builder()
-                        .from(state.smtp_email.parse().expect("failed to parse from"))
-                        .to(format!("<{email_recipient}>")
-                            .parse()
-                            .expect("failed to parse to"))
-                        .subject("Forgot Password")
-                        .header(ContentType::TEXT_PLAIN)
-                        .body(message)
-                        .unwrap();
-
-                    //メールを送信する
-                    let mailer = SmtpTransport::relay("smtp.mail.yahoo.co.jp")
-                        .unwrap()
-                        .credentials(credentials)
-                        .build();
-
-                    match mailer.send(&email) {
-                        Ok(_) => {
-                            (StatusCode::OK, "メールを送信しました".to_string()).into_response()
-                        }
-                        Err(e) => {
-                            eprintln!("{}", e);
-                            (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                "メールを送信できませんでした".to_string(),
-                            )
-                                .into_response()
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "パスワードを更新できませんでした".to_string(),
-                    )
-                        .into_response()
+    // 以前に発行したリンクは使えなくする
+    let _ = PasswordResetTokens::delete_many()
+        .filter(password_reset_tokens::Column::UserId.eq(user.id))
+        .exec(&state.postgres)
+        .await;
+
+    let token = generate_key(48);
+    let reset_token = PasswordResetTokenModel {
+        id: NotSet,
+        user_id: Set(user.id),
+        token_hash: Set(hash_token(&token)),
+        created_at: NotSet,
+        expires_at: Set(
+            (chrono::Utc::now() + chrono::Duration::minutes(RESET_TOKEN_TTL_MINUTES)).into(),
+        ),
+        used_at: NotSet,
+    };
+    if let Err(e) = reset_token.insert(&state.postgres).await {
+        eprintln!("{}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "パスワード再設定を受け付けられませんでした".to_string(),
+        )
+            .into_response();
+    }
+    // 依頼した人は本人とは限らないので、操作した主体は匿名のまま記録する
+    audit
+        .record(AuditEvent::new("user.password_reset_request", "user").target(user.id))
+        .await;
+
+    let link = state
+        .config
+        .server
+        .frontend_link(&format!("/reset-password?token={}", token));
+    // 送信はキューに積むだけなので、応答にかかる時間から登録の有無は分からない
+    let template = Template::PasswordReset {
+        link: &link,
+        ttl_minutes: RESET_TOKEN_TTL_MINUTES,
+    };
+    if let Err(e) = mailer.send(&user.email, mailer.locale(&headers), template) {
+        eprintln!("{}", e);
+    }
+
+    accepted
+}
+
+pub async fn reset_password(
+    State(state): State<AppState>,
+    mut audit: AuditLog,
+    Json(details): Json<ResetPasswordDetails>,
+) -> Response {
+    if details.password.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "新しいパスワードを入力してください".to_string(),
+        )
+            .into_response();
+    }
+    let invalid_token = || {
+        (
+            StatusCode::BAD_REQUEST,
+            "リンクが無効か、有効期限が切れています".to_string(),
+        )
+            .into_response()
+    };
+    let hashed_password = bcrypt::hash(&details.password, 10).unwrap();
+    let token_hash = hash_token(&details.token);
+
+    let result = state
+        .postgres
+        .transaction::<_, Option<i32>, DbErr>(|txn| {
+            Box::pin(async move {
+                let now = chrono::Utc::now();
+                let Some(reset_token) = PasswordResetTokens::find()
+                    .filter(password_reset_tokens::Column::TokenHash.eq(&token_hash))
+                    .filter(password_reset_tokens::Column::UsedAt.is_null())
+                    .filter(password_reset_tokens::Column::ExpiresAt.gt(now))
+                    .one(txn)
+                    .await?
+                else {
+                    return Ok(None);
+                };
+
+                // 同じトークンで同時にリクエストされても一度しか使えないようにする
+                let consumed = PasswordResetTokens::update_many()
+                    .col_expr(password_reset_tokens::Column::UsedAt, Expr::value(now))
+                    .filter(password_reset_tokens::Column::Id.eq(reset_token.id))
+                    .filter(password_reset_tokens::Column::UsedAt.is_null())
+                    .exec(txn)
+                    .await?;
+                if consumed.rows_affected == 0 {
+                    return Ok(None);
                 }
-            }
+
+                Users::update_many()
+                    .col_expr(users::Column::Password, Expr::value(hashed_password))
+                    .filter(users::Column::Id.eq(reset_token.user_id))
+                    .exec(txn)
+                    .await?;
+
+                // パスワードが漏れていた場合に備えて、すべての端末からログアウトさせる
+                Sessions::delete_many()
+                    .filter(sessions::Column::UserId.eq(reset_token.user_id))
+                    .exec(txn)
+                    .await?;
+
+                Ok(Some(reset_token.user_id))
+            })
+        })
+        .await;
+
+    match result {
+        Ok(Some(user_id)) => {
+            audit.set_actor(Actor::local_user(user_id));
+            audit
+                .record(AuditEvent::new("user.password_reset", "user").target(user_id))
+                .await;
+            (StatusCode::OK, "パスワードを再設定しました".to_string()).into_response()
         }
-        Err(_) => (
-            StatusCode::BAD_REQUEST,
-            "メールアドレスが見つかりませんでした".to_string(),
+        Ok(None) => invalid_token(),
+        Err(e) => {
+            eprintln!("{}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "パスワードを再設定できませんでした".to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn auth_check(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let provider = LocalSessionProvider::new(state.postgres.clone(), state.keys.clone());
+    match provider.authenticate(&headers).await {
+        Ok(_) => (StatusCode::OK, "ログインしています".to_string()).into_response(),
+        Err(AuthError::Unauthorized(message)) => (StatusCode::FORBIDDEN, message).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionSummary {
+    id: i32,
+    current: bool,
+    created_at: DateTimeWithTimeZone,
+    last_seen_at: DateTimeWithTimeZone,
+    expires_at: DateTimeWithTimeZone,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+}
+
+// セッションの一覧と削除はローカルアカウントでログインしている場合だけ使える
+fn local_user_id(principal: &Principal) -> Option<i32> {
+    match principal.provider {
+        "local" => principal.subject.parse().ok(),
+        _ => None,
+    }
+}
+
+fn not_local_account() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        "ローカルアカウントでログインしてください".to_string(),
+    )
+        .into_response()
+}
+
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    let Some(user_id) = local_user_id(&principal) else {
+        return not_local_account();
+    };
+
+    let result = Sessions::find()
+        .filter(sessions::Column::UserId.eq(user_id))
+        .filter(sessions::Column::ExpiresAt.gt(chrono::Utc::now()))
+        .order_by_desc(sessions::Column::LastSeenAt)
+        .all(&state.postgres)
+        .await;
+
+    match result {
+        Ok(sessions) => Json(
+            sessions
+                .into_iter()
+                .map(|session| SessionSummary {
+                    id: session.id,
+                    current: Some(session.id) == principal.session_id,
+                    created_at: session.created_at,
+                    last_seen_at: session.last_seen_at,
+                    expires_at: session.expires_at,
+                    user_agent: session.user_agent,
+                    ip_address: session.ip_address,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("セッションを取得できませんでした: {}", e),
         )
             .into_response(),
     }
 }
 
-pub async fn auth_check(State(state): State<AppState>, jar: PrivateCookieJar) -> impl IntoResponse {
-    let Some(cookie) = jar.get("foo").map(|cookie| cookie.value().to_owned()) else {
-        println!("{:?} Could not find a cookie in jar", jar);
-        return (StatusCode::FORBIDDEN, "ログインしてください".to_string()).into_response();
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    Path(session_id): Path<i32>,
+) -> Response {
+    let Some(user_id) = local_user_id(&principal) else {
+        return not_local_account();
     };
 
-    let find_session = Sessions::find()
-        .filter(sessions::Column::SessionId.eq(cookie))
-        .one(&state.postgres)
+    // 他のユーザーのセッションは削除できない
+    let result = Sessions::delete_many()
+        .filter(sessions::Column::Id.eq(session_id))
+        .filter(sessions::Column::UserId.eq(user_id))
+        .exec(&state.postgres)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected > 0 => {
+            audit
+                .record(AuditEvent::new("session.delete", "session").target(session_id))
+                .await;
+            (StatusCode::OK, "セッションを削除しました".to_string()).into_response()
+        }
+        Ok(_) => (
+            StatusCode::NOT_FOUND,
+            "セッションが見つかりませんでした".to_string(),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("セッションを削除できませんでした: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct TotpEnrollment {
+    secret: String,
+    otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCode {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodeList {
+    recovery_codes: Vec<String>,
+}
+
+async fn find_local_user(
+    state: &AppState,
+    principal: &Principal,
+) -> Result<users::Model, Response> {
+    let Some(user_id) = local_user_id(principal) else {
+        return Err(not_local_account());
+    };
+    match Users::find_by_id(user_id).one(&state.postgres).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            "ユーザーが見つかりませんでした".to_string(),
+        )
+            .into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("ユーザーを取得できませんでした: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+fn totp_already_enabled() -> Response {
+    (StatusCode::CONFLICT, "二段階認証は既に有効です".to_string()).into_response()
+}
+
+// 秘密鍵を発行する。activate_totpでコードを確認するまで二段階認証は有効にならない
+pub async fn enroll_totp(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+) -> Response {
+    let user = match find_local_user(&state, &principal).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.totp_enabled_at.is_some() {
+        return totp_already_enabled();
+    }
+
+    let secret = totp::generate_secret();
+    let result = Users::update_many()
+        .col_expr(users::Column::TotpSecret, Expr::value(secret.clone()))
+        .col_expr(
+            users::Column::TotpLastStep,
+            Expr::value(Option::<i64>::None),
+        )
+        .filter(users::Column::Id.eq(user.id))
+        .filter(users::Column::TotpEnabledAt.is_null())
+        .exec(&state.postgres)
         .await;
 
-    match find_session {
-        Ok(session) => match session {
-            Some(_) => (StatusCode::OK, "ログインしています".to_string()).into_response(),
-            None => (StatusCode::FORBIDDEN, "ログインしていません".to_string()).into_response(),
-        },
-        Err(_) => (StatusCode::FORBIDDEN, "ログインしていません".to_string()).into_response(),
+    match result {
+        Ok(_) => {
+            audit
+                .record(AuditEvent::new("user.totp_enroll", "user").target(user.id))
+                .await;
+            Json(TotpEnrollment {
+                otpauth_uri: totp::otpauth_uri(&user.username, &secret),
+                secret,
+            })
+            .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("二段階認証を登録できませんでした: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+// 認証アプリのコードを確認して二段階認証を有効にし、リカバリーコードを一度だけ返す
+pub async fn activate_totp(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    Json(details): Json<TotpCode>,
+) -> Response {
+    let user = match find_local_user(&state, &principal).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.totp_enabled_at.is_some() {
+        return totp_already_enabled();
+    }
+    let Some(secret) = user.totp_secret.as_deref() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "先に二段階認証の登録を開始してください".to_string(),
+        )
+            .into_response();
+    };
+    let now = chrono::Utc::now();
+    let Some(step) = totp::verify(secret, &details.code, now.timestamp(), None) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "コードが正しくありません".to_string(),
+        )
+            .into_response();
+    };
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes.iter().map(|code| hash_token(code)).collect();
+    let user_id = user.id;
+    let result = state
+        .postgres
+        .transaction::<_, bool, DbErr>(|txn| {
+            Box::pin(async move {
+                let enabled = Users::update_many()
+                    .col_expr(users::Column::TotpEnabledAt, Expr::value(now))
+                    .col_expr(users::Column::TotpLastStep, Expr::value(step))
+                    .col_expr(users::Column::TotpFailures, Expr::value(0))
+                    .filter(users::Column::Id.eq(user_id))
+                    .filter(users::Column::TotpEnabledAt.is_null())
+                    .exec(txn)
+                    .await?;
+                if enabled.rows_affected == 0 {
+                    return Ok(false);
+                }
+
+                RecoveryCodes::delete_many()
+                    .filter(recovery_codes::Column::UserId.eq(user_id))
+                    .exec(txn)
+                    .await?;
+                RecoveryCodes::insert_many(code_hashes.into_iter().map(|code_hash| {
+                    RecoveryCodeModel {
+                        id: NotSet,
+                        user_id: Set(user_id),
+                        code_hash: Set(code_hash),
+                        used_at: NotSet,
+                    }
+                }))
+                .exec(txn)
+                .await?;
+                Ok(true)
+            })
+        })
+        .await;
+
+    match result {
+        Ok(true) => {
+            audit
+                .record(AuditEvent::new("user.totp_activate", "user").target(user_id))
+                .await;
+            Json(RecoveryCodeList { recovery_codes }).into_response()
+        }
+        Ok(false) => totp_already_enabled(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("二段階認証を有効にできませんでした: {}", e),
+        )
+            .into_response(),
     }
 }
 
@@ -1,3 +1,9 @@
+use crate::libs::audit::{AuditEvent, AuditLog};
+use crate::libs::content_csv::{column_mapping, read_csv, write_csv, RowError};
+use crate::libs::json_schema::{content_type_schema, parse_content_type_schema};
+use crate::libs::management;
+use crate::libs::trash::{self, TrashedItem};
+use crate::models::api_keys;
 use crate::models::content_items::ActiveModel as ContentItemModel;
 use crate::models::content_types::ActiveModel as ContentTypeModel;
 use crate::models::fields;
@@ -6,14 +12,17 @@ use crate::models::prelude::{ContentItems, ContentTypes, Fields};
 use crate::{models, AppState};
 use anyhow::Result;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Extension, Path, Query, State},
+    http::{header, StatusCode},
     response::IntoResponse,
     Json,
 };
 use sea_orm::prelude::DateTimeWithTimeZone;
 use sea_orm::ActiveValue::Set;
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DbErr, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder,
+    TransactionTrait,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::postgres::PgTypeInfo;
@@ -104,6 +113,48 @@ impl Display for FieldType {
     }
 }
 
+impl FieldType {
+    // field_type_matchesと同じ判定をJSON Schemaで表現する
+    pub fn json_schema(&self) -> serde_json::Value {
+        match self {
+            FieldType::Text => json!({ "type": "string" }),
+            FieldType::Number => json!({ "type": "number" }),
+            FieldType::Date => json!({ "type": "string", "format": "date-time" }),
+            FieldType::Boolean => json!({ "type": "boolean" }),
+        }
+    }
+
+    // CSVのセルなど文字列で受け取った値をフィールドタイプに合わせて変換する
+    pub fn coerce_str(&self, raw: &str) -> Result<serde_json::Value, String> {
+        match self {
+            FieldType::Text => Ok(json!(raw)),
+            FieldType::Number => {
+                let raw = raw.trim();
+                if let Ok(n) = raw.parse::<i64>() {
+                    Ok(json!(n))
+                } else {
+                    match raw.parse::<f64>() {
+                        Ok(n) if n.is_finite() => Ok(json!(n)),
+                        _ => Err(format!("\"{}\" is not a number", raw)),
+                    }
+                }
+            }
+            FieldType::Date => {
+                let raw = raw.trim();
+                match chrono::DateTime::parse_from_rfc3339(raw) {
+                    Ok(_) => Ok(json!(raw)),
+                    Err(_) => Err(format!("\"{}\" is not an RFC 3339 date", raw)),
+                }
+            }
+            FieldType::Boolean => match raw.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" => Ok(json!(true)),
+                "false" | "0" | "no" => Ok(json!(false)),
+                _ => Err(format!("\"{}\" is not a boolean", raw.trim())),
+            },
+        }
+    }
+}
+
 impl FromStr for FieldType {
     type Err = io::Error;
 
@@ -147,11 +198,11 @@ impl<'r> Decode<'r, sqlx::Postgres> for FieldType {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Debug)]
 pub struct NewField {
-    display_name: String,
-    field_type: FieldType,
-    required: bool,
+    pub display_name: String,
+    pub field_type: FieldType,
+    pub required: bool,
 }
 
 #[derive(Deserialize)]
@@ -168,20 +219,21 @@ pub struct ContentItem {
 pub async fn create_content_type(
     Path(service_id): Path<String>,
     State(state): State<AppState>,
+    audit: AuditLog,
     Json(new_content_type): Json<NewContentType>,
 ) -> impl IntoResponse {
-    let content_type = ContentTypeModel {
-        id: Default::default(),
-        name: Set(new_content_type.name),
-        created_at: Default::default(),
-        updated_at: Default::default(),
-        service_id: Set(Some(service_id)),
-    };
-
-    let res = content_type.insert(&state.postgres);
+    let res = management::create_content_type(&state.postgres, &service_id, new_content_type.name);
 
     match res.await {
         Ok(res) => {
+            audit
+                .record(
+                    AuditEvent::new("content_type.create", "content_type")
+                        .service(&service_id)
+                        .target(res.id)
+                        .after(&res),
+                )
+                .await;
             //問い合わせの返り値からidを取得
             let content_type_id: i32 = res.id;
             (
@@ -203,27 +255,28 @@ pub async fn create_content_type(
 
 pub async fn create_field(
     State(state): State<AppState>,
-    Path((_service_id, content_type_id)): Path<(String, i32)>,
+    Path((service_id, content_type_id)): Path<(String, i32)>,
+    audit: AuditLog,
     Json(new_field): Json<NewField>,
 ) -> impl IntoResponse {
-    let new_field = FieldModel {
-        id: Default::default(),
-        content_type_id: Set(content_type_id),
-        display_id: Set(new_field.display_name),
-        field_type: Set(new_field.field_type.to_string()),
-        required: Set(new_field.required),
-        created_at: Default::default(),
-        updated_at: Default::default(),
-    };
-
-    let query = new_field.insert(&state.postgres);
+    let query = management::create_field(&state.postgres, content_type_id, new_field);
 
     match query.await {
-        Ok(_) => (
-            StatusCode::CREATED,
-            "フィールドが作成されました".to_string(),
-        )
-            .into_response(),
+        Ok(field) => {
+            audit
+                .record(
+                    AuditEvent::new("field.create", "field")
+                        .service(&service_id)
+                        .target(field.id)
+                        .after(&field),
+                )
+                .await;
+            (
+                StatusCode::CREATED,
+                "フィールドが作成されました".to_string(),
+            )
+                .into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("フィールドの作成に失敗しました: {}", e),
@@ -285,9 +338,119 @@ pub async fn get_content_type(
     }
 }
 
+pub async fn get_content_type_schema(
+    Path((service_id, content_type_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let content_type = ContentTypes::find_by_id(content_type_id)
+        .filter(models::content_types::Column::ServiceId.eq(service_id))
+        .find_with_related(Fields)
+        .order_by_asc(fields::Column::Id)
+        .all(&state.postgres)
+        .await;
+
+    match content_type {
+        Ok(rows) => match rows.first() {
+            Some((content_type, fields)) => {
+                Json(content_type_schema(content_type, fields)).into_response()
+            }
+            None => (
+                StatusCode::NOT_FOUND,
+                "コンテンツタイプが見つかりませんでした".to_string(),
+            )
+                .into_response(),
+        },
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("コンテンツタイプの取得に失敗しました: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn import_content_type(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+    audit: AuditLog,
+    Json(schema): Json<serde_json::Value>,
+) -> impl IntoResponse {
+    let imported = match parse_content_type_schema(&schema) {
+        Ok(imported) => imported,
+        Err(errors) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("JSON Schemaを取り込めません:\n{}", errors.join("\n")),
+            )
+                .into_response()
+        }
+    };
+
+    //コンテンツタイプとフィールドは一つのトランザクションで作成する
+    let owner = service_id.clone();
+    let result = state
+        .postgres
+        .transaction::<_, i32, DbErr>(|txn| {
+            Box::pin(async move {
+                let content_type = ContentTypeModel {
+                    id: Default::default(),
+                    name: Set(imported.name),
+                    created_at: Default::default(),
+                    updated_at: Default::default(),
+                    service_id: Set(Some(owner)),
+                }
+                .insert(txn)
+                .await?;
+
+                for new_field in imported.fields {
+                    FieldModel {
+                        id: Default::default(),
+                        content_type_id: Set(content_type.id),
+                        display_id: Set(new_field.display_name),
+                        field_type: Set(new_field.field_type.to_string()),
+                        required: Set(new_field.required),
+                        created_at: Default::default(),
+                        updated_at: Default::default(),
+                    }
+                    .insert(txn)
+                    .await?;
+                }
+
+                Ok(content_type.id)
+            })
+        })
+        .await;
+
+    match result {
+        Ok(content_type_id) => {
+            audit
+                .record(
+                    AuditEvent::new("content_type.import", "content_type")
+                        .service(&service_id)
+                        .target(content_type_id)
+                        .after(&schema),
+                )
+                .await;
+            (
+                StatusCode::CREATED,
+                format!(
+                    "コンテンツタイプが作成されました \n content_type_id: {}",
+                    content_type_id
+                ),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("コンテンツタイプの作成に失敗しました: {}", e),
+        )
+            .into_response(),
+    }
+}
+
 pub async fn create_content_item(
     State(state): State<AppState>,
-    Path((_service_id, content_type_id)): Path<(String, i32)>,
+    Path((service_id, content_type_id)): Path<(String, i32)>,
+    audit: AuditLog,
     Json(new_content_item): Json<NewContentItem>,
 ) -> impl IntoResponse {
     let fields = Fields::find()
@@ -326,16 +489,28 @@ pub async fn create_content_item(
                 data: Set(new_content_item.data),
                 created_at: Default::default(),
                 updated_at: Default::default(),
+                deleted_at: Default::default(),
+                deleted_by_key_id: Default::default(),
             };
 
             let query = content_item.insert(&state.postgres);
 
             match query.await {
-                Ok(_) => (
-                    StatusCode::CREATED,
-                    "コンテンツアイテムが作成されました".to_string(),
-                )
-                    .into_response(),
+                Ok(item) => {
+                    audit
+                        .record(
+                            AuditEvent::new("content_item.create", "content_item")
+                                .service(&service_id)
+                                .target(item.id)
+                                .after(&item),
+                        )
+                        .await;
+                    (
+                        StatusCode::CREATED,
+                        "コンテンツアイテムが作成されました".to_string(),
+                    )
+                        .into_response()
+                }
                 Err(e) => (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     format!("コンテンツアイテムの作成に失敗しました: {}", e),
@@ -347,16 +522,118 @@ pub async fn create_content_item(
     }
 }
 
+// 完全には削除せずゴミ箱に移す。保持期間の間はゴミ箱から復元できる
 pub async fn delete_content_item(
     State(state): State<AppState>,
-    Path((_service_id, content_item_id)): Path<(String, Uuid)>,
+    Path((service_id, content_item_id)): Path<(String, Uuid)>,
+    Extension(key): Extension<api_keys::Model>,
+    audit: AuditLog,
 ) -> impl IntoResponse {
-    let query = ContentItems::delete_by_id(content_item_id).exec(&state.postgres);
+    let query = trash::trash_item(&state.postgres, &service_id, content_item_id, key.id);
 
     match query.await {
-        Ok(_) => (
-            StatusCode::OK,
-            "コンテンツアイテムが削除されました".to_string(),
+        Ok(Some(item)) => {
+            audit
+                .record(
+                    AuditEvent::new("content_item.delete", "content_item")
+                        .service(&service_id)
+                        .target(item.id)
+                        .before(&item),
+                )
+                .await;
+            (
+                StatusCode::OK,
+                "コンテンツアイテムをゴミ箱に移動しました".to_string(),
+            )
+                .into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            "コンテンツアイテムが見つかりません".to_string(),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("コンテンツアイテムの削除に失敗しました: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_trash(
+    State(state): State<AppState>,
+    Path(service_id): Path<String>,
+) -> impl IntoResponse {
+    match trash::list_trash(&state.postgres, &service_id).await {
+        Ok(items) => {
+            let items: Vec<TrashedItem> = items.into_iter().map(TrashedItem::from).collect();
+            Json(items).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("ゴミ箱の取得に失敗しました: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn restore_trashed_item(
+    State(state): State<AppState>,
+    Path((service_id, content_item_id)): Path<(String, Uuid)>,
+    audit: AuditLog,
+) -> impl IntoResponse {
+    match trash::restore_item(&state.postgres, &service_id, content_item_id).await {
+        Ok(true) => {
+            audit
+                .record(
+                    AuditEvent::new("content_item.restore", "content_item")
+                        .service(&service_id)
+                        .target(content_item_id),
+                )
+                .await;
+            (
+                StatusCode::OK,
+                "コンテンツアイテムを復元しました".to_string(),
+            )
+                .into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            "ゴミ箱にコンテンツアイテムが見つかりません".to_string(),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("コンテンツアイテムの復元に失敗しました: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn delete_trashed_item(
+    State(state): State<AppState>,
+    Path((service_id, content_item_id)): Path<(String, Uuid)>,
+    audit: AuditLog,
+) -> impl IntoResponse {
+    match trash::delete_item(&state.postgres, &service_id, content_item_id).await {
+        Ok(Some(item)) => {
+            audit
+                .record(
+                    AuditEvent::new("content_item.purge", "content_item")
+                        .service(&service_id)
+                        .target(item.id)
+                        .before(&item),
+                )
+                .await;
+            (
+                StatusCode::OK,
+                "コンテンツアイテムを完全に削除しました".to_string(),
+            )
+                .into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            "ゴミ箱にコンテンツアイテムが見つかりません".to_string(),
         )
             .into_response(),
         Err(e) => (
@@ -369,7 +646,8 @@ pub async fn delete_content_item(
 
 pub async fn update_content_item(
     State(state): State<AppState>,
-    Path((_service_id, content_item_id)): Path<(String, Uuid)>,
+    Path((service_id, content_item_id)): Path<(String, Uuid)>,
+    audit: AuditLog,
     Json(content_item): Json<ContentItem>,
 ) -> impl IntoResponse {
     //content_itemが空の場合はBAD_REQUESTを返す
@@ -383,6 +661,7 @@ pub async fn update_content_item(
 
     //content_item_idでcontent_itemテーブルからcontent_type_idを取得する
     let query = ContentItems::find_by_id(content_item_id)
+        .filter(models::content_items::Column::DeletedAt.is_null())
         .one(&state.postgres)
         .await;
 
@@ -448,6 +727,7 @@ pub async fn update_content_item(
     let json_data = json!(valid_data);
 
     let target = ContentItems::find_by_id(content_item_id)
+        .filter(models::content_items::Column::DeletedAt.is_null())
         .one(&state.postgres)
         .await;
 
@@ -457,16 +737,28 @@ pub async fn update_content_item(
 
     match target.unwrap() {
         Some(target) => {
+            let before = target.clone();
             let mut update_row: ContentItemModel = target.into_active_model();
             update_row.data = Set(json_data);
             let update_result = update_row.update(&state.postgres).await;
 
             match update_result {
-                Ok(_) => (
-                    StatusCode::OK,
-                    "コンテンツアイテムが更新されました".to_string(),
-                )
-                    .into_response(),
+                Ok(after) => {
+                    audit
+                        .record(
+                            AuditEvent::new("content_item.update", "content_item")
+                                .service(&service_id)
+                                .target(after.id)
+                                .before(&before)
+                                .after(&after),
+                        )
+                        .await;
+                    (
+                        StatusCode::OK,
+                        "コンテンツアイテムが更新されました".to_string(),
+                    )
+                        .into_response()
+                }
                 Err(e) => (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     format!("コンテンツアイテムの更新に失敗しました: {}", e),
@@ -488,6 +780,7 @@ pub async fn get_content_items(
 ) -> impl IntoResponse {
     let rows = ContentItems::find()
         .filter(models::content_items::Column::ContentTypeId.eq(content_type_id))
+        .filter(models::content_items::Column::DeletedAt.is_null())
         .all(&state.postgres)
         .await;
 
@@ -533,6 +826,7 @@ pub async fn get_content_item(
     Path((_service_id, content_item_id)): Path<(String, Uuid)>,
 ) -> impl IntoResponse {
     let row = ContentItems::find_by_id(content_item_id)
+        .filter(models::content_items::Column::DeletedAt.is_null())
         .one(&state.postgres)
         .await;
 
@@ -555,3 +849,258 @@ pub async fn get_content_item(
             .into_response(),
     }
 }
+
+#[derive(Serialize)]
+pub struct CsvImportReport {
+    // 作成と更新を合わせた件数
+    imported: usize,
+    updated: usize,
+    ignored_columns: Vec<String>,
+    errors: Vec<RowError>,
+}
+
+// サービスに属するコンテンツタイプのフィールドを定義順に取得する。コンテンツタイプがなければNone
+async fn find_fields_of(
+    state: &AppState,
+    service_id: String,
+    content_type_id: i32,
+) -> Result<Option<Vec<Model>>, sea_orm::DbErr> {
+    let content_type = ContentTypes::find_by_id(content_type_id)
+        .filter(models::content_types::Column::ServiceId.eq(service_id))
+        .find_with_related(Fields)
+        .order_by_asc(fields::Column::Id)
+        .all(&state.postgres)
+        .await?;
+    Ok(content_type.into_iter().next().map(|(_, fields)| fields))
+}
+
+pub async fn export_content_items_csv(
+    State(state): State<AppState>,
+    Path((service_id, content_type_id)): Path<(String, i32)>,
+) -> impl IntoResponse {
+    let fields = match find_fields_of(&state, service_id, content_type_id).await {
+        Ok(Some(fields)) => fields,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                "コンテンツタイプが見つかりませんでした".to_string(),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("フィールドの取得に失敗しました: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    let items = ContentItems::find()
+        .filter(models::content_items::Column::ContentTypeId.eq(content_type_id))
+        .filter(models::content_items::Column::DeletedAt.is_null())
+        .order_by_asc(models::content_items::Column::CreatedAt)
+        .all(&state.postgres)
+        .await;
+
+    let csv = match items {
+        Ok(items) => write_csv(&fields, &items),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("コンテンツアイテムの取得に失敗しました: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    match csv {
+        Ok(csv) => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"content_items_{}.csv\"",
+                        content_type_id
+                    ),
+                ),
+            ],
+            csv,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("CSVの作成に失敗しました: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+// クエリパラメータ「map.<CSVのヘッダー名>=<フィールドのdisplay_id>」で列の対応を指定できる
+pub async fn import_content_items_csv(
+    State(state): State<AppState>,
+    Path((service_id, content_type_id)): Path<(String, i32)>,
+    audit: AuditLog,
+    Query(query): Query<HashMap<String, String>>,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let mapping = column_mapping(&query);
+    let fields = match find_fields_of(&state, service_id.clone(), content_type_id).await {
+        Ok(Some(fields)) => fields,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                "コンテンツタイプが見つかりませんでした".to_string(),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("フィールドの取得に失敗しました: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    let parsed = match read_csv(&fields, &body, &mapping) {
+        Ok(parsed) => parsed,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    //エラーのない行だけをまとめて書き込む。IDが同じコンテンツタイプの既存のアイテムと一致すれば更新し、
+    //それ以外(IDが空、ゴミ箱にある、他のコンテンツタイプのもの)は新しいIDで作成する
+    let imported = parsed.rows.len();
+    let result = state
+        .postgres
+        .transaction::<_, usize, DbErr>(|txn| {
+            Box::pin(async move {
+                let mut updated = 0;
+                for row in parsed.rows {
+                    let existing = match row.id {
+                        Some(id) => {
+                            ContentItems::find_by_id(id)
+                                .filter(
+                                    models::content_items::Column::ContentTypeId
+                                        .eq(content_type_id),
+                                )
+                                .filter(models::content_items::Column::DeletedAt.is_null())
+                                .one(txn)
+                                .await?
+                        }
+                        None => None,
+                    };
+                    if let Some(existing) = existing {
+                        let mut item: ContentItemModel = existing.into_active_model();
+                        item.data = Set(row.data);
+                        item.update(txn).await?;
+                        updated += 1;
+                        continue;
+                    }
+                    ContentItemModel {
+                        id: Set(Uuid::new_v4()),
+                        content_type_id: Set(content_type_id),
+                        data: Set(row.data),
+                        created_at: Default::default(),
+                        updated_at: Default::default(),
+                        deleted_at: Default::default(),
+                        deleted_by_key_id: Default::default(),
+                    }
+                    .insert(txn)
+                    .await?;
+                }
+                Ok(updated)
+            })
+        })
+        .await;
+
+    match result {
+        Ok(updated) => {
+            // 取り込んだアイテムは一件ずつではなく件数だけを記録する
+            audit
+                .record(
+                    AuditEvent::new("content_item.import", "content_type")
+                        .service(&service_id)
+                        .target(content_type_id)
+                        .after(&json!({ "imported": imported, "updated": updated })),
+                )
+                .await;
+            let status = if parsed.errors.is_empty() {
+                StatusCode::CREATED
+            } else {
+                StatusCode::OK
+            };
+            (
+                status,
+                Json(CsvImportReport {
+                    imported,
+                    updated,
+                    ignored_columns: parsed.ignored_columns,
+                    errors: parsed.errors,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("コンテンツアイテムの作成に失敗しました: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_cells_are_coerced_to_the_field_type() {
+        assert_eq!(FieldType::Text.coerce_str(" as is "), Ok(json!(" as is ")));
+        assert_eq!(FieldType::Number.coerce_str(" 42 "), Ok(json!(42)));
+        assert_eq!(FieldType::Number.coerce_str("-1.5"), Ok(json!(-1.5)));
+        assert_eq!(
+            FieldType::Number.coerce_str("NaN"),
+            Err("\"NaN\" is not a number".to_string())
+        );
+        assert_eq!(
+            FieldType::Date.coerce_str("2023-06-01T09:00:00+09:00"),
+            Ok(json!("2023-06-01T09:00:00+09:00"))
+        );
+        assert_eq!(
+            FieldType::Date.coerce_str("2023-06-01"),
+            Err("\"2023-06-01\" is not an RFC 3339 date".to_string())
+        );
+        for raw in ["true", "TRUE", "1", "yes"] {
+            assert_eq!(FieldType::Boolean.coerce_str(raw), Ok(json!(true)));
+        }
+        for raw in ["false", "0", "No"] {
+            assert_eq!(FieldType::Boolean.coerce_str(raw), Ok(json!(false)));
+        }
+        assert_eq!(
+            FieldType::Boolean.coerce_str(" on "),
+            Err("\"on\" is not a boolean".to_string())
+        );
+    }
+
+    #[test]
+    fn coerced_values_match_the_field_type() {
+        for (field_type, raw) in [
+            (FieldType::Text, "text"),
+            (FieldType::Number, "3.25"),
+            (FieldType::Date, "2023-06-01T00:00:00Z"),
+            (FieldType::Boolean, "yes"),
+        ] {
+            let field = Field {
+                id: 1,
+                content_type_id: 1,
+                display_id: "value".to_string(),
+                field_type,
+                required: false,
+                created_at: chrono::Utc::now().into(),
+                updated_at: chrono::Utc::now().into(),
+            };
+            assert!(field.field_type_matches(&field_type.coerce_str(raw).unwrap()));
+        }
+    }
+}
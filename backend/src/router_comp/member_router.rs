@@ -0,0 +1,505 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use sea_orm::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    DatabaseTransaction, IntoActiveModel, NotSet, QueryOrder, QuerySelect, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::libs::audit::{AuditEvent, AuditLog};
+use crate::libs::auth::Principal;
+use crate::libs::generate_random_key::generate_key;
+use crate::libs::mailer::Template;
+use crate::libs::membership::{add_member, find_member, require_role, MemberIdentity, MemberRole};
+use crate::libs::token_hash::hash_token;
+use crate::models::prelude::{ServiceInvitations, ServiceMembers, Services};
+use crate::models::service_invitations::ActiveModel as InvitationModel;
+use crate::models::{service_invitations, service_members, services};
+use crate::router_comp::auth_router::require_verified_email;
+use crate::AppState;
+
+// 招待リンクの有効期間
+const INVITATION_TTL_DAYS: i64 = 7;
+
+#[derive(Deserialize)]
+pub struct UpdateMember {
+    role: MemberRole,
+}
+
+#[derive(Deserialize)]
+pub struct CreateInvitation {
+    email: String,
+    role: MemberRole,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptInvitation {
+    token: String,
+}
+
+// トークンのハッシュは返さない
+#[derive(Serialize)]
+pub struct InvitationSummary {
+    id: i32,
+    email: String,
+    role: String,
+    invited_by: Option<i32>,
+    created_at: DateTimeWithTimeZone,
+    expires_at: DateTimeWithTimeZone,
+}
+
+impl From<service_invitations::Model> for InvitationSummary {
+    fn from(invitation: service_invitations::Model) -> Self {
+        InvitationSummary {
+            id: invitation.id,
+            email: invitation.email,
+            role: invitation.role,
+            invited_by: invitation.invited_by,
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
+
+fn database_error(action: &str, e: DbErr) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Failed to {}: {}", action, e),
+    )
+        .into_response()
+}
+
+fn member_not_found() -> Response {
+    (StatusCode::NOT_FOUND, "Member not found".to_string()).into_response()
+}
+
+fn last_owner() -> Response {
+    (
+        StatusCode::CONFLICT,
+        "A service must keep at least one owner".to_string(),
+    )
+        .into_response()
+}
+
+// オーナーの行をロックして、except以外のオーナーがいるかを調べる。
+// 同時に二人のオーナーが互いを外してオーナーがいなくなることを防ぐ
+async fn has_other_owner(
+    txn: &DatabaseTransaction,
+    service_id: &str,
+    except: i32,
+) -> Result<bool, DbErr> {
+    let owners = ServiceMembers::find()
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .filter(service_members::Column::Role.eq(MemberRole::Owner.to_string()))
+        .lock_exclusive()
+        .all(txn)
+        .await?;
+    Ok(owners.iter().any(|owner| owner.id != except))
+}
+
+async fn find_service_member(
+    txn: &DatabaseTransaction,
+    service_id: &str,
+    member_id: i32,
+) -> Result<Option<service_members::Model>, DbErr> {
+    ServiceMembers::find_by_id(member_id)
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .one(txn)
+        .await
+}
+
+pub async fn list_members(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Viewer).await
+    {
+        return e.into_response();
+    }
+    let result = ServiceMembers::find()
+        .filter(service_members::Column::ServiceId.eq(service_id))
+        .order_by_asc(service_members::Column::Id)
+        .all(&state.postgres)
+        .await;
+    match result {
+        Ok(members) => Json(members).into_response(),
+        Err(e) => database_error("list members", e),
+    }
+}
+
+// オーナーに関わる変更(オーナーにする・オーナーを外す)はオーナーだけが行える
+pub async fn update_member(
+    Path((service_id, member_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    Json(update): Json<UpdateMember>,
+) -> Response {
+    let txn = match state.postgres.begin().await {
+        Ok(txn) => txn,
+        Err(e) => return database_error("update the member", e),
+    };
+    let actor = match require_role(&txn, &principal, &service_id, MemberRole::Admin).await {
+        Ok(actor) => actor,
+        Err(e) => return e.into_response(),
+    };
+    let member = match find_service_member(&txn, &service_id, member_id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return member_not_found(),
+        Err(e) => return database_error("update the member", e),
+    };
+
+    let current = MemberRole::of(&member);
+    if (current == MemberRole::Owner || update.role == MemberRole::Owner)
+        && MemberRole::of(&actor) < MemberRole::Owner
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            "Only an owner can change the owners of the service".to_string(),
+        )
+            .into_response();
+    }
+    if current == MemberRole::Owner && update.role != MemberRole::Owner {
+        match has_other_owner(&txn, &service_id, member.id).await {
+            Ok(true) => {}
+            Ok(false) => return last_owner(),
+            Err(e) => return database_error("update the member", e),
+        }
+    }
+
+    let before = member.clone();
+    let mut member = member.into_active_model();
+    member.role = Set(update.role.to_string());
+    let result = match member.update(&txn).await {
+        Ok(member) => txn.commit().await.map(|_| member),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(member) => {
+            audit
+                .record(
+                    AuditEvent::new("member.update", "member")
+                        .service(&service_id)
+                        .target(member.id)
+                        .before(&before)
+                        .after(&member),
+                )
+                .await;
+            Json(member).into_response()
+        }
+        Err(e) => database_error("update the member", e),
+    }
+}
+
+// 管理者は他のメンバーを外せる。メンバーは自分からサービスを抜けられる
+pub async fn remove_member(
+    Path((service_id, member_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+) -> Response {
+    let txn = match state.postgres.begin().await {
+        Ok(txn) => txn,
+        Err(e) => return database_error("remove the member", e),
+    };
+    let actor = match require_role(&txn, &principal, &service_id, MemberRole::Viewer).await {
+        Ok(actor) => actor,
+        Err(e) => return e.into_response(),
+    };
+    let member = match find_service_member(&txn, &service_id, member_id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return member_not_found(),
+        Err(e) => return database_error("remove the member", e),
+    };
+
+    let required = match MemberRole::of(&member) {
+        MemberRole::Owner => MemberRole::Owner,
+        _ => MemberRole::Admin,
+    };
+    if actor.id != member.id && MemberRole::of(&actor) < required {
+        return (
+            StatusCode::FORBIDDEN,
+            format!("This requires the {} role on the service", required),
+        )
+            .into_response();
+    }
+    if MemberRole::of(&member) == MemberRole::Owner {
+        match has_other_owner(&txn, &service_id, member.id).await {
+            Ok(true) => {}
+            Ok(false) => return last_owner(),
+            Err(e) => return database_error("remove the member", e),
+        }
+    }
+
+    let result = match ServiceMembers::delete_by_id(member.id).exec(&txn).await {
+        Ok(_) => txn.commit().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            audit
+                .record(
+                    AuditEvent::new("member.remove", "member")
+                        .service(&service_id)
+                        .target(member.id)
+                        .before(&member),
+                )
+                .await;
+            (StatusCode::OK, "Member removed".to_string()).into_response()
+        }
+        Err(e) => database_error("remove the member", e),
+    }
+}
+
+pub async fn create_invitation(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    headers: HeaderMap,
+    Json(invitation): Json<CreateInvitation>,
+) -> Response {
+    let actor =
+        match require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await {
+            Ok(actor) => actor,
+            Err(e) => return e.into_response(),
+        };
+    if invitation.role == MemberRole::Owner && MemberRole::of(&actor) < MemberRole::Owner {
+        return (
+            StatusCode::FORBIDDEN,
+            "Only an owner can invite another owner".to_string(),
+        )
+            .into_response();
+    }
+    let Some(mailer) = state.mailer.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Mail is not configured".to_string(),
+        )
+            .into_response();
+    };
+    let email = invitation.email.trim().to_string();
+    if email.parse::<lettre::Address>().is_err() {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid email address: {}", email),
+        )
+            .into_response();
+    }
+    let service = match Services::find_by_id(service_id.clone())
+        .one(&state.postgres)
+        .await
+    {
+        Ok(Some(service)) => service,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response()
+        }
+        Err(e) => return database_error("create the invitation", e),
+    };
+
+    let token = generate_key(48);
+    let invitation = InvitationModel {
+        id: NotSet,
+        service_id: Set(service_id),
+        email: Set(email),
+        role: Set(invitation.role.to_string()),
+        token_hash: Set(hash_token(&token)),
+        invited_by: Set(Some(actor.id)),
+        created_at: NotSet,
+        expires_at: Set((chrono::Utc::now() + chrono::Duration::days(INVITATION_TTL_DAYS)).into()),
+        accepted_at: NotSet,
+    };
+    let invitation = match invitation.insert(&state.postgres).await {
+        Ok(invitation) => invitation,
+        Err(e) => return database_error("create the invitation", e),
+    };
+
+    let link = state
+        .config
+        .server
+        .frontend_link(&format!("/invitations/accept?token={}", token));
+    let template = Template::ServiceInvitation {
+        service_name: &service.name,
+        role: &invitation.role,
+        link: &link,
+        ttl_days: INVITATION_TTL_DAYS,
+    };
+    if let Err(e) = mailer.send(&invitation.email, mailer.locale(&headers), template) {
+        eprintln!("{}", e);
+    }
+
+    let service_id = invitation.service_id.clone();
+    let summary = InvitationSummary::from(invitation);
+    audit
+        .record(
+            AuditEvent::new("invitation.create", "invitation")
+                .service(&service_id)
+                .target(summary.id)
+                .after(&summary),
+        )
+        .await;
+    (StatusCode::CREATED, Json(summary)).into_response()
+}
+
+// 受け入れも期限切れもしていない招待
+pub async fn list_invitations(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await
+    {
+        return e.into_response();
+    }
+    let result = ServiceInvitations::find()
+        .filter(service_invitations::Column::ServiceId.eq(service_id))
+        .filter(service_invitations::Column::AcceptedAt.is_null())
+        .filter(service_invitations::Column::ExpiresAt.gt(chrono::Utc::now()))
+        .order_by_asc(service_invitations::Column::Id)
+        .all(&state.postgres)
+        .await;
+    match result {
+        Ok(invitations) => Json(
+            invitations
+                .into_iter()
+                .map(InvitationSummary::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => database_error("list invitations", e),
+    }
+}
+
+pub async fn revoke_invitation(
+    Path((service_id, invitation_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await
+    {
+        return e.into_response();
+    }
+    let result = ServiceInvitations::delete_many()
+        .filter(service_invitations::Column::ServiceId.eq(service_id.as_str()))
+        .filter(service_invitations::Column::Id.eq(invitation_id))
+        .filter(service_invitations::Column::AcceptedAt.is_null())
+        .exec(&state.postgres)
+        .await;
+    match result {
+        Ok(result) if result.rows_affected > 0 => {
+            audit
+                .record(
+                    AuditEvent::new("invitation.revoke", "invitation")
+                        .service(&service_id)
+                        .target(invitation_id),
+                )
+                .await;
+            (StatusCode::OK, "Invitation revoked".to_string()).into_response()
+        }
+        Ok(_) => (StatusCode::NOT_FOUND, "Invitation not found".to_string()).into_response(),
+        Err(e) => database_error("revoke the invitation", e),
+    }
+}
+
+// 招待されたメールアドレスで認証している利用者だけが受け入れられる
+pub async fn accept_invitation(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    Json(accept): Json<AcceptInvitation>,
+) -> Response {
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            "The invitation is invalid or has expired".to_string(),
+        )
+            .into_response()
+    };
+    let invitation = ServiceInvitations::find()
+        .inner_join(Services)
+        .filter(service_invitations::Column::TokenHash.eq(hash_token(&accept.token)))
+        .filter(services::Column::DeletedAt.is_null())
+        .filter(service_invitations::Column::AcceptedAt.is_null())
+        .filter(service_invitations::Column::ExpiresAt.gt(chrono::Utc::now()))
+        .one(&state.postgres)
+        .await;
+    let invitation = match invitation {
+        Ok(Some(invitation)) => invitation,
+        Ok(None) => return invalid(),
+        Err(e) => return database_error("accept the invitation", e),
+    };
+    let email_matches = principal
+        .email
+        .as_deref()
+        .is_some_and(|email| email.eq_ignore_ascii_case(&invitation.email));
+    if !email_matches {
+        return (
+            StatusCode::FORBIDDEN,
+            "The invitation was sent to a different email address".to_string(),
+        )
+            .into_response();
+    }
+    if let Err(response) = require_verified_email(&state, &principal).await {
+        return response;
+    }
+
+    let txn = match state.postgres.begin().await {
+        Ok(txn) => txn,
+        Err(e) => return database_error("accept the invitation", e),
+    };
+    match find_member(&txn, &principal, &invitation.service_id).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                "You are already a member of the service".to_string(),
+            )
+                .into_response()
+        }
+        Err(e) => return database_error("accept the invitation", e),
+    }
+    // 同じ招待を二度使えないように、未使用の場合だけ使用済みにする
+    let consumed = ServiceInvitations::update_many()
+        .col_expr(
+            service_invitations::Column::AcceptedAt,
+            Expr::value(chrono::Utc::now()),
+        )
+        .filter(service_invitations::Column::Id.eq(invitation.id))
+        .filter(service_invitations::Column::AcceptedAt.is_null())
+        .exec(&txn)
+        .await;
+    match consumed {
+        Ok(result) if result.rows_affected > 0 => {}
+        Ok(_) => return invalid(),
+        Err(e) => return database_error("accept the invitation", e),
+    }
+
+    let role = invitation.role.parse().unwrap_or(MemberRole::Viewer);
+    let identity = MemberIdentity::from(&principal);
+    let result = match add_member(&txn, &invitation.service_id, &identity, role).await {
+        Ok(member) => txn.commit().await.map(|_| member),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(member) => {
+            audit
+                .record(
+                    AuditEvent::new("invitation.accept", "invitation")
+                        .service(&member.service_id)
+                        .target(invitation.id)
+                        .after(&member),
+                )
+                .await;
+            (StatusCode::CREATED, Json(member)).into_response()
+        }
+        Err(e) => database_error("accept the invitation", e),
+    }
+}
@@ -1,3 +1,7 @@
+pub mod audit_router;
 pub mod auth_router;
 pub mod content_router;
+pub mod member_router;
+pub mod schema_router;
 pub mod service_router;
+pub mod usage_router;
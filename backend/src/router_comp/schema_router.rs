@@ -0,0 +1,53 @@
+use crate::libs::content_model::load_service_content_model;
+use crate::libs::openapi::{management_document, service_document};
+use crate::libs::typescript::render_declarations;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+
+pub async fn get_service_openapi(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match load_service_content_model(&state.postgres, &service_id).await {
+        Ok(Some((service, content_model))) => {
+            Json(service_document(&service, &content_model)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to load content model: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_service_typescript(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match load_service_content_model(&state.postgres, &service_id).await {
+        Ok(Some((service, content_model))) => (
+            [(
+                header::CONTENT_TYPE,
+                "application/typescript; charset=utf-8",
+            )],
+            render_declarations(&service, &content_model),
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to load content model: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_management_openapi() -> impl IntoResponse {
+    Json(management_document())
+}
@@ -1,16 +1,27 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    Json,
+    body::StreamBody,
+    extract::{Path, Query, State},
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
-use sea_orm::ActiveValue::Set;
-use sea_orm::{ActiveModelTrait, EntityTrait};
+use futures::StreamExt;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
 use std::collections::HashSet;
-use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::{fmt, io};
 
-use crate::libs::generate_random_key::generate_key;
+use crate::libs::api_keys::{self, ApiKeySummary};
+use crate::libs::audit::{AuditEvent, AuditLog};
+use crate::libs::auth::Principal;
+use crate::libs::management;
+use crate::libs::membership::{
+    find_member, require_role, MemberIdentity, MemberRole, MembershipError,
+};
+use crate::libs::service_archive::{export_service, import_service, parse_archive, ImportError};
+use crate::router_comp::auth_router::require_verified_email;
 use crate::{models, AppState};
 use serde::{Deserialize, Serialize};
 
@@ -19,7 +30,7 @@ pub struct CreateService {
     name: String,
 }
 
-#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Permission {
     Post,
     Get,
@@ -40,6 +51,38 @@ impl Display for Permission {
     }
 }
 
+impl FromStr for Permission {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Post" => Ok(Permission::Post),
+            "Get" => Ok(Permission::Get),
+            "Put" => Ok(Permission::Put),
+            "Patch" => Ok(Permission::Patch),
+            "Delete" => Ok(Permission::Delete),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid permission",
+            )),
+        }
+    }
+}
+
+impl Permission {
+    // コンテンツAPIのメソッドに必要な権限
+    pub fn for_method(method: &Method) -> Option<Permission> {
+        match *method {
+            Method::GET | Method::HEAD => Some(Permission::Get),
+            Method::POST => Some(Permission::Post),
+            Method::PUT => Some(Permission::Put),
+            Method::PATCH => Some(Permission::Patch),
+            Method::DELETE => Some(Permission::Delete),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Role {
     pub name: String,
@@ -50,70 +93,33 @@ pub struct Role {
 pub struct Service {
     pub id: String,
     pub name: String,
-    pub api_key: String,
 }
 
 pub async fn create_service(
     State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
     Json(create_service): Json<CreateService>,
 ) -> impl IntoResponse {
-    let service_id = generate_key(16);
-    let api_key = generate_key(32);
-
-    let new_service = models::services::ActiveModel {
-        id: Set(service_id.clone()),
-        name: Set(create_service.name),
-        api_key: Set(api_key.clone()),
-    };
-
-    let service_result = new_service.insert(&state.postgres).await;
-
-    match service_result {
-        Ok(service) => {
-            let new_role = models::roles::ActiveModel {
-                id: Default::default(),
-                name: Set("Admin".to_string()),
-                service_id: Set(service_id),
-                api_key: Set(api_key),
-            };
-
-            let role_result = new_role.insert(&state.postgres).await;
-
-            let role_id: i32 = match role_result {
-                Ok(role) => role.id,
-                Err(e) => {
-                    eprint!("{}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
-                }
-            };
-
-            let permissions = [
-                Permission::Post,
-                Permission::Get,
-                Permission::Put,
-                Permission::Patch,
-                Permission::Delete,
-            ];
-
-            for permission in permissions.iter() {
-                let new_permission = models::role_permissions::ActiveModel {
-                    role_id: Set(role_id),
-                    permission: Set(permission.to_string()),
-                };
-
-                let permission_result = new_permission.insert(&state.postgres).await;
-
-                if permission_result.is_err() {
-                    eprint!("{}", permission_result.err().unwrap());
-                    return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
-                }
-            }
-
+    if let Err(response) = require_verified_email(&state, &principal).await {
+        return response;
+    }
+    let owner = MemberIdentity::from(&principal);
+    match management::create_service(&state.postgres, create_service.name, Some(&owner)).await {
+        Ok((service, api_key)) => {
+            audit
+                .record(
+                    AuditEvent::new("service.create", "service")
+                        .service(&service.id)
+                        .target(&service.id)
+                        .after(&service),
+                )
+                .await;
             (
                 StatusCode::CREATED,
                 format!(
                     "Service {} created with \n API key: {} \n Service ID: {}",
-                    service.name, service.api_key, service.id
+                    service.name, api_key, service.id
                 ),
             )
                 .into_response()
@@ -125,66 +131,661 @@ pub async fn create_service(
     }
 }
 
+// 利用者がメンバーになっているサービスの一覧
+#[derive(Serialize)]
+pub struct MemberService {
+    id: String,
+    name: String,
+    role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTimeWithTimeZone>,
+}
+
+#[derive(Deserialize)]
+pub struct ListServicesOptions {
+    // trueなら復元できる削除済みのサービスを返す
+    #[serde(default)]
+    deleted: bool,
+}
+
+pub async fn list_services(
+    Query(options): Query<ListServicesOptions>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> impl IntoResponse {
+    let result = models::prelude::ServiceMembers::find()
+        .filter(models::service_members::Column::Provider.eq(principal.provider))
+        .filter(models::service_members::Column::Subject.eq(principal.subject.as_str()))
+        .order_by_asc(models::service_members::Column::ServiceId)
+        .find_also_related(models::prelude::Services)
+        .all(&state.postgres)
+        .await;
+    match result {
+        Ok(rows) => {
+            let services: Vec<MemberService> = rows
+                .into_iter()
+                .filter_map(|(member, service)| {
+                    service
+                        .filter(|service| service.deleted_at.is_some() == options.deleted)
+                        .map(|service| MemberService {
+                            id: service.id,
+                            name: service.name,
+                            role: member.role,
+                            deleted_at: service.deleted_at,
+                        })
+                })
+                .collect();
+            (StatusCode::OK, Json(services)).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list services: {}", e),
+        )
+            .into_response(),
+    }
+}
+
 pub async fn create_role(
     Path(service_id): Path<String>,
     State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
     role: Json<Role>,
 ) -> impl IntoResponse {
-    let api_key = generate_key(32);
-
-    let new_role = models::roles::ActiveModel {
-        id: Default::default(),
-        name: Set(role.name.clone()),
-        service_id: Set(service_id),
-        api_key: Set(api_key.clone()),
-    };
-
-    let role_result = new_role.insert(&state.postgres).await;
-
-    let role_id: i32 = match role_result {
-        Ok(role) => role.id,
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await
+    {
+        return e.into_response();
+    }
+    if let Some(response) = invalid_role_name(&role.name) {
+        return response;
+    }
+    match management::create_role(&state.postgres, &service_id, &role.name, &role.permissions).await
+    {
+        Ok((created, api_key)) => {
+            audit
+                .record(
+                    AuditEvent::new("role.create", "role")
+                        .service(&service_id)
+                        .target(created.id)
+                        .after(&RoleSummary::new(created, role.permissions.clone())),
+                )
+                .await;
+            (StatusCode::CREATED, api_key).into_response()
+        }
         Err(e) => {
             eprint!("{}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
+            (StatusCode::INTERNAL_SERVER_ERROR).into_response()
         }
-    };
+    }
+}
 
-    for permission in role.permissions.iter() {
-        let new_permission = models::role_permissions::ActiveModel {
-            role_id: Set(role_id),
-            permission: Set(permission.to_string()),
-        };
+#[derive(Serialize)]
+pub struct RoleSummary {
+    id: i32,
+    name: String,
+    permissions: Vec<Permission>,
+}
 
-        let permission_result = new_permission.insert(&state.postgres).await;
+impl RoleSummary {
+    fn new(role: models::roles::Model, permissions: HashSet<Permission>) -> Self {
+        let mut permissions: Vec<Permission> = permissions.into_iter().collect();
+        permissions.sort_by_key(|p| p.to_string());
+        RoleSummary {
+            id: role.id,
+            name: role.name,
+            permissions,
+        }
+    }
+}
 
-        if permission_result.is_err() {
-            eprint!("{}", permission_result.err().unwrap());
-            return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
+#[derive(Deserialize)]
+pub struct UpdateRole {
+    name: Option<String>,
+    permissions: Option<HashSet<Permission>>,
+}
+
+// 自動で作られるAdminロールと紛らわしい名前は付けられない
+fn invalid_role_name(name: &str) -> Option<Response> {
+    if name.trim().is_empty() {
+        return Some((StatusCode::BAD_REQUEST, "Role name is empty".to_string()).into_response());
+    }
+    if name == management::ADMIN_ROLE {
+        return Some(
+            (
+                StatusCode::CONFLICT,
+                format!("The {} role already exists", management::ADMIN_ROLE),
+            )
+                .into_response(),
+        );
+    }
+    None
+}
+
+fn admin_role_is_protected() -> Response {
+    (
+        StatusCode::CONFLICT,
+        format!(
+            "The {} role is created with the service and cannot be changed or deleted",
+            management::ADMIN_ROLE
+        ),
+    )
+        .into_response()
+}
+
+async fn find_role(
+    state: &AppState,
+    service_id: &str,
+    role_id: i32,
+) -> Result<(models::roles::Model, HashSet<Permission>), Response> {
+    match management::find_role(&state.postgres, service_id, role_id).await {
+        Ok(Some(role)) => Ok(role),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Role not found".to_string()).into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to find the role: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+pub async fn list_roles(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Viewer).await
+    {
+        return e.into_response();
+    }
+    match management::list_roles(&state.postgres, &service_id).await {
+        Ok(roles) => Json(
+            roles
+                .into_iter()
+                .map(|(role, permissions)| RoleSummary::new(role, permissions))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list roles: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_role(
+    Path((service_id, role_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Viewer).await
+    {
+        return e.into_response();
+    }
+    match find_role(&state, &service_id, role_id).await {
+        Ok((role, permissions)) => Json(RoleSummary::new(role, permissions)).into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn update_role(
+    Path((service_id, role_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    Json(update): Json<UpdateRole>,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await
+    {
+        return e.into_response();
+    }
+    let (role, permissions) = match find_role(&state, &service_id, role_id).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+    if management::is_admin_role(&role) {
+        return admin_role_is_protected();
+    }
+    if let Some(response) = update.name.as_deref().and_then(invalid_role_name) {
+        return response;
+    }
+    let before = RoleSummary::new(role, permissions);
+
+    let result = management::update_role(
+        &state.postgres,
+        role_id,
+        update.name,
+        update.permissions.as_ref(),
+    )
+    .await;
+    if let Err(e) = result {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to update the role: {}", e),
+        )
+            .into_response();
+    }
+    match find_role(&state, &service_id, role_id).await {
+        Ok((role, permissions)) => {
+            let after = RoleSummary::new(role, permissions);
+            audit
+                .record(
+                    AuditEvent::new("role.update", "role")
+                        .service(&service_id)
+                        .target(role_id)
+                        .before(&before)
+                        .after(&after),
+                )
+                .await;
+            Json(after).into_response()
         }
+        Err(response) => response,
     }
+}
 
-    (StatusCode::CREATED, api_key).into_response()
+// ロールを削除すると、そのロールのAPIキーも使えなくなる
+pub async fn delete_role(
+    Path((service_id, role_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await
+    {
+        return e.into_response();
+    }
+    let (role, permissions) = match find_role(&state, &service_id, role_id).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+    if management::is_admin_role(&role) {
+        return admin_role_is_protected();
+    }
+    match management::delete_role(&state.postgres, role_id).await {
+        Ok(_) => {
+            audit
+                .record(
+                    AuditEvent::new("role.delete", "role")
+                        .service(&service_id)
+                        .target(role_id)
+                        .before(&RoleSummary::new(role, permissions)),
+                )
+                .await;
+            (StatusCode::OK, "Role deleted".to_string()).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to delete the role: {}", e),
+        )
+            .into_response(),
+    }
 }
 
+// サービスは削除済みになるだけで、retention.deleted_service_daysの間は復元できる
 pub async fn delete_service(
     Path(service_id): Path<String>,
     State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
 ) -> impl IntoResponse {
-    let result = models::prelude::Services::delete_by_id(service_id)
-        .exec(&state.postgres)
-        .await;
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Owner).await
+    {
+        return e.into_response();
+    }
+    match management::delete_service(&state.postgres, &service_id).await {
+        Ok(Some(deleted_at)) => {
+            audit
+                .record(
+                    AuditEvent::new("service.delete", "service")
+                        .service(&service_id)
+                        .target(&service_id)
+                        .after(&serde_json::json!({ "deleted_at": deleted_at })),
+                )
+                .await;
+            let restorable_until = deleted_at
+                + chrono::Duration::days(state.config.retention.deleted_service_days.into());
+            (
+                StatusCode::OK,
+                format!(
+                    "Service deleted. It can be restored until {}",
+                    restorable_until.to_rfc3339()
+                ),
+            )
+                .into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to delete service: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+// 削除済みのサービスを、パージされる前であればオーナーが元に戻せる
+pub async fn restore_service(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+) -> Response {
+    match find_member(&state.postgres, &principal, &service_id).await {
+        Ok(Some(member)) if MemberRole::of(&member) == MemberRole::Owner => {}
+        Ok(Some(_)) => return MembershipError::Forbidden(MemberRole::Owner).into_response(),
+        Ok(None) => return MembershipError::NotFound.into_response(),
+        Err(e) => return MembershipError::Db(e).into_response(),
+    }
+    match management::restore_service(&state.postgres, &service_id).await {
+        Ok(true) => {
+            audit
+                .record(
+                    AuditEvent::new("service.restore", "service")
+                        .service(&service_id)
+                        .target(&service_id),
+                )
+                .await;
+            (StatusCode::OK, "Service restored".to_string()).into_response()
+        }
+        Ok(false) => (StatusCode::CONFLICT, "Service is not deleted".to_string()).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to restore service: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportOptions {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+pub async fn export_service_archive(
+    Path(service_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> impl IntoResponse {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await
+    {
+        return e.into_response();
+    }
+    let service = match models::prelude::Services::find_by_id(service_id)
+        .one(&state.postgres)
+        .await
+    {
+        Ok(Some(service)) => service,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to export service: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    match export_service(&state.postgres, &service).await {
+        Ok(records) => {
+            let body = StreamBody::new(records.map(|record| record.map(|r| r.to_line())));
+            let disposition = format!("attachment; filename=\"{}.ndjson\"", service.id);
+            (
+                [
+                    (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                body,
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to export service: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn import_service_archive(
+    Path(service_id): Path<String>,
+    Query(options): Query<ImportOptions>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    archive: String,
+) -> impl IntoResponse {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Admin).await
+    {
+        return e.into_response();
+    }
+    match models::prelude::Services::find_by_id(service_id.clone())
+        .one(&state.postgres)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to import service: {}", e),
+            )
+                .into_response()
+        }
+    }
+
+    let result = match parse_archive(&archive) {
+        Ok(records) => import_service(&state.postgres, &service_id, records, options.dry_run).await,
+        Err(e) => Err(e),
+    };
 
     match result {
-        Ok(_) => {
-            if result.unwrap().rows_affected > 0 {
-                (StatusCode::OK, "Service deleted".to_string()).into_response()
+        Ok(report) => {
+            let status = if report.dry_run {
+                StatusCode::OK
             } else {
-                (StatusCode::NOT_FOUND, "Service not found".to_string()).into_response()
-            }
+                // 新しいロールのAPIキーは記録しない
+                let roles: Vec<&String> = report.roles.iter().map(|role| &role.name).collect();
+                audit
+                    .record(
+                        AuditEvent::new("service.import", "service")
+                            .service(&service_id)
+                            .target(&service_id)
+                            .after(&serde_json::json!({
+                                "source_service_id": report.source_service_id,
+                                "content_types": report.content_types.len(),
+                                "fields": report.fields,
+                                "roles": roles,
+                                "content_items": report.content_items.len(),
+                            })),
+                    )
+                    .await;
+                StatusCode::CREATED
+            };
+            (status, Json(report)).into_response()
+        }
+        Err(e @ ImportError::Invalid(_)) => {
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
         }
+        Err(e @ ImportError::Database(_)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to import service: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IssueApiKey {
+    name: Option<String>,
+    expires_at: Option<DateTimeWithTimeZone>,
+}
+
+#[derive(Deserialize)]
+pub struct RotateApiKey {
+    expires_at: Option<DateTimeWithTimeZone>,
+    // 古いキーを使い続けられる秒数。0なら直ちに失効させる
+    #[serde(default)]
+    grace_seconds: u32,
+}
+
+// サービスの管理者であり、ロールがそのサービスのものであることを確かめる
+async fn authorize_role(
+    state: &AppState,
+    principal: &Principal,
+    service_id: &str,
+    role_id: i32,
+) -> Result<(), Response> {
+    require_role(&state.postgres, principal, service_id, MemberRole::Admin)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let role = models::prelude::Roles::find_by_id(role_id)
+        .filter(models::roles::Column::ServiceId.eq(service_id))
+        .one(&state.postgres)
+        .await;
+    match role {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Role not found".to_string()).into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to find the role: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+fn past_expiry(expires_at: Option<DateTimeWithTimeZone>) -> Option<Response> {
+    match expires_at {
+        Some(expires_at) if expires_at <= chrono::Utc::now() => Some(
+            (
+                StatusCode::BAD_REQUEST,
+                "expires_at must be in the future".to_string(),
+            )
+                .into_response(),
+        ),
+        _ => None,
+    }
+}
+
+pub async fn list_api_keys(
+    Path((service_id, role_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Err(response) = authorize_role(&state, &principal, &service_id, role_id).await {
+        return response;
+    }
+    match api_keys::list_keys(&state.postgres, role_id).await {
+        Ok(keys) => Json(
+            keys.into_iter()
+                .map(ApiKeySummary::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to delete service: {}", e),
+            format!("Failed to list API keys: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+// 平文のキーは応答でだけ返し、後から取り出すことはできない
+pub async fn issue_api_key(
+    Path((service_id, role_id)): Path<(String, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    Json(issue): Json<IssueApiKey>,
+) -> Response {
+    if let Err(response) = authorize_role(&state, &principal, &service_id, role_id).await {
+        return response;
+    }
+    if let Some(response) = past_expiry(issue.expires_at) {
+        return response;
+    }
+    match api_keys::issue_key(&state.postgres, role_id, issue.name, issue.expires_at).await {
+        Ok(issued) => {
+            audit
+                .record(
+                    AuditEvent::new("api_key.issue", "api_key")
+                        .service(&service_id)
+                        .target(issued.summary.id)
+                        .after(&issued.summary),
+                )
+                .await;
+            (StatusCode::CREATED, Json(issued)).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to issue an API key: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn rotate_api_key(
+    Path((service_id, role_id, key_id)): Path<(String, i32, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+    Json(rotate): Json<RotateApiKey>,
+) -> Response {
+    if let Err(response) = authorize_role(&state, &principal, &service_id, role_id).await {
+        return response;
+    }
+    if let Some(response) = past_expiry(rotate.expires_at) {
+        return response;
+    }
+    let grace = chrono::Duration::seconds(rotate.grace_seconds.into());
+    match api_keys::rotate_key(&state.postgres, role_id, key_id, rotate.expires_at, grace).await {
+        Ok(Some(issued)) => {
+            audit
+                .record(
+                    AuditEvent::new("api_key.rotate", "api_key")
+                        .service(&service_id)
+                        .target(key_id)
+                        .after(&issued.summary),
+                )
+                .await;
+            (StatusCode::CREATED, Json(issued)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "API key not found".to_string()).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to rotate the API key: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn revoke_api_key(
+    Path((service_id, role_id, key_id)): Path<(String, i32, i32)>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    audit: AuditLog,
+) -> Response {
+    if let Err(response) = authorize_role(&state, &principal, &service_id, role_id).await {
+        return response;
+    }
+    match api_keys::revoke_key(&state.postgres, role_id, key_id).await {
+        Ok(true) => {
+            audit
+                .record(
+                    AuditEvent::new("api_key.revoke", "api_key")
+                        .service(&service_id)
+                        .target(key_id),
+                )
+                .await;
+            (StatusCode::OK, "API key revoked".to_string()).into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, "API key not found".to_string()).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to revoke the API key: {}", e),
         )
             .into_response(),
     }
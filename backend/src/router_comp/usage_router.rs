@@ -0,0 +1,79 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::libs::auth::Principal;
+use crate::libs::membership::{require_role, MemberRole};
+use crate::libs::usage::{self, DailyUsage, UsageCounts};
+use crate::AppState;
+
+// 期間を指定しなかったときに返す日数
+const DEFAULT_DAYS: i64 = 30;
+// 一度に返せる最長の期間
+const MAX_DAYS: i64 = 366;
+
+#[derive(Deserialize)]
+pub struct UsageRange {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct UsageReport {
+    from: NaiveDate,
+    to: NaiveDate,
+    total: UsageCounts,
+    days: Vec<DailyUsage>,
+}
+
+// サービスのコンテンツAPIの利用量を日ごと・コンテンツタイプごとに返す。
+// 集計は一定間隔で書き込むので、直近の呼び出しはまだ含まれないことがある
+pub async fn get_service_usage(
+    Path(service_id): Path<String>,
+    Query(range): Query<UsageRange>,
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    if let Err(e) = require_role(&state.postgres, &principal, &service_id, MemberRole::Viewer).await
+    {
+        return e.into_response();
+    }
+    let to = range.to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let from = range
+        .from
+        .unwrap_or(to - chrono::Duration::days(DEFAULT_DAYS - 1));
+    if from > to {
+        return (
+            StatusCode::BAD_REQUEST,
+            "from must not be after to".to_string(),
+        )
+            .into_response();
+    }
+    if (to - from).num_days() >= MAX_DAYS {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("The range must be at most {} days", MAX_DAYS),
+        )
+            .into_response();
+    }
+
+    match usage::daily_usage(&state.pgpool, &service_id, from, to).await {
+        Ok(days) => Json(UsageReport {
+            from,
+            to,
+            total: usage::total(&days),
+            days,
+        })
+        .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get usage: {}", e),
+        )
+            .into_response(),
+    }
+}
@@ -1 +1,3057 @@
+use axum::body::Body;
+use axum::http::header::{
+    ACCEPT_LANGUAGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, COOKIE, RETRY_AFTER, SET_COOKIE,
+    USER_AGENT,
+};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::response::Response;
+use axum::{routing::get, Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use headless_cms::config::{
+    Config, MailConfig, MailFileConfig, MailTransportConfig, OidcConfig, PlanLimits,
+    StaticTokenConfig,
+};
+use headless_cms::libs::audit;
+use headless_cms::libs::auth::{
+    totp, AuthError, AuthProvider, OidcProvider, SessionKeys, StaticTokenProvider,
+};
+use headless_cms::libs::mailer::{Locale, Mailer};
+use headless_cms::libs::management;
+use headless_cms::libs::membership::{self, MemberRole};
+use headless_cms::libs::metrics::Metrics;
+use headless_cms::libs::rate_limit::RateLimiter;
+use headless_cms::libs::schema_version::{ensure_schema_not_ahead, run_migrations, MIGRATOR};
+use headless_cms::libs::token_hash::hash_token;
+use headless_cms::libs::trash;
+use headless_cms::libs::usage::UsageRecorder;
+use headless_cms::router::create_router;
+use headless_cms::AppState;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use sea_orm::SqlxPostgresConnector;
+use serde_json::{json, Value};
+use sqlx::postgres::PgPoolOptions;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tower::ServiceExt;
+use uuid::Uuid;
 
+const RSA_KEY: &[u8] = include_bytes!("fixtures/oidc_rsa_key.pem");
+const JWKS: &str = include_str!("fixtures/oidc_jwks.json");
+const AUDIENCE: &str = "https://cms.test";
+
+struct MockOidc {
+    issuer: String,
+    // 返すJWKS。鍵のローテーションを再現するために差し替えられる
+    jwks: Arc<Mutex<Value>>,
+    jwks_requests: Arc<AtomicUsize>,
+}
+
+// discoveryドキュメントとJWKSだけを返すOIDCプロバイダ
+fn start_mock_oidc(advertised_issuer: Option<&str>, jwks: Value) -> MockOidc {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let issuer = format!("http://{}/", addr);
+    let document = json!({
+        "issuer": advertised_issuer.unwrap_or(&issuer),
+        "jwks_uri": format!("{}jwks.json", issuer),
+    });
+    let jwks = Arc::new(Mutex::new(jwks));
+    let jwks_requests = Arc::new(AtomicUsize::new(0));
+
+    let served_jwks = jwks.clone();
+    let counter = jwks_requests.clone();
+    let app = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(move || async move { Json(document) }),
+        )
+        .route(
+            "/jwks.json",
+            get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let jwks = served_jwks.lock().unwrap().clone();
+                ([(CACHE_CONTROL, "public, max-age=600")], Json(jwks))
+            }),
+        );
+    tokio::spawn(async move {
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    });
+    MockOidc {
+        issuer,
+        jwks,
+        jwks_requests,
+    }
+}
+
+fn test_jwks() -> Value {
+    serde_json::from_str(JWKS).unwrap()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn sign(claims: Value, kid: &str) -> String {
+    sign_with(
+        Algorithm::RS256,
+        &EncodingKey::from_rsa_pem(RSA_KEY).unwrap(),
+        claims,
+        kid,
+    )
+}
+
+fn sign_with(algorithm: Algorithm, key: &EncodingKey, claims: Value, kid: &str) -> String {
+    let mut header = Header::new(algorithm);
+    header.kid = Some(kid.to_string());
+    encode(&header, &claims, key).unwrap()
+}
+
+fn bearer(token: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+    );
+    headers
+}
+
+fn oidc_config(issuer: &str, jwks_min_refetch_secs: u64) -> OidcConfig {
+    OidcConfig {
+        issuer: issuer.to_string(),
+        audience: AUDIENCE.to_string(),
+        jwks_min_refetch_secs,
+        ..Default::default()
+    }
+}
+
+async fn oidc_provider(issuer: &str) -> OidcProvider {
+    OidcProvider::discover(&oidc_config(issuer, 30))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn oidc_provider_accepts_token_signed_by_discovered_key() {
+    let issuer = start_mock_oidc(None, test_jwks()).issuer;
+    let provider = oidc_provider(&issuer).await;
+
+    let token = sign(
+        json!({
+            "sub": "user-1",
+            "email": "user@example.com",
+            "iss": issuer,
+            "aud": AUDIENCE,
+            "exp": now() + 300,
+        }),
+        "test-key",
+    );
+    let principal = provider.authenticate(&bearer(&token)).await.unwrap();
+
+    assert_eq!(principal.provider, "oidc");
+    assert_eq!(principal.subject, "user-1");
+    assert_eq!(principal.email.as_deref(), Some("user@example.com"));
+}
+
+#[tokio::test]
+async fn oidc_provider_rejects_invalid_tokens() {
+    let issuer = start_mock_oidc(None, test_jwks()).issuer;
+    let provider = oidc_provider(&issuer).await;
+    let claims =
+        |aud: &str, exp: u64| json!({ "sub": "user-1", "iss": issuer, "aud": aud, "exp": exp });
+
+    let cases = [
+        sign(claims("https://other.test", now() + 300), "test-key"),
+        sign(claims(AUDIENCE, now() - 3600), "test-key"),
+        sign(claims(AUDIENCE, now() + 300), "unknown-key"),
+        "not-a-jwt".to_string(),
+    ];
+    for token in cases {
+        let result = provider.authenticate(&bearer(&token)).await;
+        assert!(
+            matches!(result, Err(AuthError::Unauthorized(_))),
+            "{}",
+            token
+        );
+    }
+
+    let result = provider.authenticate(&HeaderMap::new()).await;
+    assert!(matches!(result, Err(AuthError::Unauthorized(_))));
+}
+
+#[tokio::test]
+async fn oidc_provider_rejects_tokens_whose_alg_does_not_match_the_key() {
+    let rsa_key = EncodingKey::from_rsa_pem(RSA_KEY).unwrap();
+
+    // JWKにalgがあれば、同じ鍵で署名されていても別のアルゴリズムは受け付けない
+    let issuer = start_mock_oidc(None, test_jwks()).issuer;
+    let provider = oidc_provider(&issuer).await;
+    let claims = valid_claims(&issuer);
+    let token = sign_with(Algorithm::RS384, &rsa_key, claims.clone(), "test-key");
+    let result = provider.authenticate(&bearer(&token)).await;
+    assert!(
+        matches!(&result, Err(AuthError::Unauthorized(message)) if message.contains("RS384")),
+        "{:?}",
+        result.map(|principal| principal.subject)
+    );
+
+    // algがなければ鍵の種類で決める。RSAの鍵ならRS384は使えるが、公開鍵を共通鍵にしたHMACは使えない
+    let mut jwks = test_jwks();
+    jwks["keys"][0].as_object_mut().unwrap().remove("alg");
+    let modulus = jwks["keys"][0]["n"].as_str().unwrap().to_string();
+    let issuer = start_mock_oidc(None, jwks).issuer;
+    let provider = oidc_provider(&issuer).await;
+    let claims = valid_claims(&issuer);
+    let token = sign_with(Algorithm::RS384, &rsa_key, claims.clone(), "test-key");
+    assert!(provider.authenticate(&bearer(&token)).await.is_ok());
+    let token = sign_with(
+        Algorithm::HS256,
+        &EncodingKey::from_secret(modulus.as_bytes()),
+        claims,
+        "test-key",
+    );
+    let result = provider.authenticate(&bearer(&token)).await;
+    assert!(matches!(result, Err(AuthError::Unauthorized(_))));
+}
+
+#[tokio::test]
+async fn oidc_discovery_rejects_mismatched_issuer() {
+    let issuer = start_mock_oidc(Some("https://attacker.test/"), test_jwks()).issuer;
+    let result = OidcProvider::discover(&oidc_config(&issuer, 30)).await;
+    assert!(result.is_err());
+}
+
+fn valid_claims(issuer: &str) -> Value {
+    json!({ "sub": "user-1", "iss": issuer, "aud": AUDIENCE, "exp": now() + 300 })
+}
+
+#[tokio::test]
+async fn oidc_provider_refetches_jwks_when_keys_rotate() {
+    let mock = start_mock_oidc(None, json!({ "keys": [] }));
+    let provider = OidcProvider::discover(&oidc_config(&mock.issuer, 0))
+        .await
+        .unwrap();
+    assert_eq!(mock.jwks_requests.load(Ordering::SeqCst), 1);
+
+    // IdPが新しい鍵を公開した後は、未知のkidをきっかけに取り直す
+    *mock.jwks.lock().unwrap() = test_jwks();
+    let token = sign(valid_claims(&mock.issuer), "test-key");
+    let principal = provider.authenticate(&bearer(&token)).await.unwrap();
+    assert_eq!(principal.subject, "user-1");
+    assert_eq!(mock.jwks_requests.load(Ordering::SeqCst), 2);
+
+    // 取得済みの鍵はキャッシュから使う
+    provider.authenticate(&bearer(&token)).await.unwrap();
+    assert_eq!(mock.jwks_requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn oidc_provider_rate_limits_refetches_for_unknown_kids() {
+    let mock = start_mock_oidc(None, test_jwks());
+    let provider = OidcProvider::discover(&oidc_config(&mock.issuer, 60))
+        .await
+        .unwrap();
+
+    for _ in 0..5 {
+        let token = sign(valid_claims(&mock.issuer), "unknown-key");
+        let result = provider.authenticate(&bearer(&token)).await;
+        assert!(matches!(result, Err(AuthError::Unauthorized(_))));
+    }
+    assert_eq!(mock.jwks_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn oidc_provider_starts_degraded_when_issuer_is_down() {
+    // 何も待ち受けていないポート
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let issuer = format!("http://{}/", listener.local_addr().unwrap());
+    drop(listener);
+
+    let provider = OidcProvider::discover(&oidc_config(&issuer, 0))
+        .await
+        .unwrap();
+    let token = sign(valid_claims(&issuer), "test-key");
+    let result = provider.authenticate(&bearer(&token)).await;
+    assert!(matches!(result, Err(AuthError::Unavailable(_))));
+}
+
+#[tokio::test]
+async fn static_token_provider_uses_token_name_as_subject() {
+    let provider = StaticTokenProvider::new(&StaticTokenConfig {
+        tokens: [("ci".to_string(), "0123456789abcdef".to_string())].into(),
+    });
+
+    let principal = provider
+        .authenticate(&bearer("0123456789abcdef"))
+        .await
+        .unwrap();
+    assert_eq!(principal.provider, "static");
+    assert_eq!(principal.subject, "ci");
+
+    let result = provider.authenticate(&bearer("0123456789abcdeg")).await;
+    assert!(matches!(result, Err(AuthError::Unauthorized(_))));
+}
+
+// 以下はPostgresが必要。TEST_DATABASE_URLが設定されていなければスキップする
+async fn test_state(
+    auth: Option<Arc<dyn AuthProvider>>,
+    session_keys: &[String],
+) -> Option<AppState> {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set; skipping");
+        return None;
+    };
+    let pgpool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&url)
+        .await
+        .unwrap();
+    run_migrations(&pgpool).await.unwrap();
+
+    let mut config = Config::default();
+    config.database.url = url;
+    config.session.keys = session_keys.to_vec();
+    config.storage.static_dir = frontend_dist();
+    Some(AppState {
+        postgres: SqlxPostgresConnector::from_sqlx_postgres_pool(pgpool.clone()),
+        pgpool,
+        keys: Arc::new(SessionKeys::from_config(&config.session)),
+        config: Arc::new(config),
+        auth,
+        mailer: None,
+        rate_limiter: Arc::new(RateLimiter::default()),
+        usage: Arc::new(UsageRecorder::default()),
+        metrics: Arc::new(Metrics::default()),
+    })
+}
+
+// メールのリンクが開くフロントエンドの代わりに、index.htmlだけを置いたディレクトリを配信する
+fn frontend_dist() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cms-frontend-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("index.html"), "<div id=\"root\"></div>").unwrap();
+    dir
+}
+
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    cookie: Option<&str>,
+    body: Option<Value>,
+) -> Response {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(cookie) = cookie {
+        request = request.header(COOKIE, cookie);
+    }
+    let request = match body {
+        Some(body) => request
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    app.clone().oneshot(request.unwrap()).await.unwrap()
+}
+
+fn session_cookie(response: &Response) -> String {
+    let set_cookie = response
+        .headers()
+        .get(SET_COOKIE)
+        .expect("the session cookie is set")
+        .to_str()
+        .unwrap();
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn local_account_register_login_call_logout() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/register",
+        None,
+        Some(json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "correct horse battery staple",
+        })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({ "username": username, "password": "wrong" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({ "username": username, "password": "correct horse battery staple" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = session_cookie(&response);
+
+    let response = send(&app, Method::GET, "/api/service/health", None, None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/service/health",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/auth/auth_check",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send(&app, Method::POST, "/api/auth/logout", Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // ログアウト後は同じクッキーでは認証できない
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/service/health",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/auth/auth_check",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn management_api_accepts_bearer_token_from_configured_provider() {
+    let provider = StaticTokenProvider::new(&StaticTokenConfig {
+        tokens: [("ci".to_string(), "0123456789abcdef".to_string())].into(),
+    });
+    let Some(state) = test_state(Some(Arc::new(provider)), &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+
+    let request = |token: &str| {
+        Request::builder()
+            .uri("/api/service/health")
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app
+        .clone()
+        .oneshot(request("0123456789abcdef"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.clone().oneshot(request("wrong-token")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+async fn register_and_login(app: &Router, username: &str, user_agent: &str) -> String {
+    let response = send(
+        app,
+        Method::POST,
+        "/api/auth/register",
+        None,
+        Some(json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "correct horse battery staple",
+        })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    login_as(app, username, user_agent).await
+}
+
+async fn login_as(app: &Router, username: &str, user_agent: &str) -> String {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/auth/login")
+        .header(CONTENT_TYPE, "application/json")
+        .header(USER_AGENT, user_agent)
+        .body(Body::from(
+            json!({ "username": username, "password": "correct horse battery staple" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    session_cookie(&response)
+}
+
+async fn json_body(response: Response) -> Value {
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+fn random_session_key() -> String {
+    let bytes: Vec<u8> = (0..64).map(|_| rand::random::<u8>()).collect();
+    BASE64.encode(bytes)
+}
+
+#[tokio::test]
+async fn sessions_on_several_devices_can_be_listed_and_revoked() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+
+    let laptop = register_and_login(&app, &username, "laptop").await;
+    let phone = login_as(&app, &username, "phone").await;
+
+    // 2台目でログインしても1台目のセッションは有効なまま
+    for cookie in [&laptop, &phone] {
+        let response = send(&app, Method::GET, "/api/service/health", Some(cookie), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = send(&app, Method::GET, "/api/auth/sessions", Some(&laptop), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let sessions = json_body(response).await;
+    let sessions = sessions.as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let phone_session = sessions
+        .iter()
+        .find(|session| session["user_agent"] == "phone")
+        .unwrap();
+    assert_eq!(phone_session["current"], false);
+    let laptop_session = sessions
+        .iter()
+        .find(|session| session["user_agent"] == "laptop")
+        .unwrap();
+    assert_eq!(laptop_session["current"], true);
+
+    let uri = format!("/api/auth/sessions/{}", phone_session["id"]);
+    let response = send(&app, Method::DELETE, &uri, Some(&laptop), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send(&app, Method::GET, "/api/service/health", Some(&phone), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/service/health",
+        Some(&laptop),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // 他人のセッションは削除できない
+    let other = register_and_login(&app, &format!("user-{}", Uuid::new_v4()), "other").await;
+    let uri = format!("/api/auth/sessions/{}", laptop_session["id"]);
+    let response = send(&app, Method::DELETE, &uri, Some(&other), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn session_cookies_survive_restart_and_key_rotation() {
+    let old_key = random_session_key();
+    let new_key = random_session_key();
+    let Some(state) = test_state(None, std::slice::from_ref(&old_key)).await else {
+        return;
+    };
+    let app = create_router(state);
+    let cookie = register_and_login(&app, &format!("user-{}", Uuid::new_v4()), "laptop").await;
+
+    // 同じ鍵で起動し直しても、鍵をローテーションしても古いクッキーは使える
+    for keys in [
+        vec![old_key.clone()],
+        vec![new_key.clone(), old_key.clone()],
+    ] {
+        let app = create_router(test_state(None, &keys).await.unwrap());
+        let response = send(
+            &app,
+            Method::GET,
+            "/api/service/health",
+            Some(&cookie),
+            None,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // 古い鍵を外すと無効になる
+    let app = create_router(test_state(None, &[new_key]).await.unwrap());
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/service/health",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+async fn insert_reset_token(
+    state: &AppState,
+    username: &str,
+    token: &str,
+    expires_in_minutes: i64,
+) {
+    sqlx::query(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+         SELECT id, $2, now() + make_interval(mins => $3::int) FROM users WHERE username = $1",
+    )
+    .bind(username)
+    .bind(hash_token(token))
+    .bind(expires_in_minutes as i32)
+    .execute(&state.pgpool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn password_reset_token_sets_new_password_once() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state.clone());
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+
+    let expired = format!("expired-{}", Uuid::new_v4());
+    insert_reset_token(&state, &username, &expired, -1).await;
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/reset-password",
+        None,
+        Some(json!({ "token": expired, "password": "a new password" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let token = format!("valid-{}", Uuid::new_v4());
+    insert_reset_token(&state, &username, &token, 60).await;
+    let reset = json!({ "token": token, "password": "a new password" });
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/reset-password",
+        None,
+        Some(reset.clone()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // トークンは一度しか使えない
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/reset-password",
+        None,
+        Some(reset),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // 再設定前のセッションとパスワードはもう使えない
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/service/health",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({ "username": username, "password": "correct horse battery staple" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({ "username": username, "password": "a new password" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+// 送信したメールをJSON Linesのファイルに記録するMailer
+fn capture_mailer() -> (Mailer, PathBuf) {
+    let path = std::env::temp_dir().join(format!("cms-mail-{}.jsonl", Uuid::new_v4()));
+    let config = MailConfig {
+        from: "noreply@cms.test".to_string(),
+        default_locale: Locale::Ja,
+        transport: MailTransportConfig::File(MailFileConfig { path: path.clone() }),
+    };
+    (Mailer::start(&config).unwrap(), path)
+}
+
+// 送信はバックグラウンドで行われるので、count通記録されるまで待つ
+async fn captured_mail(path: &PathBuf, count: usize) -> Vec<Value> {
+    for _ in 0..50 {
+        let mail: Vec<Value> = std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        if mail.len() >= count {
+            return mail;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("{} mail(s) were not captured in {}", count, path.display());
+}
+
+// メール本文のリンクがフロントエンドのrouteを開くことを確かめ、tokenクエリパラメータを返す。
+// 画面はtokenをそのままAPIにPOSTする
+async fn follow_link(app: &Router, mail: &Value, route: &str) -> String {
+    let link = mail["text"]
+        .as_str()
+        .unwrap()
+        .split_whitespace()
+        .find(|word| word.contains("token="))
+        .expect("the mail contains a link with a token");
+    assert!(mail["html"].as_str().unwrap().contains(link));
+    let token = link
+        .strip_prefix(&format!("http://localhost:8080/#{}?token=", route))
+        .unwrap_or_else(|| panic!("{} does not open {}", link, route));
+
+    // 「#」より後ろはブラウザの中で解釈されるので、サーバーにはindex.htmlを求めるだけになる
+    let response = app
+        .clone()
+        .oneshot(Request::get("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(text_body(response).await.contains("id=\"root\""));
+    token.to_string()
+}
+
+#[tokio::test]
+async fn forgot_password_mails_a_localized_reset_link() {
+    let Some(mut state) = test_state(None, &[]).await else {
+        return;
+    };
+    let (mailer, path) = capture_mailer();
+    state.mailer = Some(mailer);
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    register_and_login(&app, &username, "laptop").await;
+
+    let forgot_password = |email: String, language: &str| {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/api/auth/forgot_password")
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT_LANGUAGE, language)
+            .body(Body::from(json!(email).to_string()))
+            .unwrap()
+    };
+
+    // 登録されていないメールアドレスでも同じ応答を返し、メールは送らない
+    let unknown = app
+        .clone()
+        .oneshot(forgot_password(
+            format!("nobody-{}@example.com", Uuid::new_v4()),
+            "ja",
+        ))
+        .await
+        .unwrap();
+    let known = app
+        .clone()
+        .oneshot(forgot_password(
+            format!("{}@example.com", username),
+            "en-US,en;q=0.9,ja;q=0.5",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), StatusCode::OK);
+    assert_eq!(known.status(), StatusCode::OK);
+    assert_eq!(
+        hyper::body::to_bytes(unknown.into_body()).await.unwrap(),
+        hyper::body::to_bytes(known.into_body()).await.unwrap()
+    );
+
+    // 1通目は登録時の確認メール
+    let mail = captured_mail(&path, 2).await;
+    assert_eq!(mail.len(), 2);
+    assert_eq!(mail[1]["to"], format!("{}@example.com", username));
+    assert_eq!(mail[1]["subject"], "Reset your password");
+    let token = follow_link(&app, &mail[1], "/reset-password").await;
+    assert!(mail[1]["html"].as_str().unwrap().contains(&token));
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/reset-password",
+        None,
+        Some(json!({ "token": token, "password": "a new password" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn unverified_accounts_cannot_create_services_until_email_is_verified() {
+    let Some(mut state) = test_state(None, &[]).await else {
+        return;
+    };
+    let (mailer, path) = capture_mailer();
+    state.mailer = Some(mailer);
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+
+    let mail = captured_mail(&path, 1).await;
+    assert_eq!(mail[0]["to"], format!("{}@example.com", username));
+    assert_eq!(mail[0]["subject"], "メールアドレスの確認");
+    let token = follow_link(&app, &mail[0], "/verify-email").await;
+
+    let create_service = json!({ "name": format!("service-{}", Uuid::new_v4()) });
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/service",
+        Some(&cookie),
+        Some(create_service.clone()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // 登録直後の再送は間隔が空いていないので断る
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/resend-verification",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(RETRY_AFTER));
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/verify-email",
+        None,
+        Some(json!({ "token": format!("{}x", token) })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/verify-email",
+        None,
+        Some(json!({ "token": token })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/service",
+        Some(&cookie),
+        Some(create_service),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/resend-verification",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let _ = std::fs::remove_file(&path);
+}
+
+async fn login_with_password(app: &Router, username: &str) -> Response {
+    send(
+        app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({ "username": username, "password": "correct horse battery staple" })),
+    )
+    .await
+}
+
+async fn login_second_step(app: &Router, challenge: &str, code: &str) -> Response {
+    send(
+        app,
+        Method::POST,
+        "/api/auth/login/totp",
+        None,
+        Some(json!({ "challenge": challenge, "code": code })),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn totp_is_required_after_the_password_once_enabled() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state.clone());
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+
+    let response = send(&app, Method::POST, "/api/auth/totp", Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let enrollment = json_body(response).await;
+    let secret = enrollment["secret"].as_str().unwrap().to_string();
+    assert!(enrollment["otpauth_uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("otpauth://totp/"));
+
+    let code = |offset: i64| totp::code(&secret, now() as i64 + offset).unwrap();
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/totp/activate",
+        Some(&cookie),
+        Some(json!({ "code": "12345" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/auth/totp/activate",
+        Some(&cookie),
+        Some(json!({ "code": code(0) })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let recovery_codes: Vec<String> =
+        serde_json::from_value(json_body(response).await["recovery_codes"].clone()).unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+
+    // パスワードだけではセッションは作られない
+    let response = login_with_password(&app, &username).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert!(!response.headers().contains_key(SET_COOKIE));
+    let challenge = json_body(response).await["challenge"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // 有効化に使ったコードは再利用できない
+    let response = login_second_step(&app, &challenge, &code(0)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = login_second_step(&app, &challenge, &code(30)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = session_cookie(&response);
+    let response = send(
+        &app,
+        Method::GET,
+        "/api/service/health",
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // リカバリーコードは一度だけ使える
+    let response = login_second_step(&app, &challenge, &recovery_codes[0].to_uppercase()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = login_second_step(&app, &challenge, &recovery_codes[0]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // 直前の再利用を含めて5回続けて間違えると、しばらく正しいコードも受け付けない
+    for _ in 0..4 {
+        let response = login_second_step(&app, &challenge, "not-a-code").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = login_second_step(&app, &challenge, &recovery_codes[1]).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // 管理者が解除するとパスワードだけでログインできる
+    assert!(management::reset_two_factor(&state.postgres, &username)
+        .await
+        .unwrap());
+    let response = login_with_password(&app, &username).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = login_second_step(&app, &challenge, &recovery_codes[1]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+// 確認メールのリンクでメールアドレスを確認したアカウントを作る。mail_countは確認メールを含めた送信済みの通数
+async fn register_verified(
+    app: &Router,
+    path: &PathBuf,
+    username: &str,
+    mail_count: usize,
+) -> String {
+    let cookie = register_and_login(app, username, "laptop").await;
+    let mail = captured_mail(path, mail_count).await;
+    let response = send(
+        app,
+        Method::POST,
+        "/api/auth/verify-email",
+        None,
+        Some(json!({ "token": follow_link(app, &mail[mail_count - 1], "/verify-email").await })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    cookie
+}
+
+async fn member_id(app: &Router, cookie: &str, service_id: &str, username: &str) -> i64 {
+    let response = send(
+        app,
+        Method::GET,
+        &format!("/api/service/{}/members", service_id),
+        Some(cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let members = json_body(response).await;
+    members
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|member| member["email"] == format!("{}@example.com", username))
+        .expect("the user is a member")["id"]
+        .as_i64()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn services_are_managed_by_members_invited_by_email() {
+    let Some(mut state) = test_state(None, &[]).await else {
+        return;
+    };
+    let (mailer, path) = capture_mailer();
+    state.mailer = Some(mailer);
+    let app = create_router(state);
+    let alice = format!("alice-{}", Uuid::new_v4());
+    let bob = format!("bob-{}", Uuid::new_v4());
+    let carol = format!("carol-{}", Uuid::new_v4());
+    let alice_cookie = register_verified(&app, &path, &alice, 1).await;
+    let bob_cookie = register_verified(&app, &path, &bob, 2).await;
+    let carol_cookie = register_verified(&app, &path, &carol, 3).await;
+
+    let service_name = format!("service-{}", Uuid::new_v4());
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/service",
+        Some(&alice_cookie),
+        Some(json!({ "name": service_name })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let service_id = String::from_utf8(body.to_vec())
+        .unwrap()
+        .split("Service ID: ")
+        .nth(1)
+        .unwrap()
+        .trim()
+        .to_string();
+
+    // 作成者はオーナーになり、一覧に表示される
+    let response = send(&app, Method::GET, "/api/service", Some(&alice_cookie), None).await;
+    let services = json_body(response).await;
+    assert!(services
+        .as_array()
+        .unwrap()
+        .contains(&json!({ "id": service_id, "name": service_name, "role": "owner" })));
+
+    // メンバーでなければサービスがないのと同じに扱う
+    let response = send(
+        &app,
+        Method::DELETE,
+        &format!("/api/services/services/{}", service_id),
+        Some(&carol_cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let role = json!({ "name": "Reader", "permissions": ["Get"] });
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/roles", service_id),
+        Some(&carol_cookie),
+        Some(role.clone()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/invitations", service_id),
+        Some(&alice_cookie),
+        Some(json!({ "email": format!("{}@example.com", bob), "role": "editor" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let mail = captured_mail(&path, 4).await;
+    assert_eq!(mail[3]["to"], format!("{}@example.com", bob));
+    assert_eq!(mail[3]["subject"], format!("{}への招待", service_name));
+    let token = follow_link(&app, &mail[3], "/invitations/accept").await;
+
+    // 招待は宛先のメールアドレスの利用者しか受けられず、一度しか使えない。
+    // ログインしていなければ401になるので、画面はログインしてから送り直す
+    let accept = json!({ "token": token });
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/service/invitations/accept",
+        None,
+        Some(accept.clone()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/service/invitations/accept",
+        Some(&carol_cookie),
+        Some(accept.clone()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/service/invitations/accept",
+        Some(&bob_cookie),
+        Some(accept.clone()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = send(
+        &app,
+        Method::POST,
+        "/api/service/invitations/accept",
+        Some(&bob_cookie),
+        Some(accept),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // 編集者はロールを作れず、自分を昇格させることもできない
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/roles", service_id),
+        Some(&bob_cookie),
+        Some(role),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let alice_id = member_id(&app, &bob_cookie, &service_id, &alice).await;
+    let bob_id = member_id(&app, &bob_cookie, &service_id, &bob).await;
+    let members = |id: i64| format!("/api/service/{}/members/{}", service_id, id);
+    let response = send(
+        &app,
+        Method::PATCH,
+        &members(bob_id),
+        Some(&bob_cookie),
+        Some(json!({ "role": "admin" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // オーナーは別のオーナーに引き継げるが、最後のオーナーは外せない
+    let response = send(
+        &app,
+        Method::PATCH,
+        &members(bob_id),
+        Some(&alice_cookie),
+        Some(json!({ "role": "owner" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(
+        &app,
+        Method::DELETE,
+        &members(alice_id),
+        Some(&bob_cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(
+        &app,
+        Method::PATCH,
+        &members(bob_id),
+        Some(&bob_cookie),
+        Some(json!({ "role": "admin" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = send(
+        &app,
+        Method::DELETE,
+        &format!("/api/services/services/{}", service_id),
+        Some(&alice_cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(
+        &app,
+        Method::DELETE,
+        &format!("/api/services/services/{}", service_id),
+        Some(&bob_cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+// サービスを作成し、サービスIDとAdminロールのAPIキーを返す
+async fn create_service_as(app: &Router, cookie: &str) -> (String, String) {
+    let response = send(
+        app,
+        Method::POST,
+        "/api/service",
+        Some(cookie),
+        Some(json!({ "name": format!("service-{}", Uuid::new_v4()) })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    let field = |label: &str| {
+        body.split(label)
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap()
+            .to_string()
+    };
+    (field("Service ID: "), field("API key: "))
+}
+
+async fn call_with_key(app: &Router, method: Method, uri: &str, api_key: &str) -> StatusCode {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", api_key)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "name": "posts" }).to_string()))
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn api_keys_are_hashed_and_can_be_rotated_and_revoked() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state.clone());
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, admin_key) = create_service_as(&app, &cookie).await;
+    let openapi = format!("/api/services/{}/openapi.json", service_id);
+    let content_types = format!("/api/services/{}/content_types", service_id);
+
+    // 平文のキーはDBに残らない
+    let stored: i64 = sqlx::query_scalar("SELECT count(*) FROM api_keys WHERE key_hash = $1")
+        .bind(hash_token(&admin_key))
+        .fetch_one(&state.pgpool)
+        .await
+        .unwrap();
+    assert_eq!(stored, 1);
+    assert!(admin_key.starts_with("cms_"));
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &admin_key).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, "cms_wrong").await,
+        StatusCode::FORBIDDEN
+    );
+
+    // ロールの権限にないメソッドは呼べない
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/roles", service_id),
+        Some(&cookie),
+        Some(json!({ "name": "Reader", "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let reader_key = String::from_utf8(
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap();
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &reader_key).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        call_with_key(&app, Method::POST, &content_types, &reader_key).await,
+        StatusCode::FORBIDDEN
+    );
+
+    let role_id: i32 = sqlx::query_scalar("SELECT role_id FROM api_keys WHERE key_hash = $1")
+        .bind(hash_token(&reader_key))
+        .fetch_one(&state.pgpool)
+        .await
+        .unwrap();
+    let keys = format!("/api/service/{}/roles/{}/keys", service_id, role_id);
+
+    let response = send(
+        &app,
+        Method::POST,
+        &keys,
+        Some(&cookie),
+        Some(json!({ "name": "ci", "expires_at": "2000-01-01T00:00:00Z" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send(
+        &app,
+        Method::POST,
+        &keys,
+        Some(&cookie),
+        Some(json!({ "name": "ci", "expires_at": "2999-01-01T00:00:00Z" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let ci = json_body(response).await;
+    let ci_key = ci["key"].as_str().unwrap().to_string();
+    assert!(ci_key.starts_with(ci["prefix"].as_str().unwrap()));
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &ci_key).await,
+        StatusCode::OK
+    );
+
+    // 猶予なしでローテーションすると古いキーはすぐに使えなくなる
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("{}/{}/rotate", keys, ci["id"]),
+        Some(&cookie),
+        Some(json!({})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let rotated = json_body(response).await;
+    assert_eq!(rotated["name"], "ci");
+    let rotated_key = rotated["key"].as_str().unwrap().to_string();
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &ci_key).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &rotated_key).await,
+        StatusCode::OK
+    );
+
+    let response = send(
+        &app,
+        Method::DELETE,
+        &format!("{}/{}", keys, rotated["id"]),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &rotated_key).await,
+        StatusCode::FORBIDDEN
+    );
+
+    // 一覧にはハッシュも平文のキーも含まれず、利用日時が記録される
+    let response = send(&app, Method::GET, &keys, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed = json_body(response).await;
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), 3);
+    for key in listed {
+        assert!(key.get("key").is_none() && key.get("key_hash").is_none());
+    }
+    assert!(listed[0]["revoked_at"].is_null());
+    assert!(!listed[1]["revoked_at"].is_null());
+    assert!(!listed[2]["revoked_at"].is_null());
+    let mut last_used = Value::Null;
+    for _ in 0..50 {
+        let response = send(&app, Method::GET, &keys, Some(&cookie), None).await;
+        last_used = json_body(response).await[0]["last_used_at"].clone();
+        if !last_used.is_null() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(!last_used.is_null());
+}
+
+#[tokio::test]
+async fn roles_can_be_listed_edited_and_deleted_except_admin() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, _) = create_service_as(&app, &cookie).await;
+    let roles = format!("/api/service/{}/roles", service_id);
+    let content_types = format!("/api/services/{}/content_types", service_id);
+
+    let response = send(
+        &app,
+        Method::POST,
+        &roles,
+        Some(&cookie),
+        Some(json!({ "name": "Admin", "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send(
+        &app,
+        Method::POST,
+        &roles,
+        Some(&cookie),
+        Some(json!({ "name": "Reader", "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let reader_key = String::from_utf8(
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap();
+
+    let response = send(&app, Method::GET, &roles, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed = json_body(response).await;
+    assert_eq!(listed[0]["name"], "Admin");
+    assert_eq!(
+        listed[0]["permissions"],
+        json!(["Delete", "Get", "Patch", "Post", "Put"])
+    );
+    assert_eq!(listed[1]["name"], "Reader");
+    assert_eq!(listed[1]["permissions"], json!(["Get"]));
+    let admin = format!("{}/{}", roles, listed[0]["id"]);
+    let reader = format!("{}/{}", roles, listed[1]["id"]);
+
+    // 権限は集合として置き換えられ、既存のキーにもすぐ反映される
+    assert_eq!(
+        call_with_key(&app, Method::POST, &content_types, &reader_key).await,
+        StatusCode::FORBIDDEN
+    );
+    let response = send(
+        &app,
+        Method::PATCH,
+        &reader,
+        Some(&cookie),
+        Some(json!({ "name": "Writer", "permissions": ["Post", "Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json_body(response).await,
+        json!({ "id": listed[1]["id"], "name": "Writer", "permissions": ["Get", "Post"] })
+    );
+    assert_eq!(
+        call_with_key(&app, Method::POST, &content_types, &reader_key).await,
+        StatusCode::CREATED
+    );
+
+    // サービスと一緒に作られたAdminロールは変更も削除もできない
+    let response = send(
+        &app,
+        Method::PATCH,
+        &admin,
+        Some(&cookie),
+        Some(json!({ "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send(&app, Method::DELETE, &admin, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send(
+        &app,
+        Method::PATCH,
+        &reader,
+        Some(&cookie),
+        Some(json!({ "name": "Admin" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = send(&app, Method::DELETE, &reader, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(&app, Method::GET, &reader, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        call_with_key(&app, Method::GET, &content_types, &reader_key).await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+async fn lists_service(app: &Router, cookie: &str, uri: &str, service_id: &str) -> bool {
+    let response = send(app, Method::GET, uri, Some(cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    json_body(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|service| service["id"] == service_id)
+}
+
+#[tokio::test]
+async fn deleted_services_can_be_restored_until_purged() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let db = state.postgres.clone();
+    let pgpool = state.pgpool.clone();
+    let app = create_router(state);
+    let owner = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &owner, "laptop").await;
+    let editor = format!("user-{}", Uuid::new_v4());
+    let editor_cookie = register_and_login(&app, &editor, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let identity = management::local_member_identity(&db, &editor)
+        .await
+        .unwrap()
+        .unwrap();
+    membership::add_member(&db, &service_id, &identity, MemberRole::Editor)
+        .await
+        .unwrap();
+    let content_types = format!("/api/services/{}/content_types", service_id);
+    let openapi = format!("/api/services/{}/openapi.json", service_id);
+    let restore = format!("/api/service/{}/restore", service_id);
+    assert_eq!(
+        call_with_key(&app, Method::POST, &content_types, &api_key).await,
+        StatusCode::CREATED
+    );
+
+    // 削除はオーナーだけができ、削除後はAPIキーも使えなくなる
+    let delete = format!("/api/services/services/{}", service_id);
+    let response = send(&app, Method::DELETE, &delete, Some(&editor_cookie), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(&app, Method::DELETE, &delete, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &api_key).await,
+        StatusCode::FORBIDDEN
+    );
+    assert!(!lists_service(&app, &cookie, "/api/service", &service_id).await);
+    assert!(lists_service(&app, &cookie, "/api/service?deleted=true", &service_id).await);
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/api/service/{}/members", service_id),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = send(&app, Method::POST, &restore, Some(&editor_cookie), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(&app, Method::POST, &restore, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(&app, Method::POST, &restore, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert!(lists_service(&app, &cookie, "/api/service", &service_id).await);
+    assert_eq!(
+        call_with_key(&app, Method::GET, &openapi, &api_key).await,
+        StatusCode::OK
+    );
+
+    // 保持期間内のサービスはパージされない
+    management::delete_service(&db, &service_id).await.unwrap();
+    let an_hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+    let purged = management::purge_deleted_services(&db, an_hour_ago.into())
+        .await
+        .unwrap();
+    assert!(!purged.contains(&service_id));
+    assert!(management::purge_service(&db, &service_id).await.unwrap());
+    assert!(!lists_service(&app, &cookie, "/api/service?deleted=true", &service_id).await);
+    let response = send(&app, Method::POST, &restore, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let remaining: i64 = sqlx::query_scalar(
+        "SELECT (SELECT count(*) FROM content_types WHERE service_id = $1)
+              + (SELECT count(*) FROM roles WHERE service_id = $1)
+              + (SELECT count(*) FROM service_members WHERE service_id = $1)",
+    )
+    .bind(&service_id)
+    .fetch_one(&pgpool)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+}
+
+async fn send_with_key(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    api_key: &str,
+    body: Option<Value>,
+) -> Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", api_key)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn deleted_content_items_go_to_the_trash_until_purged() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let db = state.postgres.clone();
+    let pgpool = state.pgpool.clone();
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let base = format!("/api/services/{}", service_id);
+
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/roles", service_id),
+        Some(&cookie),
+        Some(json!({ "name": "Reader", "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let reader_key = String::from_utf8(
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap();
+
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/content_types", base),
+        &api_key,
+        Some(json!({ "name": "posts" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let content_type_id = String::from_utf8(body.to_vec())
+        .unwrap()
+        .rsplit(' ')
+        .next()
+        .unwrap()
+        .to_string();
+    let items = format!("{}/{}/content_items", base, content_type_id);
+    for title in ["first", "second"] {
+        let response = send_with_key(
+            &app,
+            Method::POST,
+            &items,
+            &api_key,
+            Some(json!({ "data": { "title": title } })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+    let response = send_with_key(&app, Method::GET, &items, &api_key, None).await;
+    let listed = json_body(response).await;
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+    let item_id = listed[0]["id"].as_str().unwrap().to_string();
+    let item = format!("{}/content_items/{}", base, item_id);
+    let trash = format!("{}/trash", base);
+    let restore = format!("{}/trash/{}/restore", base, item_id);
+
+    // 削除したアイテムは通常の読み取りから消え、削除したキーとともにゴミ箱に入る
+    let response = send_with_key(&app, Method::DELETE, &item, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_with_key(&app, Method::DELETE, &item, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send_with_key(&app, Method::GET, &item, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send_with_key(&app, Method::GET, &items, &api_key, None).await;
+    assert_eq!(json_body(response).await.as_array().unwrap().len(), 1);
+    let response = send_with_key(&app, Method::GET, &trash, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let trashed = json_body(response).await;
+    assert_eq!(trashed.as_array().unwrap().len(), 1);
+    assert_eq!(trashed[0]["id"], item_id.as_str());
+    assert_eq!(trashed[0]["data"], listed[0]["data"]);
+    assert!(trashed[0]["deleted_at"].is_string());
+    assert!(trashed[0]["deleted_by_key_id"].is_i64());
+
+    // 復元にはPost、完全な削除にはDeleteの権限が要る
+    let response = send_with_key(&app, Method::POST, &restore, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send_with_key(&app, Method::POST, &restore, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_with_key(&app, Method::POST, &restore, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send_with_key(&app, Method::GET, &item, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_with_key(&app, Method::GET, &trash, &api_key, None).await;
+    assert_eq!(json_body(response).await, json!([]));
+
+    let response = send_with_key(&app, Method::DELETE, &item, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let trashed_item = format!("{}/trash/{}", base, item_id);
+    let response = send_with_key(&app, Method::DELETE, &trashed_item, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send_with_key(&app, Method::DELETE, &trashed_item, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_with_key(&app, Method::POST, &restore, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // 保持期間が過ぎたアイテムだけがパージされる
+    let response = send_with_key(&app, Method::GET, &items, &api_key, None).await;
+    let other = format!(
+        "{}/content_items/{}",
+        base,
+        json_body(response).await[0]["id"].as_str().unwrap()
+    );
+    let response = send_with_key(&app, Method::DELETE, &other, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let a_day_ago = chrono::Utc::now() - chrono::Duration::days(1);
+    trash::purge_trash(&db, a_day_ago.into()).await.unwrap();
+    let response = send_with_key(&app, Method::GET, &trash, &api_key, None).await;
+    assert_eq!(json_body(response).await.as_array().unwrap().len(), 1);
+    sqlx::query(
+        "UPDATE content_items SET deleted_at = now() - interval '2 days'
+         WHERE content_type_id = $1 AND deleted_at IS NOT NULL",
+    )
+    .bind(content_type_id.parse::<i32>().unwrap())
+    .execute(&pgpool)
+    .await
+    .unwrap();
+    assert!(trash::purge_trash(&db, a_day_ago.into()).await.unwrap() >= 1);
+    let response = send_with_key(&app, Method::GET, &trash, &api_key, None).await;
+    assert_eq!(json_body(response).await, json!([]));
+}
+
+#[tokio::test]
+async fn mutations_are_recorded_in_an_append_only_audit_log() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let db = state.postgres.clone();
+    let pgpool = state.pgpool.clone();
+    let app = create_router(state);
+    let owner = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &owner, "laptop").await;
+    let editor = format!("user-{}", Uuid::new_v4());
+    let editor_cookie = register_and_login(&app, &editor, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let owner_id = management::local_member_identity(&db, &owner)
+        .await
+        .unwrap()
+        .unwrap()
+        .subject;
+    let identity = management::local_member_identity(&db, &editor)
+        .await
+        .unwrap()
+        .unwrap();
+    membership::add_member(&db, &service_id, &identity, MemberRole::Editor)
+        .await
+        .unwrap();
+    let base = format!("/api/services/{}", service_id);
+    let audit_logs = format!("/api/service/{}/audit_logs", service_id);
+
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/content_types", base),
+        &api_key,
+        Some(json!({ "name": "posts" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let content_type_id = String::from_utf8(body.to_vec())
+        .unwrap()
+        .rsplit(' ')
+        .next()
+        .unwrap()
+        .to_string();
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/{}/fields", base, content_type_id),
+        &api_key,
+        Some(json!({ "display_name": "title", "field_type": "Text", "required": false })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let items = format!("{}/{}/content_items", base, content_type_id);
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &items,
+        &api_key,
+        Some(json!({ "data": { "title": "first" } })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = send_with_key(&app, Method::GET, &items, &api_key, None).await;
+    let item_id = json_body(response).await[0]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let item = format!("{}/content_items/{}", base, item_id);
+
+    // 呼び出し側のX-Request-Idはそのまま使われ、応答でも返る
+    let request_id = format!("req-{}", Uuid::new_v4());
+    let request = Request::builder()
+        .method(Method::PATCH)
+        .uri(&item)
+        .header("x-api-key", &api_key)
+        .header("x-request-id", &request_id)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "data": { "title": "second" } }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-request-id"], request_id.as_str());
+    let response = send_with_key(&app, Method::DELETE, &item, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let delete_request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    // アイテムの記録は新しい順に返り、APIキーとそのロールが操作した主体になる
+    let for_item = format!("{}?target_id={}", audit_logs, item_id);
+    let response = send(&app, Method::GET, &for_item, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = json_body(response).await;
+    let entries = page["entries"].as_array().unwrap();
+    let actions: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            "content_item.delete",
+            "content_item.update",
+            "content_item.create"
+        ]
+    );
+    assert_eq!(page["next_cursor"], Value::Null);
+    for entry in entries {
+        assert_eq!(entry["actor_type"], "api_key");
+        assert!(entry["actor_role_id"].is_i64());
+        assert_eq!(entry["service_id"], service_id.as_str());
+        assert_eq!(entry["target_type"], "content_item");
+    }
+    assert_eq!(entries[1]["request_id"], request_id.as_str());
+    assert_eq!(entries[1]["before"]["data"]["title"], "first");
+    assert_eq!(entries[1]["after"]["data"]["title"], "second");
+    assert_eq!(entries[0]["request_id"], delete_request_id.as_str());
+    assert_eq!(entries[0]["before"]["data"]["title"], "second");
+
+    // 絞り込みとカーソルでのページ送り
+    let uri = format!("{}?request_id={}", audit_logs, request_id);
+    let response = send(&app, Method::GET, &uri, Some(&cookie), None).await;
+    let page = json_body(response).await;
+    assert_eq!(page["entries"].as_array().unwrap().len(), 1);
+    assert_eq!(page["entries"][0]["action"], "content_item.update");
+    let uri = format!("{}?action=service.create", audit_logs);
+    let response = send(&app, Method::GET, &uri, Some(&cookie), None).await;
+    let page = json_body(response).await;
+    assert_eq!(page["entries"][0]["actor_type"], "user");
+    assert_eq!(
+        page["entries"][0]["actor_id"],
+        format!("local:{}", owner_id).as_str()
+    );
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("{}&limit=2", for_item),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    let page = json_body(response).await;
+    assert_eq!(page["entries"].as_array().unwrap().len(), 2);
+    let cursor = page["next_cursor"].as_i64().unwrap();
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("{}&limit=2&cursor={}", for_item, cursor),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    let page = json_body(response).await;
+    assert_eq!(page["entries"].as_array().unwrap().len(), 1);
+    assert_eq!(page["entries"][0]["action"], "content_item.create");
+    assert_eq!(page["next_cursor"], Value::Null);
+
+    // エクスポートは古い順のNDJSON
+    let export = format!("{}/export?target_id={}", audit_logs, item_id);
+    let response = send(&app, Method::GET, &export, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let exported: Vec<Value> = String::from_utf8(body.to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let actions: Vec<&str> = exported
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            "content_item.create",
+            "content_item.update",
+            "content_item.delete"
+        ]
+    );
+
+    // 管理者でなければ読めない
+    for uri in [&audit_logs, &export] {
+        let response = send(&app, Method::GET, uri, Some(&editor_cookie), None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    // ログインのようにサービスに属さない操作も記録される
+    let filter = audit::AuditFilter {
+        action: Some("session.create".to_string()),
+        actor_id: Some(format!("local:{}", owner_id)),
+        ..Default::default()
+    };
+    let sessions = audit::list(&db, None, &filter, None, 10).await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].service_id, None);
+
+    // 記録は書き換えも削除もできない
+    let updated = sqlx::query("UPDATE audit_logs SET action = 'tampered' WHERE service_id = $1")
+        .bind(&service_id)
+        .execute(&pgpool)
+        .await;
+    assert!(updated.is_err());
+    let deleted = sqlx::query("DELETE FROM audit_logs WHERE service_id = $1")
+        .bind(&service_id)
+        .execute(&pgpool)
+        .await;
+    assert!(deleted.is_err());
+}
+
+#[tokio::test]
+async fn api_keys_are_rate_limited_by_the_service_plan() {
+    let Some(mut state) = test_state(None, &[]).await else {
+        return;
+    };
+    let mut config = (*state.config).clone();
+    config.rate_limit.plans.insert(
+        "tiny".to_string(),
+        PlanLimits {
+            requests_per_minute: 1,
+            burst: 2,
+            monthly_quota: None,
+        },
+    );
+    config.rate_limit.plans.insert(
+        "metered".to_string(),
+        PlanLimits {
+            requests_per_minute: 600,
+            burst: 100,
+            monthly_quota: Some(3),
+        },
+    );
+    state.config = Arc::new(config);
+    let db = state.postgres.clone();
+    let pgpool = state.pgpool.clone();
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/roles", service_id),
+        Some(&cookie),
+        Some(json!({ "name": "Reader", "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let reader_key = String::from_utf8(
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap();
+    let openapi = format!("/api/services/{}/openapi.json", service_id);
+    let header = |response: &Response, name: &str| -> Option<u64> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap().parse().unwrap())
+    };
+
+    // 既定のプランでも残りの回数が返る
+    let response = send_with_key(&app, Method::GET, &openapi, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "x-ratelimit-limit"), Some(100));
+    assert_eq!(header(&response, "x-ratelimit-remaining"), Some(99));
+    assert_eq!(header(&response, "x-quota-limit"), None);
+
+    // 月間の上限は権限のあるリクエストだけを数え、サービスのすべてのキーで共有する
+    assert!(
+        management::set_service_plan(&db, &service_id, Some("metered".to_string()))
+            .await
+            .unwrap()
+    );
+    let content_types = format!("/api/services/{}/content_types", service_id);
+    let response = send_with_key(&app, Method::POST, &content_types, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    for (key, remaining) in [(&api_key, 2), (&reader_key, 1), (&api_key, 0)] {
+        let response = send_with_key(&app, Method::GET, &openapi, key, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "x-quota-limit"), Some(3));
+        assert_eq!(header(&response, "x-quota-remaining"), Some(remaining));
+    }
+    let response = send_with_key(&app, Method::GET, &openapi, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(header(&response, "retry-after").unwrap() >= 1);
+    assert_eq!(header(&response, "x-quota-remaining"), Some(0));
+    let counted: i64 =
+        sqlx::query_scalar("SELECT requests FROM service_monthly_requests WHERE service_id = $1")
+            .bind(&service_id)
+            .fetch_one(&pgpool)
+            .await
+            .unwrap();
+    assert_eq!(counted, 3);
+
+    // バケットが空になったキーだけが429になる
+    assert!(
+        management::set_service_plan(&db, &service_id, Some("tiny".to_string()))
+            .await
+            .unwrap()
+    );
+    let response = send_with_key(&app, Method::GET, &openapi, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "x-ratelimit-limit"), Some(2));
+    assert_eq!(header(&response, "x-ratelimit-remaining"), Some(1));
+    let response = send_with_key(&app, Method::GET, &openapi, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "x-ratelimit-remaining"), Some(0));
+    assert!(header(&response, "x-ratelimit-reset").unwrap() >= 60);
+    let response = send_with_key(&app, Method::GET, &openapi, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(header(&response, "retry-after").unwrap() >= 1);
+    assert_eq!(header(&response, "x-ratelimit-remaining"), Some(0));
+    let response = send_with_key(&app, Method::GET, &openapi, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn content_api_usage_is_reported_per_day_and_content_type() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let usage = state.usage.clone();
+    let pgpool = state.pgpool.clone();
+    let app = create_router(state);
+    let owner = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &owner, "laptop").await;
+    let outsider = format!("user-{}", Uuid::new_v4());
+    let outsider_cookie = register_and_login(&app, &outsider, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/roles", service_id),
+        Some(&cookie),
+        Some(json!({ "name": "Reader", "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let reader_key = String::from_utf8(
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap();
+    let base = format!("/api/services/{}", service_id);
+
+    // パスにコンテンツタイプを含まない呼び出し
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/content_types", base),
+        &api_key,
+        Some(json!({ "name": "posts" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let content_type_id: i64 = String::from_utf8(body.to_vec())
+        .unwrap()
+        .rsplit(' ')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let missing = format!("{}/content_items/{}", base, Uuid::new_v4());
+    let response = send_with_key(&app, Method::GET, &missing, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // コンテンツタイプごとの呼び出し。権限で拒否したものも数える
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/{}/fields", base, content_type_id),
+        &api_key,
+        Some(json!({ "display_name": "title", "field_type": "Text", "required": false })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let items = format!("{}/{}/content_items", base, content_type_id);
+    let item = json!({ "data": { "title": "hello" } });
+    let response = send_with_key(&app, Method::POST, &items, &api_key, Some(item.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = send_with_key(&app, Method::POST, &items, &reader_key, Some(item)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send_with_key(&app, Method::GET, &items, &reader_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // 無効なキーの呼び出しはどのサービスにも数えない
+    let response = send_with_key(&app, Method::GET, &items, "not-a-key", None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    usage.flush(&pgpool).await.unwrap();
+    let response = send_with_key(&app, Method::GET, &items, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    usage.flush(&pgpool).await.unwrap();
+
+    let uri = format!("/api/service/{}/usage", service_id);
+    let response = send(&app, Method::GET, &uri, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json_body(response).await;
+    let today = chrono::Utc::now().date_naive().to_string();
+    assert_eq!(report["to"], today.as_str());
+    assert_eq!(report["total"]["requests"], 7);
+    assert_eq!(report["total"]["client_errors"], 2);
+    assert_eq!(report["total"]["server_errors"], 0);
+    let days = report["days"].as_array().unwrap();
+    assert_eq!(days.len(), 2);
+    assert_eq!(days[0]["day"], today.as_str());
+    assert_eq!(days[0]["content_type_id"], Value::Null);
+    assert_eq!(days[0]["requests"], 2);
+    assert_eq!(days[0]["client_errors"], 1);
+    assert!(days[0]["bytes_in"].as_i64().unwrap() > 0);
+    assert_eq!(days[1]["content_type_id"], content_type_id);
+    assert_eq!(days[1]["requests"], 5);
+    assert_eq!(days[1]["client_errors"], 1);
+    assert!(days[1]["bytes_out"].as_i64().unwrap() > 0);
+
+    // 期間の指定
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("{}?from=2000-01-01&to=2000-01-31", uri),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    let report = json_body(response).await;
+    assert_eq!(report["days"], json!([]));
+    assert_eq!(report["total"]["requests"], 0);
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("{}?from=2000-02-01&to=2000-01-31", uri),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send(&app, Method::GET, &uri, Some(&outsider_cookie), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn metrics_are_exported_in_the_prometheus_text_format() {
+    let Some(mut state) = test_state(None, &[]).await else {
+        return;
+    };
+    let mut config = (*state.config).clone();
+    config.metrics.bearer_token = Some("scrape-token".to_string());
+    state.config = Arc::new(config);
+    let metrics = state.metrics.clone();
+    let app = create_router(state);
+
+    let missing = format!(
+        "/api/services/{}/content_items/{}",
+        Uuid::new_v4(),
+        Uuid::new_v4()
+    );
+    let response = send(&app, Method::GET, &missing, None, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(&app, Method::GET, "/api/health", None, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(&app, Method::GET, "/api/health", None, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    metrics.job_finished("purge", Instant::now(), false);
+
+    // トークンがなければ応答しない
+    let response = send(&app, Method::GET, "/metrics", None, None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .header(AUTHORIZATION, "Bearer scrape-token")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = String::from_utf8(
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap();
+
+    // 実際のパスではなくルートのパターンごとに数える
+    assert!(body.contains("# TYPE cms_http_request_duration_seconds histogram"));
+    assert!(body.contains(
+        r#"cms_http_request_duration_seconds_count{method="GET",route="/api/health",status="200"} 2"#
+    ));
+    assert!(body.contains(
+        r#"cms_http_request_duration_seconds_bucket{method="GET",route="/api/health",status="200",le="+Inf"} 2"#
+    ));
+    assert!(body.contains(
+        r#"cms_http_request_duration_seconds_count{method="GET",route="/api/services/:service_id/content_items/:content_item_id",status="403"} 1"#
+    ));
+    assert!(body.contains("cms_db_pool_max_connections "));
+    assert!(body.contains(r#"cms_db_pool_connections{state="idle"} "#));
+    assert!(body.contains(r#"cms_job_failures_total{job="purge"} 1"#));
+    assert!(body.contains(r#"cms_job_runs_total{job="purge"} 1"#));
+    assert!(!body.contains("cms_job_last_success_timestamp_seconds{"));
+    // OIDCもメールも設定していなければ出さない
+    assert!(!body.contains("cms_jwks_"));
+    assert!(!body.contains("cms_mail_queue_depth"));
+}
+
+async fn text_body(response: Response) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn content_types_round_trip_through_json_schema() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let base = format!("/api/services/{}", service_id);
+    let schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "articles",
+        "type": "object",
+        "properties": {
+            "title": { "type": "string" },
+            "published_at": { "type": "string", "format": "date-time" },
+            "rank": { "type": "integer" }
+        },
+        "required": ["title"],
+        "additionalProperties": false
+    });
+
+    // 真偽値以外のadditionalPropertiesは強制できないので取り込まない
+    let mut open_schema = schema.clone();
+    open_schema["additionalProperties"] = json!({ "type": "string" });
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/content_types/import", base),
+        &api_key,
+        Some(open_schema),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(text_body(response).await.contains("$.additionalProperties"));
+
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/content_types/import", base),
+        &api_key,
+        Some(schema),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let content_type_id: i64 = text_body(response)
+        .await
+        .rsplit(' ')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let uri = format!("{}/content_types/{}/schema", base, content_type_id);
+    let response = send_with_key(&app, Method::GET, &uri, &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let exported = json_body(response).await;
+    assert_eq!(exported["title"], "articles");
+    assert_eq!(exported["required"], json!(["title"]));
+    assert_eq!(
+        exported["properties"],
+        json!({
+            "title": { "type": "string" },
+            "published_at": { "type": "string", "format": "date-time" },
+            "rank": { "type": "number" }
+        })
+    );
+
+    let response = send_with_key(
+        &app,
+        Method::GET,
+        &format!("{}/openapi.json", base),
+        &api_key,
+        None,
+    )
+    .await;
+    let document = json_body(response).await;
+    assert!(document["paths"]["/content_types/import"]["post"].is_object());
+    assert!(document["paths"]["/content_types/{content_type_id}/schema"]["get"].is_object());
+}
+
+#[tokio::test]
+async fn openapi_documents_are_served_for_services_and_the_management_api() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+    let response = send(&app, Method::GET, "/api/openapi.json", None, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["openapi"], "3.1.0");
+
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let base = format!("/api/services/{}", service_id);
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/content_types", base),
+        &api_key,
+        Some(json!({ "name": "posts" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = send_with_key(
+        &app,
+        Method::GET,
+        &format!("{}/openapi.json", base),
+        &api_key,
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let document = json_body(response).await;
+    assert_eq!(document["servers"][0]["url"], base);
+    let paths = document["paths"].as_object().unwrap();
+    assert!(paths
+        .keys()
+        .any(|path| path.ends_with("/content_items") && path != "/content_items"));
+}
+
+#[tokio::test]
+async fn typescript_declarations_are_served_for_a_service() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let base = format!("/api/services/{}", service_id);
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/content_types", base),
+        &api_key,
+        Some(json!({ "name": "posts" })),
+    )
+    .await;
+    let content_type_id = text_body(response)
+        .await
+        .rsplit(' ')
+        .next()
+        .unwrap()
+        .to_string();
+    let response = send_with_key(
+        &app,
+        Method::POST,
+        &format!("{}/{}/fields", base, content_type_id),
+        &api_key,
+        Some(json!({ "display_name": "title", "field_type": "Text", "required": true })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = send_with_key(
+        &app,
+        Method::GET,
+        &format!("{}/types.d.ts", base),
+        &api_key,
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "application/typescript; charset=utf-8"
+    );
+    let declarations = text_body(response).await;
+    assert!(declarations.contains(&format!(
+        "export interface Posts{} {{\n  title: string;\n}}",
+        content_type_id
+    )));
+    assert!(declarations.contains(&format!("  {}: Posts{};", content_type_id, content_type_id)));
+}
+
+async fn import_archive(app: &Router, cookie: &str, uri: &str, archive: &str) -> Response {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(COOKIE, cookie)
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from(archive.to_string()))
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+// コンテンツタイプを作成し、フィールドを追加してIDを返す
+async fn create_content_type_with_fields(
+    app: &Router,
+    base: &str,
+    api_key: &str,
+    name: &str,
+    fields: &[Value],
+) -> i64 {
+    let response = send_with_key(
+        app,
+        Method::POST,
+        &format!("{}/content_types", base),
+        api_key,
+        Some(json!({ "name": name })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let content_type_id = text_body(response)
+        .await
+        .rsplit(' ')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+    for field in fields {
+        let response = send_with_key(
+            app,
+            Method::POST,
+            &format!("{}/{}/fields", base, content_type_id),
+            api_key,
+            Some(field.clone()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+    content_type_id
+}
+
+#[tokio::test]
+async fn service_archives_import_into_another_service_with_new_ids() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let pgpool = state.pgpool.clone();
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (source_id, source_key) = create_service_as(&app, &cookie).await;
+    let (target_id, target_key) = create_service_as(&app, &cookie).await;
+    let source = format!("/api/services/{}", source_id);
+
+    let content_type_id = create_content_type_with_fields(
+        &app,
+        &source,
+        &source_key,
+        "posts",
+        &[json!({ "display_name": "title", "field_type": "Text", "required": true })],
+    )
+    .await;
+    let items = format!("{}/{}/content_items", source, content_type_id);
+    for title in ["first", "second", "trashed"] {
+        let response = send_with_key(
+            &app,
+            Method::POST,
+            &items,
+            &source_key,
+            Some(json!({ "data": { "title": title } })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+    let trashed: Uuid = sqlx::query_scalar(
+        "SELECT id FROM content_items WHERE content_type_id = $1 AND data->>'title' = 'trashed'",
+    )
+    .bind(content_type_id as i32)
+    .fetch_one(&pgpool)
+    .await
+    .unwrap();
+    let response = send_with_key(
+        &app,
+        Method::DELETE,
+        &format!("{}/content_items/{}", source, trashed),
+        &source_key,
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/api/service/{}/roles", source_id),
+        Some(&cookie),
+        Some(json!({ "name": "Reader", "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/api/service/{}/export", source_id),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+    let archive = text_body(response).await;
+    let records: Vec<Value> = archive
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records[0]["kind"], "header");
+    assert_eq!(records[0]["service_id"], source_id.as_str());
+    assert!(records.iter().any(|record| record["kind"] == "role"
+        && record["name"] == "Admin"
+        && record["admin"] == true));
+    // ゴミ箱のアイテムは書き出さない
+    assert_eq!(
+        records
+            .iter()
+            .filter(|record| record["kind"] == "content_item")
+            .count(),
+        2
+    );
+
+    let count_content_types = || {
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM content_types WHERE service_id = $1")
+            .bind(&target_id)
+            .fetch_one(&pgpool)
+    };
+    let import = format!("/api/service/{}/import", target_id);
+
+    // dry runは検証だけして何も書き込まない
+    let response =
+        import_archive(&app, &cookie, &format!("{}?dry_run=true", import), &archive).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json_body(response).await;
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["content_items"].as_object().unwrap().len(), 2);
+    assert_eq!(count_content_types().await.unwrap(), 0);
+
+    // 存在しないコンテンツタイプを参照するアーカイブは全体を取り込まない
+    let broken = format!(
+        "{}{}\n",
+        archive,
+        json!({
+            "kind": "content_item",
+            "id": Uuid::new_v4(),
+            "content_type_id": -1,
+            "data": {},
+            "created_at": "2023-06-01T00:00:00Z",
+            "updated_at": "2023-06-01T00:00:00Z"
+        })
+    );
+    let response = import_archive(&app, &cookie, &import, &broken).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(text_body(response)
+        .await
+        .contains("refers to unknown content type -1"));
+    assert_eq!(count_content_types().await.unwrap(), 0);
+
+    let response = import_archive(&app, &cookie, &import, &archive).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let report = json_body(response).await;
+    assert_eq!(report["source_service_id"], source_id.as_str());
+    assert_eq!(report["fields"], 1);
+    let new_content_type_id = report["content_types"][content_type_id.to_string()]
+        .as_i64()
+        .unwrap();
+    assert_ne!(new_content_type_id, content_type_id);
+    for (old_id, new_id) in report["content_items"].as_object().unwrap() {
+        assert_ne!(old_id, new_id.as_str().unwrap());
+    }
+    let reader_key = report["roles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|role| role["name"] == "Reader")
+        .and_then(|role| role["api_key"].as_str())
+        .unwrap()
+        .to_string();
+
+    // 取り込まれたロールの新しいキーで、振り直したIDのアイテムを読める
+    let response = send_with_key(
+        &app,
+        Method::GET,
+        &format!(
+            "/api/services/{}/{}/content_items",
+            target_id, new_content_type_id
+        ),
+        &reader_key,
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut titles: Vec<String> = json_body(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["data"]["title"].as_str().unwrap().to_string())
+        .collect();
+    titles.sort();
+    assert_eq!(titles, vec!["first", "second"]);
+
+    // Adminロールは取り込まず、取り込み先のAdminロールがキーも変わらずに一つだけ残る
+    assert_eq!(
+        report["roles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|role| role["name"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        vec!["Reader"]
+    );
+    let roles = format!("/api/service/{}/roles", target_id);
+    let response = send(&app, Method::GET, &roles, Some(&cookie), None).await;
+    let roles_after_import = json_body(response).await;
+    let mut names: Vec<&str> = roles_after_import
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|role| role["name"].as_str().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["Admin", "Reader"]);
+    let role_id = |name: &str| {
+        roles_after_import
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|role| role["name"] == name)
+            .unwrap()["id"]
+            .as_i64()
+            .unwrap()
+    };
+    let admin = format!("{}/{}", roles, role_id("Admin"));
+    let response = send(
+        &app,
+        Method::PATCH,
+        &admin,
+        Some(&cookie),
+        Some(json!({ "permissions": ["Get"] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send(&app, Method::DELETE, &admin, Some(&cookie), None).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send(
+        &app,
+        Method::DELETE,
+        &format!("{}/{}", roles, role_id("Reader")),
+        Some(&cookie),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_with_key(
+        &app,
+        Method::GET,
+        &format!(
+            "/api/services/{}/content_types/{}",
+            target_id, new_content_type_id
+        ),
+        &target_key,
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // 以前のアーカイブでもAdminという名前のロールは取り込まない
+    let legacy_admin = format!(
+        "{}\n{}\n",
+        records[0],
+        json!({ "kind": "role", "id": 1, "name": "Admin", "permissions": ["Get"] })
+    );
+    let response = import_archive(&app, &cookie, &import, &legacy_admin).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(json_body(response).await["roles"], json!([]));
+    let admin_roles: i64 =
+        sqlx::query_scalar("SELECT count(*) FROM roles WHERE service_id = $1 AND name = 'Admin'")
+            .bind(&target_id)
+            .fetch_one(&pgpool)
+            .await
+            .unwrap();
+    assert_eq!(admin_roles, 1);
+}
+
+async fn import_csv(app: &Router, uri: &str, api_key: &str, csv: &str) -> Response {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header("x-api-key", api_key)
+        .header(CONTENT_TYPE, "text/csv")
+        .body(Body::from(csv.to_string()))
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn csv_exports_import_back_as_updates() {
+    let Some(state) = test_state(None, &[]).await else {
+        return;
+    };
+    let app = create_router(state);
+    let username = format!("user-{}", Uuid::new_v4());
+    let cookie = register_and_login(&app, &username, "laptop").await;
+    let (service_id, api_key) = create_service_as(&app, &cookie).await;
+    let base = format!("/api/services/{}", service_id);
+    let content_type_id = create_content_type_with_fields(
+        &app,
+        &base,
+        &api_key,
+        "posts",
+        &[
+            json!({ "display_name": "title", "field_type": "Text", "required": true }),
+            json!({ "display_name": "views", "field_type": "Number", "required": false }),
+        ],
+    )
+    .await;
+    let items = format!("{}/{}/content_items", base, content_type_id);
+    for (title, views) in [("first", 1), ("second", 2)] {
+        let response = send_with_key(
+            &app,
+            Method::POST,
+            &items,
+            &api_key,
+            Some(json!({ "data": { "title": title, "views": views } })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response =
+        send_with_key(&app, Method::GET, &format!("{}.csv", items), &api_key, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let csv = text_body(response).await;
+    let mut lines: Vec<String> = csv.lines().map(str::to_string).collect();
+    assert_eq!(lines[0], "id,title,views");
+    assert_eq!(lines.len(), 3);
+
+    // 見出しを変えて編集し、IDのない行を足す
+    lines[0] = "id,Headline,views".to_string();
+    for line in lines.iter_mut().skip(1) {
+        *line = line.replace(",first,", ",first (edited),");
+    }
+    lines.push(",third,3".to_string());
+    let edited = lines.join("\n");
+
+    // 接頭辞のないクエリパラメータは列の対応として扱わない
+    let response = import_csv(
+        &app,
+        &format!("{}/import?Headline=title", items),
+        &api_key,
+        &edited,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json_body(response).await;
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["ignored_columns"], json!(["Headline"]));
+
+    let response = import_csv(
+        &app,
+        &format!("{}/import?map.Headline=title&dry_run=true", items),
+        &api_key,
+        &edited,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let report = json_body(response).await;
+    assert_eq!(report["imported"], 3);
+    assert_eq!(report["updated"], 2);
+    assert_eq!(report["errors"], json!([]));
+
+    let response = send_with_key(&app, Method::GET, &items, &api_key, None).await;
+    let mut rows: Vec<(String, i64)> = json_body(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| {
+            (
+                item["data"]["title"].as_str().unwrap().to_string(),
+                item["data"]["views"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    rows.sort();
+    assert_eq!(
+        rows,
+        vec![
+            ("first (edited)".to_string(), 1),
+            ("second".to_string(), 2),
+            ("third".to_string(), 3),
+        ]
+    );
+}
+
+// 管理用のCLIを実際のバイナリとして動かす
+fn cms(database_url: &str, args: &[&str]) -> String {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cms"))
+        .env("DATABASE_URL", database_url)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "cms {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn management_cli_builds_a_service_and_generates_types() {
+    let Some(state) = test_state(None, &[random_session_key()]).await else {
+        return;
+    };
+    let url = state.config.database.url.clone();
+
+    let created = cms(&url, &["service", "create", "CLI"]);
+    let service_id = created
+        .lines()
+        .find_map(|line| line.strip_prefix("service_id: "))
+        .unwrap()
+        .to_string();
+    assert!(created.lines().any(|line| line.starts_with("api_key: ")));
+
+    let content_type_id = cms(&url, &["content-type", "create", &service_id, "Article"]);
+    let content_type_id = content_type_id.trim();
+    cms(
+        &url,
+        &[
+            "field",
+            "create",
+            content_type_id,
+            "title",
+            "Text",
+            "--required",
+        ],
+    );
+    cms(
+        &url,
+        &["role", "create", &service_id, "Reader", "-p", "Get"],
+    );
+    let roles = cms(&url, &["role", "list", &service_id]);
+    assert!(roles.contains("Admin"));
+    assert!(roles.contains("Reader"));
+
+    let declarations = cms(&url, &["typegen", &service_id]);
+    assert!(declarations.contains(&format!(
+        "export interface Article{} {{\n  title: string;\n}}",
+        content_type_id
+    )));
+
+    let archive = std::env::temp_dir().join(format!("cms-cli-{}.json", Uuid::new_v4()));
+    cms(
+        &url,
+        &["export", &service_id, "-o", archive.to_str().unwrap()],
+    );
+    let report = cms(
+        &url,
+        &[
+            "import",
+            &service_id,
+            archive.to_str().unwrap(),
+            "--dry-run",
+        ],
+    );
+    assert!(!report.is_empty());
+    let _ = std::fs::remove_file(&archive);
+
+    cms(&url, &["service", "delete", &service_id, "--purge"]);
+}
+
+// 他のテストの_sqlx_migrationsに触らないよう、使い捨てのスキーマの中で確かめる
+#[tokio::test]
+async fn startup_refuses_a_schema_newer_than_the_binary() {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set; skipping");
+        return;
+    };
+    let schema = format!("schema_version_{}", Uuid::new_v4().simple());
+    let search_path = format!("SET search_path TO {}", schema);
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .after_connect(move |conn, _| {
+            let search_path = search_path.clone();
+            Box::pin(async move {
+                sqlx::Executor::execute(conn, search_path.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect(&url)
+        .await
+        .unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {}", schema))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // まだ何も適用されていないDBはそのまま起動できる
+    ensure_schema_not_ahead(&pool).await.unwrap();
+
+    sqlx::query("CREATE TABLE _sqlx_migrations (version BIGINT PRIMARY KEY)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let known: Vec<i64> = MIGRATOR.iter().map(|m| m.version).collect();
+    for version in &known {
+        sqlx::query("INSERT INTO _sqlx_migrations (version) VALUES ($1) ON CONFLICT DO NOTHING")
+            .bind(version)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    ensure_schema_not_ahead(&pool).await.unwrap();
+
+    sqlx::query("INSERT INTO _sqlx_migrations (version) VALUES (99991231000000)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let error = ensure_schema_not_ahead(&pool)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("migration 99991231000000 is applied"),
+        "{}",
+        error
+    );
+    assert!(
+        error.contains(&known.iter().max().unwrap().to_string()),
+        "{}",
+        error
+    );
+    let error = run_migrations(&pool).await.unwrap_err().to_string();
+    assert!(error.contains("ahead of this binary"), "{}", error);
+
+    sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema))
+        .execute(&pool)
+        .await
+        .unwrap();
+}
@@ -2,6 +2,9 @@
 import { Route, Routes } from 'react-router-dom';
 import Home from './routes/home.tsx';
 import Dashboard from './routes/dashboard.tsx';
+import VerifyEmail from './routes/verify-email.tsx';
+import ResetPassword from './routes/reset-password.tsx';
+import AcceptInvitation from './routes/accept-invitation.tsx';
 import Explore from './components/Explore.tsx';
 import Favorites from './components/Favorites.tsx';
 import Settings from './components/Settings.tsx';
@@ -28,6 +31,12 @@ const App = () => {
               <Route path="/favorites" element={<Favorites />} />
               <Route path="/settings" element={<Settings />} />
               <Route path="/trending" element={<Trending />} />
+              <Route path="/verify-email" element={<VerifyEmail />} />
+              <Route path="/reset-password" element={<ResetPassword />} />
+              <Route
+                path="/invitations/accept"
+                element={<AcceptInvitation />}
+              />
               <Route path="*" element={<h1>Not Found</h1>} />
             </Routes>
           </div>
@@ -0,0 +1,27 @@
+// 本番ではバックエンドが/でフロントエンドを配信するので、同じオリジンの/apiを呼ぶ
+const API_BASE = import.meta.env.VITE_API_URL ?? '/api';
+
+export type ApiResult = {
+  ok: boolean;
+  status: number;
+  message: string;
+};
+
+// セッションクッキーを送るので、別オリジンの開発サーバーからでもcredentialsを付ける
+export const postJson = async (
+  path: string,
+  body: unknown,
+  headers: Record<string, string> = {},
+): Promise<ApiResult> => {
+  const response = await fetch(`${API_BASE}${path}`, {
+    method: 'POST',
+    credentials: 'include',
+    headers: { 'Content-Type': 'application/json', ...headers },
+    body: JSON.stringify(body),
+  });
+  return {
+    ok: response.ok,
+    status: response.status,
+    message: await response.text(),
+  };
+};
@@ -1,11 +1,21 @@
 import React from 'react';
-import { Auth0Provider } from '@auth0/auth0-react';
+import { AppState, Auth0Provider } from '@auth0/auth0-react';
 import { HashRouter } from 'react-router-dom';
 import ReactDOM from 'react-dom/client';
 import App from './App.tsx';
 import './index.css';
 import { ChakraProvider } from '@chakra-ui/react';
 
+// HashRouterはhistory.replaceStateに気付かないので、ログイン後の画面へはpopstateで知らせて移る
+const onRedirectCallback = (appState?: AppState) => {
+  window.history.replaceState(
+    {},
+    document.title,
+    appState?.returnTo ?? window.location.pathname,
+  );
+  window.dispatchEvent(new PopStateEvent('popstate'));
+};
+
 ReactDOM.createRoot(document.getElementById('root')!).render(
   <React.StrictMode>
     <HashRouter>
@@ -16,6 +26,7 @@ ReactDOM.createRoot(document.getElementById('root')!).render(
           authorizationParams={{
             redirect_uri: window.location.origin,
           }}
+          onRedirectCallback={onRedirectCallback}
         >
           <App />
         </Auth0Provider>
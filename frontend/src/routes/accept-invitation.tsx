@@ -0,0 +1,74 @@
+import { useAuth0 } from '@auth0/auth0-react';
+import { useEffect, useRef, useState } from 'react';
+import { Link, useLocation, useSearchParams } from 'react-router-dom';
+import { postJson } from '../api.ts';
+
+// 招待メールのリンクから開く画面。招待を受けるにはログインが必要なので、
+// 401ならログインしてからこの画面に戻り、もう一度送る
+const AcceptInvitation = () => {
+  const [searchParams] = useSearchParams();
+  const token = searchParams.get('token');
+  const location = useLocation();
+  const {
+    isAuthenticated,
+    isLoading,
+    loginWithRedirect,
+    getAccessTokenSilently,
+  } = useAuth0();
+  const [message, setMessage] = useState('招待を確認しています...');
+  const [needsLogin, setNeedsLogin] = useState(false);
+  const [accepted, setAccepted] = useState(false);
+  const sent = useRef(false);
+
+  useEffect(() => {
+    if (isLoading || sent.current) {
+      return;
+    }
+    sent.current = true;
+    if (!token) {
+      setMessage('招待が無効か、有効期限が切れています');
+      return;
+    }
+    const accept = async () => {
+      // Auth0でログインしていればBearerトークンを、そうでなければセッションクッキーを使う
+      const headers: Record<string, string> = isAuthenticated
+        ? { Authorization: `Bearer ${await getAccessTokenSilently()}` }
+        : {};
+      const result = await postJson(
+        '/service/invitations/accept',
+        { token },
+        headers,
+      );
+      if (result.status === 401) {
+        setNeedsLogin(true);
+        setMessage('招待を受けるにはログインしてください');
+      } else if (result.ok) {
+        setAccepted(true);
+        setMessage('招待を受けました');
+      } else {
+        setMessage(result.message);
+      }
+    };
+    accept().catch((e) => {
+      console.error(e);
+      setMessage('招待を受けられませんでした');
+    });
+  }, [token, isLoading, isAuthenticated, getAccessTokenSilently]);
+
+  const login = () => {
+    loginWithRedirect({
+      appState: { returnTo: `/#${location.pathname}${location.search}` },
+    }).catch((e) => console.error(e));
+  };
+
+  return (
+    <div>
+      <h1>サービスへの招待</h1>
+      <p>{message}</p>
+      {needsLogin && <button onClick={login}>ログイン</button>}
+      {accepted && <Link to="/dashboard">ダッシュボードへ</Link>}
+    </div>
+  );
+};
+
+export default AcceptInvitation;
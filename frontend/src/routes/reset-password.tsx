@@ -0,0 +1,83 @@
+import { FormEvent, useState } from 'react';
+import { Link, useSearchParams } from 'react-router-dom';
+import { postJson } from '../api.ts';
+
+// パスワード再設定メールのリンクから開く画面。新しいパスワードとtokenをAPIに送る
+const ResetPassword = () => {
+  const [searchParams] = useSearchParams();
+  const token = searchParams.get('token');
+  const [password, setPassword] = useState('');
+  const [confirmation, setConfirmation] = useState('');
+  const [message, setMessage] = useState('');
+  const [done, setDone] = useState(false);
+  const [sending, setSending] = useState(false);
+
+  if (!token) {
+    return (
+      <div>
+        <h1>パスワードの再設定</h1>
+        <p>リンクが無効か、有効期限が切れています</p>
+      </div>
+    );
+  }
+
+  const onSubmit = (e: FormEvent) => {
+    e.preventDefault();
+    if (password !== confirmation) {
+      setMessage('確認用のパスワードが一致しません');
+      return;
+    }
+    setSending(true);
+    postJson('/auth/reset-password', { token, password })
+      .then((result) => {
+        setMessage(result.message);
+        setDone(result.ok);
+      })
+      .catch((e) => {
+        console.error(e);
+        setMessage('パスワードを再設定できませんでした');
+      })
+      .finally(() => setSending(false));
+  };
+
+  return (
+    <div>
+      <h1>パスワードの再設定</h1>
+      {done ? (
+        <>
+          <p>{message}</p>
+          <Link to="/">トップへ戻る</Link>
+        </>
+      ) : (
+        <form onSubmit={onSubmit}>
+          <label>
+            新しいパスワード
+            <input
+              type="password"
+              autoComplete="new-password"
+              value={password}
+              onChange={(e) => setPassword(e.target.value)}
+              required
+            />
+          </label>
+          <label>
+            新しいパスワード(確認)
+            <input
+              type="password"
+              autoComplete="new-password"
+              value={confirmation}
+              onChange={(e) => setConfirmation(e.target.value)}
+              required
+            />
+          </label>
+          <button type="submit" disabled={sending}>
+            再設定する
+          </button>
+          {message && <p>{message}</p>}
+        </form>
+      )}
+    </div>
+  );
+};
+
+export default ResetPassword;
@@ -0,0 +1,39 @@
+import { useEffect, useRef, useState } from 'react';
+import { Link, useSearchParams } from 'react-router-dom';
+import { postJson } from '../api.ts';
+
+// 確認メールのリンクから開く画面。tokenをAPIに送ってメールアドレスを確認する
+const VerifyEmail = () => {
+  const [searchParams] = useSearchParams();
+  const token = searchParams.get('token');
+  const [message, setMessage] = useState('確認しています...');
+  const sent = useRef(false);
+
+  useEffect(() => {
+    // StrictModeでは二回呼ばれるので、送信は一度だけにする
+    if (sent.current) {
+      return;
+    }
+    sent.current = true;
+    if (!token) {
+      setMessage('リンクが無効か、有効期限が切れています');
+      return;
+    }
+    postJson('/auth/verify-email', { token })
+      .then((result) => setMessage(result.message))
+      .catch((e) => {
+        console.error(e);
+        setMessage('メールアドレスを確認できませんでした');
+      });
+  }, [token]);
+
+  return (
+    <div>
+      <h1>メールアドレスの確認</h1>
+      <p>{message}</p>
+      <Link to="/">トップへ戻る</Link>
+    </div>
+  );
+};
+
+export default VerifyEmail;